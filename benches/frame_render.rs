@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use runes::cartridge::{Cartridge, Mirroring, Region};
+use runes::ppu::PPU;
+
+// Deterministic noise so every tile has a different pattern, attribute and palette
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32).map(|i| (i.wrapping_add(seed).wrapping_mul(2654435761) >> 13) as u8).collect()
+}
+
+fn write_vram(ppu: &mut PPU, addr: u16, data: &[u8]) {
+    ppu.write_to_address_register((addr >> 8) as u8);
+    ppu.write_to_address_register(addr as u8);
+    for &byte in data {
+        ppu.write_data(byte);
+    }
+}
+
+// Scrolled background and sprites enabled, with an empty OAM so the background path dominates
+fn render_ppu() -> PPU {
+    let cartridge = Cartridge::from_parts(vec![0; 0x8000], noise(0x2000, 1), 0, Mirroring::Horizontal);
+    let mut ppu = PPU::new(cartridge.create_mapper(), Mirroring::Horizontal, Region::Ntsc);
+    ppu.warm_up_enabled = false;
+    ppu.reset();
+
+    write_vram(&mut ppu, 0x2000, &noise(0x800, 2));
+    write_vram(&mut ppu, 0x3F00, &noise(32, 3).iter().map(|value| value & 0x3F).collect::<Vec<_>>());
+    ppu.write_to_control_register(0x11);
+    ppu.write_to_scroll_register(37);
+    ppu.write_to_scroll_register(91);
+    ppu.write_to_address_register(0x00);
+    ppu.write_to_address_register(0x00);
+    ppu.write_to_mask_register(0x1E);
+    ppu
+}
+
+fn frame_render(c: &mut Criterion) {
+    let mut ppu = render_ppu();
+    c.bench_function("frame_render", |b| {
+        b.iter(|| {
+            while !ppu.poll_frame_complete() {
+                ppu.clock();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, frame_render);
+criterion_main!(benches);
@@ -0,0 +1,398 @@
+use crate::cartridge::Region;
+use crate::savestate::{StateReader, StateWriter};
+
+// Frame sequencer lengths in CPU cycles, the 4-step IRQ is raised during the last 3 cycles
+const NTSC_FOUR_STEP_CYCLES: u32 = 29830;
+const NTSC_FIVE_STEP_CYCLES: u32 = 37282;
+const PAL_FOUR_STEP_CYCLES: u32 = 33254;
+const PAL_FIVE_STEP_CYCLES: u32 = 41566;
+// Second quarter frame step, the first of the two half frame clocks in either mode
+const NTSC_HALF_FRAME_CYCLE: u32 = 14913;
+const PAL_HALF_FRAME_CYCLE: u32 = 16627;
+
+// Length counter loads, indexed by bits 3-7 of $4003/$4007/$400B/$400F
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28,
+    32, 30,
+];
+
+// DMC output clock periods in CPU cycles, indexed by the low 4 bits of $4010
+const NTSC_DMC_RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_DMC_RATES: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+// Only the frame counter, the length counters and the DMC's sample reader are emulated so far,
+// enough for games polling $4015 and for DMC fetches stealing CPU cycles. No sound is produced.
+pub struct APU {
+    pub region: Region,
+
+    // $4017
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    pub frame_irq: bool,
+
+    frame_cycle: u32, // CPU cycles since the sequencer was last reset
+    reset_delay: u8,  // CPU cycles until a $4017 write resets the sequencer
+    odd_cycle: bool,
+
+    // $4015
+    channel_enable: u8,
+
+    // Pulse 1, pulse 2, triangle and noise. The DMC has no length counter, it reports its
+    // remaining sample bytes in $4015 instead.
+    length_counters: [u8; 4],
+    length_halt: [bool; 4],
+
+    // DMC, $4010-$4013
+    dmc_irq_enabled: bool,
+    dmc_loop: bool,
+    dmc_rate: u8,
+    dmc_sample_address: u16,
+    dmc_sample_length: u16,
+    pub dmc_irq: bool,
+
+    // DMC memory reader and output unit
+    dmc_address: u16,
+    dmc_bytes_remaining: u16,
+    dmc_buffer_full: bool,
+    dmc_buffer: u8,
+    dmc_bits_remaining: u8,
+    dmc_timer: u16,
+}
+
+impl APU {
+    pub fn new(region: Region) -> APU {
+        APU {
+            region,
+
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+
+            frame_cycle: 0,
+            reset_delay: 0,
+            odd_cycle: false,
+
+            channel_enable: 0,
+
+            length_counters: [0; 4],
+            length_halt: [false; 4],
+
+            dmc_irq_enabled: false,
+            dmc_loop: false,
+            dmc_rate: 0,
+            dmc_sample_address: 0xC000,
+            dmc_sample_length: 1,
+            dmc_irq: false,
+
+            dmc_address: 0xC000,
+            dmc_bytes_remaining: 0,
+            dmc_buffer_full: false,
+            dmc_buffer: 0,
+            dmc_bits_remaining: 0,
+            dmc_timer: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.five_step_mode = false;
+        self.irq_inhibit = false;
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+        self.reset_delay = 0;
+        self.odd_cycle = false;
+        self.channel_enable = 0;
+        self.length_counters = [0; 4];
+        self.dmc_irq_enabled = false;
+        self.dmc_irq = false;
+        self.dmc_bytes_remaining = 0;
+        self.dmc_buffer_full = false;
+        self.dmc_bits_remaining = 0;
+        self.dmc_timer = 0;
+    }
+
+    fn sequence_length(&self) -> u32 {
+        match (self.region, self.five_step_mode) {
+            (Region::Pal, false) => PAL_FOUR_STEP_CYCLES,
+            (Region::Pal, true) => PAL_FIVE_STEP_CYCLES,
+            (_, false) => NTSC_FOUR_STEP_CYCLES,
+            (_, true) => NTSC_FIVE_STEP_CYCLES,
+        }
+    }
+
+    fn half_frame_cycle(&self) -> u32 {
+        match self.region {
+            Region::Pal => PAL_HALF_FRAME_CYCLE,
+            _ => NTSC_HALF_FRAME_CYCLE,
+        }
+    }
+
+    fn dmc_period(&self) -> u16 {
+        match self.region {
+            Region::Pal => PAL_DMC_RATES[self.dmc_rate as usize],
+            _ => NTSC_DMC_RATES[self.dmc_rate as usize],
+        }
+    }
+
+    // Each output clock shifts out one bit, the sample buffer is emptied into the shift register
+    // every 8 bits, which is what makes the memory reader fetch the next byte
+    fn clock_dmc(&mut self) {
+        if self.dmc_timer > 0 {
+            self.dmc_timer -= 1;
+            return;
+        }
+        self.dmc_timer = self.dmc_period() - 1;
+
+        if self.dmc_bits_remaining > 0 {
+            self.dmc_bits_remaining -= 1;
+        }
+        if self.dmc_bits_remaining == 0 {
+            self.dmc_bits_remaining = 8;
+            self.dmc_buffer_full = false;
+        }
+    }
+
+    // Address of the sample byte the DMC wants fetched, once its buffer is empty and the sample
+    // isn't finished. The bus does the read, which stalls the CPU, and hands it to fill_dmc_buffer.
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        if !self.dmc_buffer_full && self.dmc_bytes_remaining > 0 {
+            Some(self.dmc_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill_dmc_buffer(&mut self, value: u8) {
+        self.dmc_buffer = value;
+        self.dmc_buffer_full = true;
+        // The sample address wraps from $FFFF back to $8000
+        self.dmc_address = if self.dmc_address == 0xFFFF { 0x8000 } else { self.dmc_address + 1 };
+        self.dmc_bytes_remaining -= 1;
+
+        if self.dmc_bytes_remaining == 0 {
+            if self.dmc_loop {
+                self.restart_dmc_sample();
+            } else if self.dmc_irq_enabled {
+                self.dmc_irq = true;
+            }
+        }
+    }
+
+    fn restart_dmc_sample(&mut self) {
+        self.dmc_address = self.dmc_sample_address;
+        self.dmc_bytes_remaining = self.dmc_sample_length;
+    }
+
+    fn clock_length_counters(&mut self) {
+        for (counter, halt) in self.length_counters.iter_mut().zip(self.length_halt) {
+            if *counter > 0 && !halt {
+                *counter -= 1;
+            }
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.frame_cycle = 0;
+                // Switching to 5-step mode clocks the half frame units right away
+                if self.five_step_mode {
+                    self.clock_length_counters();
+                }
+            }
+        }
+
+        self.frame_cycle += 1;
+
+        let length = self.sequence_length();
+        if self.frame_cycle == self.half_frame_cycle() || self.frame_cycle == length - 1 {
+            self.clock_length_counters();
+        }
+        if !self.five_step_mode && !self.irq_inhibit && self.frame_cycle >= length - 2 {
+            self.frame_irq = true;
+        }
+
+        if self.frame_cycle >= length {
+            self.frame_cycle = 0;
+        }
+
+        self.clock_dmc();
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc_irq
+    }
+
+    // $4015 read: bits 0-3 are set while the channel's length counter is non-zero, bit 4 while the
+    // DMC has sample bytes left, bit 6 is the frame IRQ, which the read acknowledges, and bit 7 the
+    // DMC IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    // $4015 without acknowledging the frame IRQ, for the debugger
+    pub fn peek_status(&self) -> u8 {
+        let mut status = if self.frame_irq { 0x40 } else { 0x00 };
+        if self.dmc_irq {
+            status |= 0x80;
+        }
+        if self.dmc_bytes_remaining > 0 {
+            status |= 0x10;
+        }
+        for (channel, &counter) in self.length_counters.iter().enumerate() {
+            if counter > 0 {
+                status |= 1 << channel;
+            }
+        }
+        status
+    }
+
+    // $4015 write, a disabled channel has its length counter cleared and can't be reloaded.
+    // Enabling the DMC restarts its sample only once the previous one has finished.
+    pub fn write_channel_enable(&mut self, data: u8) {
+        self.channel_enable = data & 0x1F;
+        for (channel, counter) in self.length_counters.iter_mut().enumerate() {
+            if data & (1 << channel) == 0 {
+                *counter = 0;
+            }
+        }
+
+        self.dmc_irq = false;
+        if data & 0x10 == 0 {
+            self.dmc_bytes_remaining = 0;
+        } else if self.dmc_bytes_remaining == 0 {
+            self.restart_dmc_sample();
+        }
+    }
+
+    // $4000-$4013, only the length counter halt and load bits and the DMC's sample settings are used so far
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4010 => {
+                self.dmc_irq_enabled = data & 0x80 != 0;
+                if !self.dmc_irq_enabled {
+                    self.dmc_irq = false;
+                }
+                self.dmc_loop = data & 0x40 != 0;
+                self.dmc_rate = data & 0x0F;
+                return;
+            }
+            0x4012 => {
+                self.dmc_sample_address = 0xC000 + data as u16 * 64;
+                return;
+            }
+            0x4013 => {
+                self.dmc_sample_length = data as u16 * 16 + 1;
+                return;
+            }
+            _ => {}
+        }
+
+        let channel = ((addr - 0x4000) / 4) as usize;
+        if channel >= 4 {
+            return;
+        }
+        match addr & 0x03 {
+            // The triangle's halt is its linear counter control bit
+            0 if channel == 2 => self.length_halt[channel] = data & 0x80 != 0,
+            0 => self.length_halt[channel] = data & 0x20 != 0,
+            3 if self.channel_enable & (1 << channel) != 0 => {
+                self.length_counters[channel] = LENGTH_TABLE[(data >> 3) as usize];
+            }
+            _ => {}
+        }
+    }
+
+    // $4017 write
+    pub fn write_frame_counter(&mut self, data: u8) {
+        self.five_step_mode = data & 0x80 != 0;
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.frame_irq = false;
+        }
+
+        // The sequencer is reset 3 CPU cycles after the write, or 4 if it lands between APU cycles
+        self.reset_delay = if self.odd_cycle { 4 } else { 3 };
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.five_step_mode);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.frame_irq);
+        writer.write_u32(self.frame_cycle);
+        writer.write_u8(self.reset_delay);
+        writer.write_bool(self.odd_cycle);
+        writer.write_u8(self.channel_enable);
+        writer.write_bytes(&self.length_counters);
+        for halt in self.length_halt {
+            writer.write_bool(halt);
+        }
+        writer.write_bool(self.dmc_irq_enabled);
+        writer.write_bool(self.dmc_loop);
+        writer.write_u8(self.dmc_rate);
+        writer.write_u16(self.dmc_sample_address);
+        writer.write_u16(self.dmc_sample_length);
+        writer.write_bool(self.dmc_irq);
+        writer.write_u16(self.dmc_address);
+        writer.write_u16(self.dmc_bytes_remaining);
+        writer.write_bool(self.dmc_buffer_full);
+        writer.write_u8(self.dmc_buffer);
+        writer.write_u8(self.dmc_bits_remaining);
+        writer.write_u16(self.dmc_timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.five_step_mode = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.frame_irq = reader.read_bool()?;
+        self.frame_cycle = reader.read_u32()?;
+        self.reset_delay = reader.read_u8()?;
+        self.odd_cycle = reader.read_bool()?;
+        self.channel_enable = reader.read_u8()?;
+        reader.read_into(&mut self.length_counters)?;
+        for halt in &mut self.length_halt {
+            *halt = reader.read_bool()?;
+        }
+        self.dmc_irq_enabled = reader.read_bool()?;
+        self.dmc_loop = reader.read_bool()?;
+        self.dmc_rate = reader.read_u8()? & 0x0F;
+        self.dmc_sample_address = reader.read_u16()?;
+        self.dmc_sample_length = reader.read_u16()?;
+        self.dmc_irq = reader.read_bool()?;
+        self.dmc_address = reader.read_u16()?;
+        self.dmc_bytes_remaining = reader.read_u16()?;
+        self.dmc_buffer_full = reader.read_bool()?;
+        self.dmc_buffer = reader.read_u8()?;
+        self.dmc_bits_remaining = reader.read_u8()?;
+        self.dmc_timer = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CPU cycles from a $4017 write until the frame IRQ is raised, None if it never is
+    fn cycles_until_frame_irq(frame_counter: u8) -> Option<u32> {
+        let mut apu = APU::new(Region::Ntsc);
+        apu.write_frame_counter(frame_counter);
+        (1..=2 * NTSC_FIVE_STEP_CYCLES).find(|_| {
+            apu.clock();
+            apu.irq_pending()
+        })
+    }
+
+    #[test]
+    fn apu_four_step_frame_irq_timing() {
+        // The sequencer restarts on the 3rd cycle after the write and raises the IRQ on its 29828th
+        assert_eq!(cycles_until_frame_irq(0x00), Some(29830));
+        assert_eq!(cycles_until_frame_irq(0x40), None);
+        assert_eq!(cycles_until_frame_irq(0x80), None);
+    }
+}
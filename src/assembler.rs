@@ -0,0 +1,169 @@
+// Tiny 6502 assembler for building test programs.
+// One instruction per line (or separated by '/'), numeric operands only:
+//   lda #$05 / sta $00 / ldx 16 / bne $0600 / jmp ($1234) / brk
+// Operands are $hex or decimal, ';' starts a comment. Labels aren't supported.
+
+use crate::bus::Bus;
+use crate::cpu::AddressingMode;
+use crate::opcodes::references::INSTRUCTION_LOOKUP;
+use crate::opcodes::Opcode;
+
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    for line in source.split(['\n', '/']) {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let address = origin.wrapping_add(bytes.len() as u16);
+        let instruction = assemble_instruction(line, address)
+            .map_err(|e| format!("{} in \"{}\"", e, line))?;
+        bytes.extend(instruction);
+    }
+
+    Ok(bytes)
+}
+
+// Assembles the source and writes it to memory starting at origin
+pub fn inject(bus: &mut Bus, source: &str, origin: u16) -> Result<(), String> {
+    let bytes = assemble(source, origin)?;
+    for (offset, byte) in bytes.iter().enumerate() {
+        bus.mem_write(origin.wrapping_add(offset as u16), *byte);
+    }
+    Ok(())
+}
+
+fn assemble_instruction(line: &str, address: u16) -> Result<Vec<u8>, String> {
+    let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+        None => (line, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+
+    if !INSTRUCTION_LOOKUP.iter().any(|i| is_mnemonic(&i.operate, &mnemonic)) {
+        return Err(format!("Unknown mnemonic {}", mnemonic));
+    }
+
+    // Implied and accumulator
+    if operand.is_empty() || operand == "A" {
+        return encode(&mnemonic, AddressingMode::IMP, &[]);
+    }
+
+    if let Some(value) = operand.strip_prefix('#') {
+        let value = parse_number(value)?;
+        return encode(&mnemonic, AddressingMode::IMM, &[byte_operand(value)?]);
+    }
+
+    if let Some(inner) = operand.strip_prefix('(') {
+        if let Some(value) = inner.strip_suffix(",X)") {
+            let value = byte_operand(parse_number(value)?)?;
+            return encode(&mnemonic, AddressingMode::IZX, &[value]);
+        }
+        if let Some(value) = inner.strip_suffix("),Y") {
+            let value = byte_operand(parse_number(value)?)?;
+            return encode(&mnemonic, AddressingMode::IZY, &[value]);
+        }
+        if let Some(value) = inner.strip_suffix(')') {
+            let value = parse_number(value)?;
+            return encode(&mnemonic, AddressingMode::IND, &value.to_le_bytes());
+        }
+        return Err(format!("Bad indirect operand {}", operand));
+    }
+
+    if has_mode(&mnemonic, &AddressingMode::REL) {
+        let target = parse_number(&operand)?;
+        let offset = target as i32 - (address as i32 + 2);
+        if !(-128..=127).contains(&offset) {
+            return Err(format!("Branch target ${:04X} out of range", target));
+        }
+        return encode(&mnemonic, AddressingMode::REL, &[offset as i8 as u8]);
+    }
+
+    let (value, zero_page_mode, absolute_mode) = if let Some(value) = operand.strip_suffix(",X") {
+        (parse_number(value)?, AddressingMode::ZPX, AddressingMode::ABX)
+    } else if let Some(value) = operand.strip_suffix(",Y") {
+        (parse_number(value)?, AddressingMode::ZPY, AddressingMode::ABY)
+    } else {
+        (parse_number(&operand)?, AddressingMode::ZP0, AddressingMode::ABS)
+    };
+
+    if value <= 0xFF && has_mode(&mnemonic, &zero_page_mode) {
+        encode(&mnemonic, zero_page_mode, &[value as u8])
+    } else {
+        encode(&mnemonic, absolute_mode, &value.to_le_bytes())
+    }
+}
+
+fn is_mnemonic(operate: &Opcode, mnemonic: &str) -> bool {
+    !matches!(operate, Opcode::XXX) && format!("{:?}", operate) == mnemonic
+}
+
+fn has_mode(mnemonic: &str, mode: &AddressingMode) -> bool {
+    INSTRUCTION_LOOKUP
+        .iter()
+        .any(|i| is_mnemonic(&i.operate, mnemonic) && i.addrmode == *mode)
+}
+
+fn encode(mnemonic: &str, mode: AddressingMode, operand: &[u8]) -> Result<Vec<u8>, String> {
+    let opcode = INSTRUCTION_LOOKUP
+        .iter()
+        .position(|i| is_mnemonic(&i.operate, mnemonic) && i.addrmode == mode)
+        .ok_or(format!("{} doesn't support {:?} addressing", mnemonic, mode))?;
+
+    let mut bytes = vec![opcode as u8];
+    bytes.extend_from_slice(operand);
+    Ok(bytes)
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("Bad number {}", text))
+}
+
+fn byte_operand(value: u16) -> Result<u8, String> {
+    u8::try_from(value).map_err(|_| format!("${:04X} doesn't fit in a byte", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, Mirroring};
+    use crate::cpu::CPU;
+
+    #[test]
+    fn assembler_round_trips_through_disassembler() {
+        let source = [
+            "LDA #$05",
+            "STA $10",
+            "STA $0200,X",
+            "LDX $20,Y",
+            "LDA ($40),Y",
+            "LDA ($40,X)",
+            "ASL",
+            "JMP ($1234)",
+            "BNE $0300",
+        ];
+        let mut cpu = CPU::new(Cartridge::from_parts(vec![0; 0x8000], Vec::new(), 0, Mirroring::Horizontal));
+        inject(&mut cpu.bus, &source.join("\n"), 0x0300).unwrap();
+
+        let mut addr = 0x0300;
+        for expected in source {
+            let (text, length) = cpu.disassemble(addr);
+            assert_eq!(text[10..].trim_end(), expected);
+            addr += length;
+        }
+    }
+
+    #[test]
+    fn assembler_rejects_bad_operands() {
+        assert!(assemble("lda #$100", 0).is_err());
+        assert!(assemble("bne $0200", 0).is_err());
+        assert!(assemble("foo", 0).is_err());
+    }
+}
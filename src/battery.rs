@@ -0,0 +1,158 @@
+use crate::cpu::CPU;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FLUSH_INTERVAL_SECONDS: f64 = 30.0;
+
+// Battery backed PRG RAM of the running game, mirrored to Cartridge::save_file_path().
+// The file is rewritten whenever the RAM changed, at most once per flush interval of emulated
+// time, so a crash loses no more than that.
+pub struct BatterySave {
+    pub path: PathBuf,
+    pub flush_interval: f64, // Seconds of emulated time
+    written: Vec<u8>,        // RAM as it is on disk
+    last_flush_frame: u64,
+}
+
+impl BatterySave {
+    // None for cartridges without a battery. An existing save is loaded into the mapper.
+    pub fn open(cpu: &mut CPU, flush_interval: f64) -> Result<Option<BatterySave>, String> {
+        if !cpu.bus.cartridge.has_battery() {
+            return Ok(None);
+        }
+
+        let path = cpu.bus.cartridge.save_file_path();
+        let mut mapper = cpu.bus.mapper.borrow_mut();
+        let Some(ram) = mapper.battery_ram().filter(|ram| !ram.is_empty()) else {
+            return Ok(None);
+        };
+
+        let written = match fs::read(&path) {
+            Ok(data) => {
+                mapper.load_battery_ram(&data);
+                mapper.battery_ram().unwrap_or_default().to_vec()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ram.to_vec(),
+            Err(e) => return Err(format!("Couldn't read {}: {}", path.display(), e)),
+        };
+
+        Ok(Some(BatterySave {
+            path,
+            flush_interval,
+            written,
+            last_flush_frame: cpu.bus.ppu.frame_count,
+        }))
+    }
+
+    // Writes the RAM if it changed since the last write, returns whether it did
+    pub fn flush(&mut self, cpu: &CPU) -> Result<bool, String> {
+        self.last_flush_frame = cpu.bus.ppu.frame_count;
+
+        let mapper = cpu.bus.mapper.borrow();
+        let Some(ram) = mapper.battery_ram() else {
+            return Ok(false);
+        };
+        if ram == self.written.as_slice() {
+            return Ok(false);
+        }
+
+        write_atomically(&self.path, ram)?;
+        self.written = ram.to_vec();
+        Ok(true)
+    }
+
+    // Called every frame, flushes once the interval has passed
+    pub fn update(&mut self, cpu: &CPU) -> Result<bool, String> {
+        // Loading a state or resetting can move the frame count backwards
+        let frame_count = cpu.bus.ppu.frame_count;
+        self.last_flush_frame = self.last_flush_frame.min(frame_count);
+
+        let interval_frames = (self.flush_interval * cpu.bus.region.frame_rate()) as u64;
+        if frame_count - self.last_flush_frame < interval_frames {
+            return Ok(false);
+        }
+        self.flush(cpu)
+    }
+}
+
+// Writes next to the destination first and renames over it, so a crash mid-write never leaves
+// a truncated save behind
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    }
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = fs::File::create(&temp_path).map_err(|e| e.to_string())?;
+    file.write_all(data).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, path).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    // Scratch directory holding a battery backed NROM image, saves go to its saves/ subdirectory
+    fn battery_rom(name: &str) -> (PathBuf, Cartridge) {
+        let directory = std::env::temp_dir().join(format!("runes-battery-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let rom_path = directory.join("game.nes");
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x02, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 0x4000 + 0x2000, 0);
+        fs::write(&rom_path, data).unwrap();
+
+        let mut cartridge = Cartridge::new(&rom_path.to_string_lossy()).unwrap();
+        cartridge.save_directory = directory.join("saves");
+        (directory, cartridge)
+    }
+
+    #[test]
+    fn battery_write_atomically_round_trip() {
+        let directory = std::env::temp_dir().join(format!("runes-battery-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let path = directory.join("saves").join("game.sav");
+
+        write_atomically(&path, &[1, 2, 3]).unwrap();
+        write_atomically(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![4, 5]);
+
+        // Nothing but the save itself, the temp file was renamed over it
+        let files: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, ["game.sav"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn battery_hash_named_save_wins_over_legacy() {
+        let (directory, cartridge) = battery_rom("hashed");
+        let hashed = cartridge.save_directory.join(format!("{}.sav", cartridge.sha1_hex()));
+        write_atomically(&hashed, &[0x11; 0x2000]).unwrap();
+        fs::write(directory.join("game.sav"), [0x22; 0x2000]).unwrap();
+
+        let mut cpu = CPU::new(cartridge);
+        let save = BatterySave::open(&mut cpu, DEFAULT_FLUSH_INTERVAL_SECONDS).unwrap().unwrap();
+        assert_eq!(save.path, hashed);
+        assert_eq!(cpu.bus.mem_read(0x6000), 0x11);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn battery_legacy_save_without_hash_named_save() {
+        let (directory, cartridge) = battery_rom("legacy");
+        fs::write(directory.join("game.sav"), [0x22; 0x2000]).unwrap();
+
+        let mut cpu = CPU::new(cartridge);
+        let save = BatterySave::open(&mut cpu, DEFAULT_FLUSH_INTERVAL_SECONDS).unwrap().unwrap();
+        assert_eq!(save.path, directory.join("game.sav"));
+        assert_eq!(cpu.bus.mem_read(0x6000), 0x22);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
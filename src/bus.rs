@@ -1,40 +1,117 @@
-use crate::cartridge::Cartridge;
+use crate::apu::APU;
+use crate::cartridge::{Cartridge, Mirroring, Region};
+use crate::controller::{ControllerDevice, StandardController};
+use crate::mapper::{Mapper, MapperRef};
 use crate::ppu::PPU;
-
+use crate::savestate::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 
 // Memory addresses
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
-const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+// OAM DMA halts the CPU for one cycle, then does 256 read/write pairs.
+// Starting on an odd CPU cycle costs one more cycle to align the reads.
+const OAM_DMA_CYCLES: u16 = 513;
+// A DMC sample fetch halts the CPU for one cycle, then has a dummy cycle and the read, plus one
+// alignment cycle when the read would land on a put cycle. During an OAM DMA the halt and dummy
+// cycles overlap the OAM transfer, only the read and one realignment cycle for the OAM DMA are added.
+const DMC_DMA_CYCLES: u16 = 3;
+const DMC_DMA_DURING_OAM_CYCLES: u16 = 2;
+
+// How many unmapped accesses are kept for the debug view
+pub const UNMAPPED_LOG_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct UnmappedAccess {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+    pub cycle: u64, // CPU cycles since power on
+}
+
 pub struct Bus {
     pub cpu_vram: [u8; 2048],
     pub cartridge: Cartridge,
+    pub mapper: MapperRef,
     pub ppu: PPU,
-    controller: [u8; 2],
-    controller_state: [u8; 2],
-    controller_strobe: bool,
+    pub apu: APU,
+    pub region: Region,
+    controller: [u8; 2], // Buttons held on the host, turbo is applied on the way to the device
+    pub ports: [Box<dyn ControllerDevice>; 2],
+    // Host controller 1 feeds $4017 and controller 2 feeds $4016, for games that read the pads backwards
+    pub swap_controllers: bool,
+
+    // Turbo buttons toggle at turbo_rate presses per second of emulated time
+    turbo: [u8; 2],
+    pub turbo_rate: f64,
+
+    // Famicom: the second controller's microphone shows up as bit 2 of $4016
+    pub famicom_mode: bool,
+    pub microphone: bool,
+
+    pub cpu_cycle_count: u64,
+    dma_stall: u16,
+    pub unmapped_accesses: VecDeque<UnmappedAccess>,
+
+    // Cheats, RAM addresses (mirrors folded into $0000-$07FF) pinned to a value
+    pub frozen: HashMap<u16, u8>,
 }
 
 impl Bus {
     pub fn new(cartridge: Cartridge) -> Bus {
-        Bus {
+        let mapper = cartridge.create_mapper();
+        Bus::from_parts(cartridge, mapper)
+    }
+
+    // Bus around an arbitrary mapper and raw PRG/CHR, so mapper code can run without a ROM file.
+    // The mapper gets its own copy of the data, prg/chr only fill in the cartridge for the debug views.
+    pub fn with_mapper(mapper: impl Mapper + 'static, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Bus {
+        let cartridge = Cartridge::from_parts(prg_rom, chr_rom, 0, Mirroring::Horizontal);
+        Bus::from_parts(cartridge, Rc::new(RefCell::new(mapper)))
+    }
+
+    fn from_parts(cartridge: Cartridge, mapper: MapperRef) -> Bus {
+        let mut bus = Bus {
             cpu_vram: [0; 2048],
-            ppu: PPU::new(
-                cartridge.chr_rom.clone(),
-                cartridge.mirror.clone(),
-                cartridge.chr_is_ram,
-            ),
+            ppu: PPU::new(mapper.clone(), cartridge.mirror, cartridge.region),
+            mapper,
+            apu: APU::new(cartridge.region),
+            region: cartridge.region,
             cartridge,
             controller: [0; 2],
-            controller_state: [0; 2],
-            controller_strobe: false,
-        }
+            ports: [Box::new(StandardController::default()), Box::new(StandardController::default())],
+            swap_controllers: false,
+
+            turbo: [0; 2],
+            turbo_rate: 15.0,
+
+            famicom_mode: false,
+            microphone: false,
+
+            cpu_cycle_count: 0,
+            dma_stall: 0,
+            unmapped_accesses: VecDeque::with_capacity(UNMAPPED_LOG_CAPACITY),
+
+            frozen: HashMap::new(),
+        };
+
+        // Boards that control mirroring start in their own power-on mode, not the header's
+        bus.sync_mirroring();
+        bus
     }
 }
 
 impl Bus {
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.region = region;
+    }
+
     pub fn get_controller_state(&self, index: usize) -> u8 {
         self.controller.get(index).copied().unwrap_or(0)
     }
@@ -42,12 +119,47 @@ impl Bus {
     pub fn set_controller_state(&mut self, index: usize, state: u8) {
         if let Some(slot) = self.controller.get_mut(index) {
             *slot = state;
-            if self.controller_strobe {
-                self.controller_state[index] = state;
+            for port in 0..2 {
+                let input = self.controller_input(port);
+                self.ports[port].set_state(input);
             }
         }
     }
 
+    // Plugs a device into port 0 or 1
+    pub fn set_controller_device(&mut self, index: usize, device: Box<dyn ControllerDevice>) {
+        if let Some(port) = self.ports.get_mut(index) {
+            *port = device;
+        }
+    }
+
+    // Buttons held through their turbo key
+    pub fn set_turbo_state(&mut self, index: usize, buttons: u8) {
+        if let Some(slot) = self.turbo.get_mut(index) {
+            *slot = buttons;
+        }
+    }
+
+    // Turbo is pressed during the first half of every period
+    fn turbo_pressed(&self) -> bool {
+        if self.turbo_rate <= 0.0 {
+            return true;
+        }
+
+        let half_period = self.region.cpu_clock_hz() / (self.turbo_rate * 2.0);
+        ((self.cpu_cycle_count as f64 / half_period) as u64).is_multiple_of(2)
+    }
+
+    // Buttons as seen by the port's shift register when it gets latched
+    fn controller_input(&self, port: usize) -> u8 {
+        let index = if self.swap_controllers { port ^ 1 } else { port };
+        if self.turbo_pressed() {
+            self.controller[index] | self.turbo[index]
+        } else {
+            self.controller[index]
+        }
+    }
+
     pub fn mem_read(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
@@ -56,7 +168,8 @@ impl Bus {
             },
 
             // PPU
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => 0,
+            // Write-only registers read back the open bus latch
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.ppu.read_io_latch(),
             0x2002 => self.ppu.read_status_register(),
 
             0x2004 => self.ppu.read_oam_data(),
@@ -70,21 +183,32 @@ impl Bus {
 
             0x4014 => 0,
 
+            0x4015 => self.apu.read_status(),
+
             0x4016 | 0x4017 => {
                 let index = (addr & 0x0001) as usize;
-                let value = (self.controller_state[index] & 0x01) | 0x40;
-                if !self.controller_strobe {
-                    self.controller_state[index] >>= 1;
+                let mut value = (self.ports[index].read_bit() & 0x1F) | 0x40;
+                if index == 0 && self.famicom_mode && self.microphone {
+                    value |= 0x04;
                 }
                 value
             },
 
-            // ROM(Cartridge)
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            // Cartridge
+            // Nothing drives the bus where the board has no ROM or RAM, so the value left from
+            // fetching the operand remains, which for absolute addressing is the address high byte
+            0x4020..=0xFFFF => {
+                let data = self.mapper.borrow_mut().cpu_read(addr);
+                data.unwrap_or_else(|| {
+                    let open_bus = (addr >> 8) as u8;
+                    self.log_unmapped_access(addr, open_bus, false);
+                    open_bus
+                })
+            },
             
 
             _ => {
-                println!("Unmapped memory address: {:#X}", addr);
+                self.log_unmapped_access(addr, 0, false);
                 0
             }
 
@@ -95,14 +219,19 @@ impl Bus {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x07FF;
+                let data = self.frozen.get(&mirror_down_addr).copied().unwrap_or(data);
                 self.cpu_vram[mirror_down_addr as usize] = data;
             },
 
-            0x2000 => self.ppu.write_to_control_register(data),
+            0x2000 => {
+                self.mapper.borrow_mut().notify_ppu_control(data);
+                self.ppu.invalidate_background_tile();
+                self.ppu.write_to_control_register(data)
+            }
 
             0x2001 => self.ppu.write_to_mask_register(data),
 
-            0x2002 => {},
+            0x2002 => self.ppu.write_io_latch(data),
 
             0x2003 => self.ppu.write_to_oam_address(data),
 
@@ -114,7 +243,7 @@ impl Bus {
 
             0x2007 => self.ppu.write_data(data),
 
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+            0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = 0x2000 + (addr & 0x0007);
                 self.mem_write(mirror_down_addr, data);
             },
@@ -125,37 +254,372 @@ impl Bus {
                     let value = self.mem_read(base + i);
                     self.ppu.write_to_oam_data(value);
                 }
+                self.dma_stall += OAM_DMA_CYCLES + (self.cpu_cycle_count % 2) as u16;
             },
 
             0x4016 => {
                 let strobe = data & 0x01 == 0x01;
-                if strobe || self.controller_strobe {
-                    self.controller_state = self.controller;
+                for index in 0..2 {
+                    // Turbo is sampled at latch time
+                    let input = self.controller_input(index);
+                    self.ports[index].set_state(input);
+                    self.ports[index].strobe(strobe);
                 }
-                self.controller_strobe = strobe;
             },
 
-            0x4017 => {},
+            0x4015 => self.apu.write_channel_enable(data),
+
+            0x4017 => self.apu.write_frame_counter(data),
 
-            0x8000..=0xFFFF => {
-                // Mapper not implemented yet.
+            // Sound channels are not emulated yet, only their length counters
+            0x4000..=0x4013 => self.apu.write_register(addr, data),
+
+            // ROM writes on boards without registers are simply dropped
+            0x4020..=0xFFFF => {
+                let handled = self.mapper.borrow_mut().cpu_write(addr, data);
+                if handled {
+                    self.sync_mirroring();
+                    // A bank switch mid-line can change the CHR under the tile being drawn
+                    self.ppu.invalidate_background_tile();
+                } else if addr < 0x8000 {
+                    self.log_unmapped_access(addr, data, true);
+                }
             },
 
-            _ => {
-                println!("Unmapped memory address: {:#X}", addr);
-            }
+            _ => self.log_unmapped_access(addr, data, true),
+
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cpu_vram);
+        for port in &self.ports {
+            port.save_state(writer);
+        }
+        // Bank registers and IRQ counters only make sense to the board that wrote them
+        writer.write_u8(self.cartridge.mapper);
+        self.mapper.borrow().save_state(writer);
+        self.ppu.save_state(writer);
+        self.apu.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.cpu_vram)?;
+        for port in &mut self.ports {
+            port.load_state(reader)?;
+        }
+        let mapper = reader.read_u8()?;
+        if mapper != self.cartridge.mapper {
+            return Err(format!("Save state is for mapper {}, this cartridge uses mapper {}", mapper, self.cartridge.mapper));
+        }
+        self.mapper.borrow_mut().load_state(reader)?;
+        self.sync_mirroring();
+        self.ppu.load_state(reader)?;
+        self.apu.load_state(reader)
+    }
+
+    // Pins a RAM address to a value. Only RAM can be frozen, returns false for anything else.
+    pub fn freeze(&mut self, addr: u16, value: u8) -> bool {
+        if addr > RAM_MIRRORS_END {
+            return false;
+        }
+
+        let mirror_down_addr = addr & 0x07FF;
+        self.frozen.insert(mirror_down_addr, value);
+        self.cpu_vram[mirror_down_addr as usize] = value;
+        true
+    }
+
+    pub fn thaw(&mut self, addr: u16) {
+        self.frozen.remove(&(addr & 0x07FF));
+    }
+
+    // Called once per CPU cycle, dma_cycles_left is how much longer the CPU is already halted for DMA
+    pub fn tick(&mut self, dma_cycles_left: u16) {
+        self.cpu_cycle_count += 1;
+        self.apu.clock();
+        self.mapper.borrow_mut().cpu_clock();
+
+        if let Some(address) = self.apu.dmc_fetch_address() {
+            let value = self.mem_read(address);
+            self.apu.fill_dmc_buffer(value);
+            // A fetch in the last cycle of an OAM DMA can't share its halt, it runs on its own afterwards
+            self.dma_stall += if dma_cycles_left > 1 {
+                DMC_DMA_DURING_OAM_CYCLES
+            } else {
+                DMC_DMA_CYCLES + (self.cpu_cycle_count % 2) as u16
+            };
+        }
+    }
+
+    // CPU cycles stolen by OAM and DMC DMA since the last call
+    pub fn take_dma_stall(&mut self) -> u16 {
+        std::mem::take(&mut self.dma_stall)
+    }
 
+    fn log_unmapped_access(&mut self, address: u16, value: u8, write: bool) {
+        if self.unmapped_accesses.len() == UNMAPPED_LOG_CAPACITY {
+            self.unmapped_accesses.pop_front();
         }
+
+        self.unmapped_accesses.push_back(UnmappedAccess {
+            address,
+            value,
+            write,
+            cycle: self.cpu_cycle_count,
+        });
+    }
+
+    // Level of the shared IRQ line, any source holding it asserts an interrupt
+    pub fn poll_irq(&self) -> bool {
+        self.apu.irq_pending() || self.mapper.borrow_mut().irq_pending()
     }
 
-    pub fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.cartridge.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            // Mirror
-            addr -= 0x4000;
+    // Mappers with a mirroring register override the header's mirroring
+    fn sync_mirroring(&mut self) {
+        let mirroring = self.mapper.borrow().mirroring();
+        if let Some(mirroring) = mirroring {
+            if mirroring != self.ppu.mirroring {
+                self.ppu.set_mirroring(mirroring);
+            }
         }
+    }
 
-        self.cartridge.prg_rom[addr as usize]
+    // Called for every filtered rising edge of PPU A12.
+    // Mappers with a scanline counter (MMC3) hook in here.
+    pub fn notify_a12(&mut self, _scanline: u16, _cycle: u16) {
+        self.mapper.borrow_mut().notify_a12();
+    }
+
+    // Reads memory without any side effects, for debugging views
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07FF) as usize],
+            0x4015 => self.apu.peek_status(),
+            0x4020..=0xFFFF => self.read_prg_rom(addr),
+            _ => 0,
+        }
+    }
+
+    pub fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.mapper.borrow_mut().peek(addr).unwrap_or(0)
     }
     
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::NullController;
+    use crate::mapper::{Mapper66, Mapper71};
+    use crate::ppu::SYSTEM_PALLETE;
+
+    // 16KB PRG banks filled with their own bank number
+    fn numbered_prg(banks: u8) -> Vec<u8> {
+        (0..banks).flat_map(|bank| vec![bank; 0x4000]).collect()
+    }
+
+    #[test]
+    fn bus_with_mapper_switches_uxrom_banks() {
+        let prg = numbered_prg(8);
+        let mut bus = Bus::with_mapper(Mapper71::new(prg.clone(), Vec::new(), true, false), prg, Vec::new());
+        assert_eq!(bus.mem_read(0x8000), 0);
+        assert_eq!(bus.mem_read(0xC000), 7);
+
+        bus.mem_write(0xC000, 3);
+        assert_eq!(bus.mem_read(0x8000), 3);
+        assert_eq!(bus.mem_read(0xBFFF), 3);
+        assert_eq!(bus.mem_read(0xFFFF), 7);
+    }
+
+    #[test]
+    fn bus_write_only_ppu_registers_read_the_latch() {
+        let mut bus = nrom_bus();
+        bus.mem_write(0x2000, 0x5A);
+        assert_eq!(bus.mem_read(0x2000), 0x5A);
+        for addr in [0x2001, 0x2003, 0x2005, 0x2006, 0x3FF8] {
+            assert_eq!(bus.mem_read(addr), 0x5A, "${:04X}", addr);
+        }
+
+        // The latch decays to 0 after about a frame without being refreshed
+        for _ in 0..341 * 262 {
+            bus.ppu.clock();
+        }
+        assert_eq!(bus.mem_read(0x2000), 0x00);
+    }
+
+    #[test]
+    fn bus_mid_line_chr_switch_reaches_cached_tile() {
+        // GxROM CHR bank 0 is solid color 1, bank 1 solid color 2
+        let bank_0 = [[0xFF; 8], [0x00; 8]].concat().repeat(512);
+        let bank_1 = [[0x00; 8], [0xFF; 8]].concat().repeat(512);
+        let chr = [bank_0, bank_1].concat();
+        // PRG is all $FF so bus conflicts leave the bank writes alone
+        let prg = vec![0xFF; 0x8000];
+        let mut bus = Bus::with_mapper(Mapper66::new(prg.clone(), chr.clone(), false), prg, chr);
+        bus.ppu.warm_up_enabled = false;
+        bus.ppu.reset();
+        for (addr, value) in [(0x3F00, 0x0F), (0x3F01, 0x30), (0x3F02, 0x16)] {
+            bus.mem_write(0x2006, (addr >> 8) as u8);
+            bus.mem_write(0x2006, addr as u8);
+            bus.mem_write(0x2007, value);
+        }
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_write(0x2001, 0x0A);
+
+        // Halfway through the tile at x=128-135 of line 100
+        while bus.ppu.position() != (100, 133) {
+            bus.ppu.clock();
+        }
+        bus.mem_write(0x8000, 0x01);
+        while !bus.ppu.poll_frame_complete() {
+            bus.ppu.clock();
+        }
+
+        let pixel = |x: usize, y: usize| {
+            let index = (y * 256 + x) * 4;
+            let frame = bus.ppu.completed_frame();
+            (frame[index], frame[index + 1], frame[index + 2])
+        };
+        assert_eq!(pixel(131, 100), SYSTEM_PALLETE[0x30]);
+        assert_eq!(pixel(132, 100), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(0, 101), SYSTEM_PALLETE[0x16]);
+    }
+
+    fn nrom_bus() -> Bus {
+        Bus::new(Cartridge::from_parts(vec![0; 0x8000], Vec::new(), 0, Mirroring::Horizontal))
+    }
+
+    #[test]
+    fn bus_unmapped_accesses_are_logged_in_order_and_capped() {
+        let mut bus = nrom_bus();
+        bus.mem_read(0x5000);
+        bus.mem_write(0x5001, 0x42);
+        let logged: Vec<(u16, u8, bool)> =
+            bus.unmapped_accesses.iter().map(|access| (access.address, access.value, access.write)).collect();
+        // Reads of nothing see the address high byte left on the bus
+        assert_eq!(logged, [(0x5000, 0x50, false), (0x5001, 0x42, true)]);
+
+        for offset in 0..UNMAPPED_LOG_CAPACITY as u16 {
+            bus.mem_read(0x5100 + offset);
+        }
+        assert_eq!(bus.unmapped_accesses.len(), UNMAPPED_LOG_CAPACITY);
+        assert_eq!(bus.unmapped_accesses.front().unwrap().address, 0x5100);
+        assert_eq!(bus.unmapped_accesses.back().unwrap().address, 0x5100 + UNMAPPED_LOG_CAPACITY as u16 - 1);
+    }
+
+    // Latches the controllers the way a game does once per frame, returns whether A reads pressed
+    fn poll_button_a(bus: &mut Bus, port: u16) -> bool {
+        bus.mem_write(0x4016, 0x01);
+        bus.mem_write(0x4016, 0x00);
+        bus.mem_read(port) & 0x01 != 0
+    }
+
+    #[test]
+    fn bus_turbo_toggles_at_its_rate_in_emulated_time() {
+        let mut bus = nrom_bus();
+        bus.set_turbo_state(0, 0x01);
+        let cycles_per_frame = bus.region.cpu_clock_hz() / bus.region.frame_rate();
+
+        // One emulated second polled once per frame
+        let pressed: Vec<bool> = (0..60)
+            .map(|frame| {
+                bus.cpu_cycle_count = (frame as f64 * cycles_per_frame) as u64;
+                poll_button_a(&mut bus, 0x4016)
+            })
+            .collect();
+        let presses = pressed.windows(2).filter(|pair| !pair[0] && pair[1]).count() + pressed[0] as usize;
+        assert_eq!(presses, 15);
+        // Held for half of each period, give or take a frame as 60.1Hz doesn't divide evenly by 15Hz
+        let held = pressed.iter().filter(|&&pressed| pressed).count();
+        assert!((29..=31).contains(&held), "{}", held);
+    }
+
+    #[test]
+    fn bus_famicom_microphone_bit() {
+        let mut bus = nrom_bus();
+        bus.microphone = true;
+        assert_eq!(bus.mem_read(0x4016) & 0x04, 0x00);
+
+        bus.famicom_mode = true;
+        assert_eq!(bus.mem_read(0x4016) & 0x04, 0x04);
+        assert_eq!(bus.mem_read(0x4017) & 0x04, 0x00);
+        bus.microphone = false;
+        assert_eq!(bus.mem_read(0x4016) & 0x04, 0x00);
+    }
+
+    #[test]
+    fn bus_null_device_on_port_2_reads_zero() {
+        let mut bus = nrom_bus();
+        bus.set_controller_device(1, Box::new(NullController));
+        bus.set_controller_state(0, 0xFF);
+        bus.set_controller_state(1, 0xFF);
+
+        bus.mem_write(0x4016, 0x01);
+        bus.mem_write(0x4016, 0x00);
+        for bit in 0..8 {
+            assert_eq!(bus.mem_read(0x4016) & 0x01, 0x01, "bit {}", bit);
+            assert_eq!(bus.mem_read(0x4017) & 0x1F, 0x00, "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn bus_swapped_controllers_cross_ports() {
+        let mut bus = nrom_bus();
+        bus.set_controller_state(0, 0x01);
+        assert!(poll_button_a(&mut bus, 0x4016));
+        assert!(!poll_button_a(&mut bus, 0x4017));
+
+        bus.swap_controllers = true;
+        assert!(!poll_button_a(&mut bus, 0x4016));
+        assert!(poll_button_a(&mut bus, 0x4017));
+    }
+
+    #[test]
+    fn bus_nrom_128_mirrors_prg_and_nrom_256_does_not() {
+        let mut prg = numbered_prg(1);
+        prg[0x1234] = 0x42;
+        let mut bus = Bus::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal));
+        assert_eq!(bus.mem_read(0x9234), 0x42);
+        assert_eq!(bus.mem_read(0xD234), 0x42);
+        assert_eq!(bus.mem_read(0xFFFF), 0);
+
+        let mut bus = Bus::new(Cartridge::from_parts(numbered_prg(2), Vec::new(), 0, Mirroring::Horizontal));
+        assert_eq!(bus.mem_read(0x8000), 0);
+        assert_eq!(bus.mem_read(0xC000), 1);
+        assert_eq!(bus.mem_read(0xFFFF), 1);
+    }
+
+    #[test]
+    fn bus_status_read_acknowledges_frame_irq() {
+        let mut bus = nrom_bus();
+        bus.mem_write(0x4017, 0x00);
+        while !bus.poll_irq() {
+            bus.tick(0);
+        }
+
+        // The debugger's peek leaves it pending, the CPU's $4015 read reports and clears it
+        assert_eq!(bus.peek(0x4015) & 0x40, 0x40);
+        assert!(bus.poll_irq());
+        assert_eq!(bus.mem_read(0x4015) & 0x40, 0x40);
+        assert!(!bus.poll_irq());
+        assert_eq!(bus.mem_read(0x4015) & 0x40, 0x00);
+    }
+
+    #[test]
+    fn bus_peek_leaves_mmc5_irq_pending() {
+        let mut bus = Bus::new(Cartridge::from_parts(vec![0; 0x20000], Vec::new(), 5, Mirroring::Horizontal));
+        bus.mem_write(0x5203, 1);
+        bus.mem_write(0x5204, 0x80);
+        for _ in 0..2 {
+            bus.mapper.borrow_mut().notify_scanline(true);
+        }
+        assert!(bus.poll_irq());
+
+        assert_eq!(bus.peek(0x5204), 0xC0);
+        assert!(bus.poll_irq());
+        assert_eq!(bus.mem_read(0x5204), 0xC0);
+        assert!(!bus.poll_irq());
+    }
+}
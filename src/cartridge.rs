@@ -1,5 +1,9 @@
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use crate::hash::{crc32, sha1, to_hex};
+use crate::mapper::{Mapper0, Mapper11, Mapper206, Mapper30, Mapper4, Mapper5, Mapper66, Mapper69, Mapper7, Mapper71, MapperRef};
+use crate::romdb::RomDbEntry;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct INesHeader {
@@ -21,11 +25,13 @@ impl std::fmt::Display for INesHeader {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     FourScreen,
+    OneScreenLower, // Every nametable maps to the first 1KB of VRAM
+    OneScreenUpper, // Every nametable maps to the second 1KB of VRAM
 }
 
 impl std::fmt::Display for Mirroring {
@@ -34,10 +40,128 @@ impl std::fmt::Display for Mirroring {
             Mirroring::Horizontal => write!(f, "Horizontal"),
             Mirroring::Vertical => write!(f, "Vertical"),
             Mirroring::FourScreen => write!(f, "FourScreen"),
+            Mirroring::OneScreenLower => write!(f, "OneScreenLower"),
+            Mirroring::OneScreenUpper => write!(f, "OneScreenUpper"),
+        }
+    }
+}
+
+impl std::str::FromStr for Mirroring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "horizontal" => Ok(Mirroring::Horizontal),
+            "vertical" => Ok(Mirroring::Vertical),
+            "fourscreen" => Ok(Mirroring::FourScreen),
+            "onescreenlower" => Ok(Mirroring::OneScreenLower),
+            "onescreenupper" => Ok(Mirroring::OneScreenUpper),
+            _ => Err(format!("Unknown mirroring: {}", s)),
+        }
+    }
+}
+
+// Replacements for header fields that are known to be wrong in a dump
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Overrides {
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub prg_ram_kb: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy, // PAL clocked famiclone with NTSC-like PPU timing
+}
+
+impl Region {
+    pub fn cpu_clock_hz(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn ppu_clock_hz(&self) -> f64 {
+        match self {
+            Region::Pal => self.cpu_clock_hz() * 3.2,
+            _ => self.cpu_clock_hz() * 3.0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+            Region::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region: {}", s)),
         }
     }
 }
 
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const DEFAULT_SAVE_DIRECTORY: &str = "saves";
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(std::io::Error),
+    NotInes,
+    Truncated { expected: usize, got: usize }, // File sizes in bytes
+    SizeMismatch { expected: usize, actual: usize }, // Header + trainer + PRG + CHR against the file length
+    UnsupportedMapper(u16),
+    EmptyPrgRom,
+}
+
+impl std::fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::NotInes => write!(f, "File is not in iNES file format"),
+            CartridgeError::Truncated { expected, got } => {
+                write!(f, "File is truncated: expected {} bytes, got {}", expected, got)
+            }
+            CartridgeError::SizeMismatch { expected, actual } if actual < expected => {
+                write!(f, "ROM is {} bytes short of the {} the header describes", expected - actual, expected)
+            }
+            CartridgeError::SizeMismatch { expected, actual } => {
+                write!(f, "ROM has {} bytes past the {} the header describes", actual - expected, expected)
+            }
+            CartridgeError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper {}", mapper),
+            CartridgeError::EmptyPrgRom => write!(f, "Invalid header: PRG ROM size is 0"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// Mappers the bus knows how to drive
+const SUPPORTED_MAPPERS: [u8; 10] = [0, 4, 5, 7, 11, 30, 66, 69, 71, 206];
+
 #[derive(Debug, Clone)]
 pub struct Cartridge {
     pub header: INesHeader,
@@ -46,63 +170,113 @@ pub struct Cartridge {
     pub chr_is_ram: bool,
     pub mirror: Mirroring,
     pub mapper: u8,
+    pub region: Region,
+    // Hashes of PRG+CHR ROM without the header, the way ROM databases identify dumps
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    // Submapper from a ROM database, for plain iNES headers that have no room for one
+    pub submapper_override: Option<u8>,
+    // PRG/CHR RAM sizes in bytes from a ROM database, in place of what the header says
+    pub prg_ram_size_override: Option<usize>,
+    pub chr_ram_size_override: Option<usize>,
+    pub overrides: Overrides,
+    pub rom_path: Option<PathBuf>, // None for cartridges built in memory
+    pub save_directory: PathBuf,
 }
 
 impl Cartridge {
-    pub fn new(filename: &str) -> Result<Cartridge, String> {
-        let mut file = File::open(filename).unwrap();
-        let mut header_buffer: Vec<u8> = vec![0; 16];
+    pub fn new(filename: &str) -> Result<Cartridge, CartridgeError> {
+        Self::load(filename, false, Overrides::default())
+    }
+
+    pub fn new_with_overrides(filename: &str, overrides: Overrides) -> Result<Cartridge, CartridgeError> {
+        Self::load(filename, false, overrides)
+    }
 
-        file.read_exact(&mut header_buffer).unwrap();
+    // With ignore_size_mismatch a bad dump is padded with zeros or cut to the size its header describes
+    pub fn load(filename: &str, ignore_size_mismatch: bool, overrides: Overrides) -> Result<Cartridge, CartridgeError> {
+        let data = std::fs::read(filename).map_err(CartridgeError::Io)?;
+        let mut cartridge = Self::from_bytes(data, ignore_size_mismatch, overrides)?;
+        cartridge.rom_path = Some(PathBuf::from(filename));
+        Ok(cartridge)
+    }
+
+    // Parses an iNES/NES 2.0 image held in memory, the cartridge has no ROM path
+    pub fn from_bytes(mut data: Vec<u8>, ignore_size_mismatch: bool, overrides: Overrides) -> Result<Cartridge, CartridgeError> {
+        if data.len() < HEADER_SIZE {
+            return Err(CartridgeError::Truncated { expected: HEADER_SIZE, got: data.len() });
+        }
 
         let header = INesHeader {
-            name: [header_buffer[0], header_buffer[1], header_buffer[2], header_buffer[3]],
-            prg_rom_size: header_buffer[4],
-            chr_rom_size: header_buffer[5],
-            mapper_1: header_buffer[6],
-            mapper_2: header_buffer[7],
-            prg_ram_size: header_buffer[8],
-            tv_system_1: header_buffer[9],
-            tv_system_2: header_buffer[10],
-            _unused: [header_buffer[11], header_buffer[12], header_buffer[13], header_buffer[14], header_buffer[15]],
+            name: [data[0], data[1], data[2], data[3]],
+            prg_rom_size: data[4],
+            chr_rom_size: data[5],
+            mapper_1: data[6],
+            mapper_2: data[7],
+            prg_ram_size: data[8],
+            tv_system_1: data[9],
+            tv_system_2: data[10],
+            _unused: [data[11], data[12], data[13], data[14], data[15]],
         };
 
         if header.name != [0x4E, 0x45, 0x53, 0x1A] {
-            return Err("File is not in iNES file format".to_string());
+            return Err(CartridgeError::NotInes);
         }
 
-        // Skip the trainer data if header.mapper_1 is 0x04
-        if header.mapper_1 & 0x04 == 0x04 {
-            file.seek(SeekFrom::Current(512)).unwrap();
+        let mapper = overrides.mapper.unwrap_or((header.mapper_2 & 0xF0) | (header.mapper_1 >> 4));
+        if !SUPPORTED_MAPPERS.contains(&mapper) {
+            return Err(CartridgeError::UnsupportedMapper(mapper as u16));
         }
 
-        let mapper = (header.mapper_2 & 0xF0) | (header.mapper_1 >> 4);
+        let prg_bank_size = Self::header_prg_rom_size(&header);
+        if prg_bank_size == 0 {
+            return Err(CartridgeError::EmptyPrgRom);
+        }
 
-        let prg_bank_size = 16384 * header.prg_rom_size as usize;
-        let mut prg_rom = vec![0; prg_bank_size];
-        file.read_exact(&mut prg_rom).unwrap();
+        // Skip the trainer data if header.mapper_1 is 0x04
+        let prg_start = if header.mapper_1 & 0x04 == 0x04 { HEADER_SIZE + TRAINER_SIZE } else { HEADER_SIZE };
 
-        let chr_is_ram = header.chr_rom_size == 0;
+        let chr_is_ram = Self::header_chr_rom_size(&header) == 0;
         let chr_bank_size = if chr_is_ram {
-            8192
+            Self::header_chr_ram_size(&header, mapper)
         } else {
-            8192 * header.chr_rom_size as usize
+            Self::header_chr_rom_size(&header)
         };
-        let mut chr_rom = vec![0; chr_bank_size];
-        if !chr_is_ram {
-            file.read_exact(&mut chr_rom).unwrap();
+
+        let chr_start = prg_start.saturating_add(prg_bank_size);
+        let expected = if chr_is_ram { chr_start } else { chr_start.saturating_add(chr_bank_size) };
+        // NES 2.0 misc ROMs (count in byte 14) follow CHR and take up the rest of the file
+        let has_misc_rom = header.mapper_2 & 0x0C == 0x08 && header._unused[3] & 0x03 != 0;
+        if data.len() != expected && !(has_misc_rom && data.len() > expected) {
+            let mismatch = CartridgeError::SizeMismatch { expected, actual: data.len() };
+            if !ignore_size_mismatch {
+                return Err(mismatch);
+            }
+            eprintln!("Warning: {}, loading it anyway", mismatch);
+            data.resize(expected, 0);
         }
 
+        let prg_rom = data[prg_start..chr_start].to_vec();
+        let chr_rom = if chr_is_ram {
+            vec![0; chr_bank_size]
+        } else {
+            data[chr_start..expected].to_vec()
+        };
+
         // Mirroing
         let four_screen = header.mapper_1 & 0x08 == 0x08;
         let vertical = header.mapper_1 & 0x01 == 0x01;
 
-        let mirror = match (four_screen, vertical) {
-            (true, _) => Mirroring::FourScreen,
-            (false, true) => Mirroring::Vertical,
-            (false, false) => Mirroring::Horizontal,
+        let mirror = match (overrides.mirroring, four_screen, vertical) {
+            (Some(mirroring), _, _) => mirroring,
+            (None, true, _) => Mirroring::FourScreen,
+            (None, false, true) => Mirroring::Vertical,
+            (None, false, false) => Mirroring::Horizontal,
         };
 
+        let region = Self::detect_region(&header);
+        let rom_data = &data[prg_start..expected];
+
         Ok(Cartridge {
             header,
             prg_rom,
@@ -110,6 +284,437 @@ impl Cartridge {
             chr_is_ram,
             mirror,
             mapper,
+            region,
+            crc32: crc32(rom_data),
+            sha1: sha1(rom_data),
+            submapper_override: None,
+            prg_ram_size_override: None,
+            chr_ram_size_override: None,
+            overrides,
+            rom_path: None,
+            save_directory: PathBuf::from(DEFAULT_SAVE_DIRECTORY),
         })
     }
+
+    // Board for this cartridge, with its own copy of PRG and CHR.
+    // Cartridge::new only accepts SUPPORTED_MAPPERS, anything else falls back to NROM.
+    pub fn create_mapper(&self) -> MapperRef {
+        let prg = self.prg_rom.clone();
+        let chr = self.chr_rom.clone();
+        match self.mapper {
+            4 => Rc::new(RefCell::new(Mapper4::new(prg, chr, self.chr_is_ram, self.mirror, self.prg_ram_size()))),
+            5 => Rc::new(RefCell::new(Mapper5::new(prg, chr, self.chr_is_ram, self.prg_ram_size()))),
+            7 => Rc::new(RefCell::new(Mapper7::new(prg, chr, self.chr_is_ram, self.submapper() == Some(2)))),
+            11 => Rc::new(RefCell::new(Mapper11::new(prg, chr, self.chr_is_ram))),
+            30 => {
+                // UNROM 512 reuses the four-screen bit: alone it means mirroring control, with the
+                // vertical bit the board really has four-screen VRAM
+                let mirroring_control = self.header.mapper_1 & 0x09 == 0x08;
+                Rc::new(RefCell::new(Mapper30::new(prg, chr, self.chr_is_ram, mirroring_control, self.has_battery())))
+            }
+            66 => Rc::new(RefCell::new(Mapper66::new(prg, chr, self.chr_is_ram))),
+            69 => Rc::new(RefCell::new(Mapper69::new(prg, chr, self.chr_is_ram, self.prg_ram_size()))),
+            71 => Rc::new(RefCell::new(Mapper71::new(prg, chr, self.chr_is_ram, self.submapper() == Some(1)))),
+            206 => Rc::new(RefCell::new(Mapper206::new(prg, chr, self.chr_is_ram))),
+            _ => Rc::new(RefCell::new(Mapper0::new(prg, chr, self.chr_is_ram, self.prg_ram_size()))),
+        }
+    }
+
+    // Bit 1 of flags 6: PRG RAM is battery backed and should outlive the session
+    pub fn has_battery(&self) -> bool {
+        self.header.mapper_1 & 0x02 != 0
+    }
+
+    // Saves are named after the ROM's SHA-1, so renaming or moving the ROM keeps them.
+    // Without one, a <rom>.sav next to the ROM, where other emulators put saves, is used when it exists.
+    pub fn save_file_path(&self) -> PathBuf {
+        let hashed = self.save_directory.join(format!("{}.sav", self.sha1_hex()));
+        if hashed.exists() {
+            return hashed;
+        }
+        match self.rom_path.as_ref().map(|path| path.with_extension("sav")) {
+            Some(legacy) if legacy.exists() => legacy,
+            _ => hashed,
+        }
+    }
+
+    // Work RAM at $6000-$7FFF in bytes, 0 for boards without any.
+    // NES 2.0 gives volatile and battery backed RAM as 64 << shift in byte 10, 0 meaning none.
+    // iNES counts 8KB units in byte 8, with 0 still meaning 8KB since most dumps leave it unset.
+    pub fn prg_ram_size(&self) -> usize {
+        if let Some(kb) = self.overrides.prg_ram_kb {
+            return kb as usize * 1024;
+        }
+        if let Some(size) = self.prg_ram_size_override {
+            return size;
+        }
+
+        if self.header.mapper_2 & 0x0C == 0x08 {
+            let shift_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            shift_size(self.header.tv_system_2 & 0x0F) + shift_size(self.header.tv_system_2 >> 4)
+        } else {
+            self.header.prg_ram_size.max(1) as usize * 0x2000
+        }
+    }
+
+    // CHR RAM in bytes. For boards with CHR ROM this is extra RAM (TQROM and the like), which the
+    // mapper places in its own windows, chr_rom only ever holds the ROM.
+    pub fn chr_ram_size(&self) -> usize {
+        self.chr_ram_size_override
+            .unwrap_or_else(|| Self::header_chr_ram_size(&self.header, self.mapper))
+    }
+
+    pub fn has_chr_rom_and_ram(&self) -> bool {
+        !self.chr_is_ram && self.chr_ram_size() > 0
+    }
+
+    // Same mapper and memory sizes, so the machine state of one build of a ROM fits the other
+    pub fn same_layout(&self, other: &Cartridge) -> bool {
+        self.mapper == other.mapper
+            && self.prg_rom.len() == other.prg_rom.len()
+            && self.chr_rom.len() == other.chr_rom.len()
+            && self.chr_is_ram == other.chr_is_ram
+            && self.prg_ram_size() == other.prg_ram_size()
+    }
+
+    // NES 2.0 gives volatile and battery backed CHR RAM as 64 << shift in byte 11, like PRG RAM in byte 10.
+    // iNES has no field for it, a board without CHR ROM gets the usual 8KB (32KB on UNROM 512, which
+    // always has four banks), as does a NES 2.0 header that declares neither CHR ROM nor RAM.
+    fn header_chr_ram_size(header: &INesHeader, mapper: u8) -> usize {
+        if header.mapper_2 & 0x0C == 0x08 {
+            let shift_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            let size = shift_size(header._unused[0] & 0x0F) + shift_size(header._unused[0] >> 4);
+            if size > 0 || Self::header_chr_rom_size(header) > 0 {
+                return size;
+            }
+        }
+        match (Self::header_chr_rom_size(header), mapper) {
+            (0, 30) => 0x8000,
+            (0, _) => 0x2000,
+            _ => 0,
+        }
+    }
+
+    // ROM sizes in bytes. NES 2.0 adds the high nibbles of the bank counts in byte 9, a $F nibble
+    // switches the low byte to exponent-multiplier form, 2^E * (MM * 2 + 1) bytes.
+    fn header_rom_size(header: &INesHeader, lsb: u8, msb: u8, bank_size: usize) -> usize {
+        if header.mapper_2 & 0x0C != 0x08 {
+            return lsb as usize * bank_size;
+        }
+        if msb == 0x0F {
+            return 2usize.saturating_pow((lsb >> 2) as u32).saturating_mul((lsb & 0x03) as usize * 2 + 1);
+        }
+        ((msb as usize) << 8 | lsb as usize) * bank_size
+    }
+
+    fn header_prg_rom_size(header: &INesHeader) -> usize {
+        Self::header_rom_size(header, header.prg_rom_size, header.tv_system_1 & 0x0F, 16384)
+    }
+
+    fn header_chr_rom_size(header: &INesHeader) -> usize {
+        Self::header_rom_size(header, header.chr_rom_size, header.tv_system_1 >> 4, 8192)
+    }
+
+    // Builds a cartridge around raw PRG/CHR data, for running code without a ROM file.
+    // Empty CHR is treated as 8KB of CHR RAM, like a header with 0 CHR banks.
+    pub fn from_parts(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mapper: u8, mirror: Mirroring) -> Cartridge {
+        let chr_is_ram = chr_rom.is_empty();
+        let chr_rom = if chr_is_ram { vec![0; 8192] } else { chr_rom };
+
+        let header = INesHeader {
+            name: [0x4E, 0x45, 0x53, 0x1A],
+            prg_rom_size: (prg_rom.len() / 16384) as u8,
+            chr_rom_size: if chr_is_ram { 0 } else { (chr_rom.len() / 8192) as u8 },
+            mapper_1: (mapper << 4) | if mirror == Mirroring::Vertical { 0x01 } else { 0x00 },
+            mapper_2: mapper & 0xF0,
+            prg_ram_size: 0,
+            tv_system_1: 0,
+            tv_system_2: 0,
+            _unused: [0; 5],
+        };
+
+        let mut rom_data = prg_rom.clone();
+        if !chr_is_ram {
+            rom_data.extend_from_slice(&chr_rom);
+        }
+
+        Cartridge {
+            header,
+            prg_rom,
+            chr_rom,
+            chr_is_ram,
+            mirror,
+            mapper,
+            region: Region::Ntsc,
+            crc32: crc32(&rom_data),
+            sha1: sha1(&rom_data),
+            submapper_override: None,
+            prg_ram_size_override: None,
+            chr_ram_size_override: None,
+            overrides: Overrides::default(),
+            rom_path: None,
+            save_directory: PathBuf::from(DEFAULT_SAVE_DIRECTORY),
+        }
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        to_hex(&self.sha1)
+    }
+
+    // Replaces the header's mapper, submapper, region, mirroring and RAM sizes with a database entry, for dumps
+    // with bad headers. Only the mapper nibbles of the header are rewritten, the other bytes mean different
+    // things in iNES and NES 2.0, so the rest is kept next to it.
+    pub fn apply_db_entry(&mut self, entry: &RomDbEntry) -> Result<(), CartridgeError> {
+        let mapper = match u8::try_from(entry.mapper) {
+            Ok(mapper) if SUPPORTED_MAPPERS.contains(&mapper) => mapper,
+            _ => return Err(CartridgeError::UnsupportedMapper(entry.mapper)),
+        };
+
+        self.mapper = mapper;
+        self.header.mapper_1 = (self.header.mapper_1 & 0x0F) | (mapper << 4);
+        self.header.mapper_2 = (self.header.mapper_2 & 0x0F) | (mapper & 0xF0);
+        self.submapper_override = Some(entry.submapper);
+        if let Some(region) = entry.region {
+            self.region = region;
+        }
+        if let Some(mirroring) = entry.mirroring {
+            self.mirror = mirroring;
+        }
+        self.prg_ram_size_override = Some(entry.prg_ram_size);
+        self.chr_ram_size_override = Some(entry.chr_ram_size);
+        if self.chr_is_ram && entry.chr_ram_size > 0 {
+            self.chr_rom.resize(entry.chr_ram_size, 0);
+        }
+        Ok(())
+    }
+
+    // NES 2.0 keeps the submapper in the high nibble of byte 8, plain iNES has none
+    pub fn submapper(&self) -> Option<u8> {
+        if self.submapper_override.is_some() {
+            self.submapper_override
+        } else if self.header.mapper_2 & 0x0C == 0x08 {
+            Some(self.header.prg_ram_size >> 4)
+        } else {
+            None
+        }
+    }
+
+    fn detect_region(header: &INesHeader) -> Region {
+        // NES 2.0 stores the CPU/PPU timing in byte 12
+        if header.mapper_2 & 0x0C == 0x08 {
+            return match header._unused[1] & 0x03 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc,
+            };
+        }
+
+        // iNES byte 9 is the official flag, byte 10 is an unofficial but common extension
+        if header.tv_system_1 & 0x01 == 0x01 || header.tv_system_2 & 0x03 == 0x02 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    // iNES image with the given header flags and zero filled ROM
+    fn ines_image(prg_banks: u8, chr_banks: u8, flags_6: u8, flags_7: u8) -> Vec<u8> {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, flags_6, flags_7];
+        data.resize(HEADER_SIZE, 0);
+        data.resize(HEADER_SIZE + prg_banks as usize * 16384 + chr_banks as usize * 8192, 0);
+        data
+    }
+
+    #[test]
+    fn cartridge_rejects_zero_prg_rom() {
+        let result = Cartridge::from_bytes(ines_image(0, 1, 0x00, 0x00), false, Overrides::default());
+        assert!(matches!(result, Err(CartridgeError::EmptyPrgRom)));
+    }
+
+    #[test]
+    fn cartridge_reports_missing_file() {
+        let path = std::env::temp_dir().join(format!("runes-missing-{}.nes", std::process::id()));
+        let result = Cartridge::new(&path.to_string_lossy());
+        assert!(matches!(result, Err(CartridgeError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn cartridge_rejects_short_header() {
+        let result = Cartridge::from_bytes(vec![0x4E, 0x45, 0x53, 0x1A, 0x01], false, Overrides::default());
+        assert!(matches!(result, Err(CartridgeError::Truncated { expected: 16, got: 5 })));
+    }
+
+    #[test]
+    fn cartridge_rejects_bad_magic() {
+        let mut data = ines_image(1, 1, 0x00, 0x00);
+        data[3] = 0x00;
+        let result = Cartridge::from_bytes(data, false, Overrides::default());
+        assert!(matches!(result, Err(CartridgeError::NotInes)));
+    }
+
+    #[test]
+    fn cartridge_rejects_prg_past_end_of_file() {
+        // The header asks for two 16KB banks, the file only holds one
+        let mut data = ines_image(1, 0, 0x00, 0x00);
+        data[4] = 2;
+        let result = Cartridge::from_bytes(data, false, Overrides::default());
+        assert!(matches!(result, Err(CartridgeError::SizeMismatch { expected: 32784, actual: 16400 })));
+    }
+
+    #[test]
+    fn cartridge_rejects_unsupported_mapper() {
+        // Mapper 1 (MMC1) isn't implemented
+        let result = Cartridge::from_bytes(ines_image(1, 1, 0x10, 0x00), false, Overrides::default());
+        match result {
+            Err(error) => assert_eq!(error.to_string(), "Unsupported mapper 1"),
+            Ok(_) => panic!("mapper 1 was accepted"),
+        }
+    }
+
+    #[test]
+    fn cartridge_mirroring_parses_every_mode() {
+        for mirroring in [
+            Mirroring::Horizontal,
+            Mirroring::Vertical,
+            Mirroring::FourScreen,
+            Mirroring::OneScreenLower,
+            Mirroring::OneScreenUpper,
+        ] {
+            assert_eq!(mirroring.to_string().parse::<Mirroring>(), Ok(mirroring));
+        }
+    }
+
+    #[test]
+    fn cartridge_size_mismatch_one_byte_short() {
+        let mut data = ines_image(1, 1, 0x00, 0x00);
+        let expected = data.len();
+        data.pop();
+        let result = Cartridge::from_bytes(data.clone(), false, Overrides::default());
+        assert!(matches!(
+            result,
+            Err(CartridgeError::SizeMismatch { expected: e, actual }) if e == expected && actual == expected - 1
+        ));
+
+        // Opting in pads the missing byte with zero
+        let cartridge = Cartridge::from_bytes(data, true, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_rom.len(), 0x4000);
+        assert_eq!(cartridge.chr_rom.len(), 0x2000);
+    }
+
+    #[test]
+    fn cartridge_size_mismatch_4kb_long() {
+        let mut data = ines_image(1, 1, 0x00, 0x00);
+        let expected = data.len();
+        data.extend(vec![0xFF; 0x1000]);
+        let result = Cartridge::from_bytes(data.clone(), false, Overrides::default());
+        assert!(matches!(
+            result,
+            Err(CartridgeError::SizeMismatch { expected: e, actual }) if e == expected && actual == expected + 0x1000
+        ));
+
+        // Opting in drops the extra bytes
+        let cartridge = Cartridge::from_bytes(data, true, Overrides::default()).unwrap();
+        assert_eq!(cartridge.chr_rom, vec![0; 0x2000]);
+    }
+
+    #[test]
+    fn cartridge_mirroring_override_reaches_the_ppu() {
+        let vertical = ines_image(1, 1, 0x01, 0x00);
+        let cartridge = Cartridge::from_bytes(vertical.clone(), false, Overrides::default()).unwrap();
+        assert_eq!(Bus::new(cartridge).ppu.mirroring, Mirroring::Vertical);
+
+        let overrides = Overrides { mirroring: Some(Mirroring::Horizontal), ..Overrides::default() };
+        let cartridge = Cartridge::from_bytes(vertical, false, overrides).unwrap();
+        assert_eq!(cartridge.mirror, Mirroring::Horizontal);
+        assert_eq!(Bus::new(cartridge).ppu.mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn cartridge_nes2_misc_rom_follows_chr() {
+        // One misc ROM in byte 14, its size is whatever is left after CHR
+        let mut data = ines_image(1, 1, 0x00, 0x08);
+        data[14] = 0x01;
+        data.extend(vec![0xA5; 0x100]);
+        let cartridge = Cartridge::from_bytes(data, false, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_rom.len(), 0x4000);
+        assert_eq!(cartridge.chr_rom.len(), 0x2000);
+        assert!(cartridge.chr_rom.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn cartridge_nes2_size_high_nibbles() {
+        // PRG with $1 in the high nibble is 256 + 1 banks, CHR with $F is 2^12 * (0 * 2 + 1) bytes
+        let mut data = ines_image(1, 0, 0x00, 0x08);
+        data[5] = 12 << 2;
+        data[9] = 0xF1;
+        data.resize(HEADER_SIZE + 257 * 0x4000 + 0x1000, 0);
+        let cartridge = Cartridge::from_bytes(data, false, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_rom.len(), 257 * 0x4000);
+        assert_eq!(cartridge.chr_rom.len(), 0x1000);
+        assert!(!cartridge.chr_is_ram);
+    }
+
+    #[test]
+    fn cartridge_prg_ram_size_from_header() {
+        // iNES byte 8 counts 8KB units, the $6000 window shows the first 8KB of 16KB
+        let mut data = ines_image(1, 1, 0x00, 0x00);
+        data[8] = 2;
+        let cartridge = Cartridge::from_bytes(data, false, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_ram_size(), 0x4000);
+        let mut bus = Bus::new(cartridge);
+        bus.mem_write(0x6000, 0x11);
+        bus.mem_write(0x7FFF, 0x22);
+        assert_eq!((bus.mem_read(0x6000), bus.mem_read(0x7FFF)), (0x11, 0x22));
+
+        // NES 2.0 byte 10 with 64 << 5 is 2KB, repeated four times across the window
+        let mut data = ines_image(1, 1, 0x00, 0x08);
+        data[10] = 0x05;
+        let cartridge = Cartridge::from_bytes(data, false, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_ram_size(), 0x0800);
+        let mut bus = Bus::new(cartridge);
+        bus.mem_write(0x6001, 0x33);
+        for addr in [0x6001, 0x6801, 0x7001, 0x7801] {
+            assert_eq!(bus.mem_read(addr), 0x33, "${:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn cartridge_nes2_without_prg_ram_is_open_bus() {
+        let cartridge = Cartridge::from_bytes(ines_image(1, 1, 0x00, 0x08), false, Overrides::default()).unwrap();
+        assert_eq!(cartridge.prg_ram_size(), 0);
+        let mut bus = Bus::new(cartridge);
+        bus.mem_write(0x6000, 0x44);
+        assert_eq!(bus.mem_read(0x6000), 0x60);
+        assert_eq!(bus.mem_read(0x7F10), 0x7F);
+    }
+
+    #[test]
+    fn cartridge_unrom512_nes2_chr_ram_banks() {
+        // UNROM 512 with 64 << 9 bytes of CHR RAM in byte 11, PRG of $FF so the bank writes see no bus conflict
+        let mut data = ines_image(2, 0, 0xE0, 0x18);
+        data[11] = 0x09;
+        data[HEADER_SIZE..].fill(0xFF);
+        let cartridge = Cartridge::from_bytes(data, false, Overrides::default()).unwrap();
+        assert!(cartridge.chr_is_ram);
+        assert_eq!(cartridge.chr_rom.len(), 0x8000);
+
+        let mapper = cartridge.create_mapper();
+        let mut mapper = mapper.borrow_mut();
+        for bank in 0..4u8 {
+            mapper.cpu_write(0x8000, bank << 5);
+            mapper.ppu_write(0x0000, 0xA0 + bank);
+            mapper.ppu_write(0x1FFF, 0xB0 + bank);
+        }
+        for bank in (0..4u8).rev() {
+            mapper.cpu_write(0x8000, bank << 5);
+            assert_eq!(mapper.ppu_read(0x0000), Some(0xA0 + bank), "bank {}", bank);
+            assert_eq!(mapper.ppu_read(0x1FFF), Some(0xB0 + bank), "bank {}", bank);
+        }
+    }
 }
@@ -0,0 +1,127 @@
+use crate::savestate::{StateReader, StateWriter};
+
+// Something plugged into a controller port, read serially through $4016/$4017
+pub trait ControllerDevice {
+    // Bit 0 of a $4016 write, the same line goes to both ports
+    fn strobe(&mut self, high: bool);
+    // One read of the port, only bits 0-4 are driven by the device
+    fn read_bit(&mut self) -> u8;
+    // Buttons held on the host side, in standard pad order (A, B, Select, Start, Up, Down, Left, Right)
+    fn set_state(&mut self, buttons: u8);
+
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String>;
+}
+
+// Standard pad, an 8 bit shift register reloaded from the buttons while strobe is high
+#[derive(Default)]
+pub struct StandardController {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl ControllerDevice for StandardController {
+    fn strobe(&mut self, high: bool) {
+        // The falling edge latches too, so buttons pressed while strobe was high are kept
+        if high || self.strobe {
+            self.shift = self.buttons;
+        }
+        self.strobe = high;
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        let bit = self.shift & 0x01;
+        if !self.strobe {
+            self.shift >>= 1;
+        }
+        bit
+    }
+
+    fn set_state(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.shift);
+        writer.write_bool(self.strobe);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.shift = reader.read_u8()?;
+        self.strobe = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+// Empty port, nothing drives the data lines
+pub struct NullController;
+
+impl ControllerDevice for NullController {
+    fn strobe(&mut self, _high: bool) {}
+
+    fn read_bit(&mut self) -> u8 {
+        0
+    }
+
+    fn set_state(&mut self, _buttons: u8) {}
+
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Host buttons between two emulated frame boundaries. A tap released before the next boundary
+// still counts for one whole frame.
+#[derive(Default)]
+pub struct ButtonLatch {
+    held: u8,
+    tapped: u8, // Pressed since the last boundary, even if already released
+}
+
+impl ButtonLatch {
+    pub fn update(&mut self, held: u8, pressed: u8) {
+        self.held = held;
+        self.tapped |= pressed;
+    }
+
+    // Buttons for the frame that starts now
+    pub fn latch(&mut self) -> u8 {
+        let buttons = self.held | self.tapped;
+        self.tapped = 0;
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controller_tap_lasts_one_frame() {
+        let mut latch = ButtonLatch::default();
+        // A pressed and released between two boundaries, seen over a few repaints
+        latch.update(0x01, 0x01);
+        latch.update(0x00, 0x00);
+        latch.update(0x00, 0x00);
+
+        let frames: Vec<u8> = (0..3).map(|_| latch.latch()).collect();
+        assert_eq!(frames, [0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn controller_held_button_lasts_until_released() {
+        let mut latch = ButtonLatch::default();
+        latch.update(0x80, 0x80);
+        assert_eq!(latch.latch(), 0x80);
+        latch.update(0x80, 0x00);
+        assert_eq!(latch.latch(), 0x80);
+        latch.update(0x00, 0x00);
+        assert_eq!(latch.latch(), 0x00);
+    }
+}
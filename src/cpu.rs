@@ -1,6 +1,16 @@
 use crate::opcodes::{references, Opcode};
 use crate::bus::Bus;
-use crate::cartridge::Cartridge;
+use crate::ppu::PPUStatusFlags;
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::cartridge::{Cartridge, Region};
+use crate::trace::TraceLog;
+use std::collections::HashSet;
+
+// Upper bound of system clocks for the run-until helpers, a bit more than one frame
+const MAX_RUN_CYCLES: u32 = 341 * 312;
+// Cap for a single step, so a runaway ROM can't lock up the UI thread. A step has to cover the
+// longest instruction plus the OAM DMA and DMC fetches it can trigger, ~530 CPU cycles.
+pub const MAX_STEP_CLOCKS: u32 = 4096;
 
 enum StatusFlag {
     C = (1 << 0), // Carry Bit
@@ -48,6 +58,18 @@ pub struct CPU {
     pub bus: Bus,
 
     pub system_clock_counter: u32,
+
+    pub halted: bool, // Set by the JAM opcodes, cleared on reset
+    pub dma_stall: u16, // CPU cycles left where DMA owns the bus
+
+    // Debugger
+    pub opcode_breakpoints: HashSet<u8>,
+    pub break_on_illegal: bool,
+    pub breakpoint_hit: Option<(u16, u8)>, // (PC, opcode) of the instruction that triggered the break
+    pub trace_log: Option<TraceLog>,
+
+    // Accept save states made with a different ROM, e.g. another revision of the same game
+    pub ignore_state_rom_mismatch: bool,
 }
     
 impl CPU {
@@ -70,6 +92,16 @@ impl CPU {
             bus: Bus::new(cartridge),
 
             system_clock_counter: 0,
+
+            halted: false,
+            dma_stall: 0,
+
+            opcode_breakpoints: HashSet::new(),
+            break_on_illegal: false,
+            breakpoint_hit: None,
+            trace_log: None,
+
+            ignore_state_rom_mismatch: false,
         } 
     }
 
@@ -84,12 +116,32 @@ impl CPU {
     pub fn clock(&mut self) {
 
         self.bus.ppu.clock();
+        for (scanline, cycle) in self.bus.ppu.take_a12_edges() {
+            self.bus.notify_a12(scanline, cycle);
+        }
+        if self.bus.ppu.scanline_break {
+            self.bus.ppu.scanline_break = false;
+            self.breakpoint_hit = Some((self.program_counter, self.opcode));
+        }
 
-        // CPU runs 1/3 as fast as PPU
+        if self.cpu_cycle_due() {
+            self.bus.tick(if self.cycles == 0 { self.dma_stall } else { 0 });
+            self.dma_stall += self.bus.take_dma_stall();
+        }
 
-        if self.system_clock_counter % 3 == 0 {
+        if self.cpu_cycle_due() && !self.halted && self.cycles == 0 && self.dma_stall > 0 {
+            // DMA starts after the write that triggered it, the CPU sits idle until it's done
+            self.dma_stall -= 1;
+        } else if self.cpu_cycle_due() && !self.halted {
             if self.cycles == 0 {
+                if self.trace_log.is_some() {
+                    self.write_trace();
+                }
+
                 self.opcode = self.read(self.program_counter, false);
+                if self.is_opcode_breakpoint(self.opcode) {
+                    self.breakpoint_hit = Some((self.program_counter, self.opcode));
+                }
                 self.program_counter = self.program_counter.wrapping_add(1);
 
                 let operate = &references::INSTRUCTION_LOOKUP[self.opcode as usize].operate;
@@ -170,6 +222,7 @@ Opcode::PLA => self.pla(),
                     Opcode::TXA => self.txa(),
                     Opcode::TXS => self.txs(),
                     Opcode::TYA => self.tya(),
+                    Opcode::JAM => self.jam(),
                     Opcode::XXX => self.xxx(),
                 };
 
@@ -180,16 +233,88 @@ Opcode::PLA => self.pla(),
             }
 
             self.cycles -= 1;
+
+            // IRQ is level triggered and checked once the current instruction has finished
+            if self.cycles == 0 && self.bus.poll_irq() {
+                self.irq();
+            }
         }
 
         // When entering vblank, the PPU will set the NMI flag 
-        if self.bus.ppu.nmi {
+        if self.bus.ppu.nmi && !self.halted {
             self.bus.ppu.nmi = false;
             self.nmi();
         }
 
         self.system_clock_counter += 1;
     }
+
+    // CPU runs 1/3 as fast as PPU on NTSC and Dendy, and 1/3.2 on PAL (5 CPU cycles every 16 PPU cycles)
+    fn cpu_cycle_due(&self) -> bool {
+        match self.bus.region {
+            Region::Pal => matches!(self.system_clock_counter % 16, 0 | 3 | 6 | 9 | 12),
+            _ => self.system_clock_counter.is_multiple_of(3),
+        }
+    }
+
+    // Runs the system clock one PPU tick at a time until the condition holds.
+    // Stops early on a breakpoint, returns whether the condition was reached.
+    pub fn run_until<F: FnMut(&CPU) -> bool>(&mut self, mut condition: F) -> bool {
+        for _ in 0..MAX_RUN_CYCLES {
+            self.clock();
+            if condition(self) {
+                return true;
+            }
+            if self.breakpoint_hit.is_some() {
+                return false;
+            }
+        }
+        false
+    }
+
+    // Runs one instruction to completion, false if it didn't complete within MAX_STEP_CLOCKS.
+    // Completion is only checked on CPU cycles, the PPU clocks in between would otherwise end the
+    // step right away when it starts between instructions.
+    pub fn step_instruction(&mut self) -> bool {
+        for _ in 0..MAX_STEP_CLOCKS {
+            let cpu_cycle = self.cpu_cycle_due();
+            self.clock();
+            if cpu_cycle && self.complete() {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Runs until the PPU is about to process the given (scanline, cycle)
+    pub fn run_to_ppu_position(&mut self, scanline: u16, cycle: u16) -> bool {
+        self.run_until(|cpu| cpu.bus.ppu.scanline == scanline && cpu.bus.ppu.cycle == cycle)
+    }
+
+    pub fn run_to_next_scanline(&mut self) -> bool {
+        let scanline = self.bus.ppu.scanline;
+        self.run_until(|cpu| cpu.bus.ppu.scanline != scanline)
+    }
+
+    // Runs until the current subroutine returns, i.e. an RTS/RTI pops the stack above where it was.
+    // Nested calls and interrupts taken on the way only bring SP back to that level, so they don't stop it.
+    pub fn step_out(&mut self) -> bool {
+        let stack_pointer = self.stack_pointer;
+        self.run_until(|cpu| {
+            let returned = matches!(cpu.opcode, 0x60 | 0x40); // RTS, RTI
+            cpu.cycles == 0 && returned && cpu.stack_pointer > stack_pointer
+        })
+    }
+
+    // Runs until the PPU has set the vblank flag
+    pub fn run_to_vblank(&mut self) -> bool {
+        let mut in_vblank = self.bus.ppu.status_register & PPUStatusFlags::VerticalBlank as u8 != 0;
+        self.run_until(|cpu| {
+            let was_in_vblank = in_vblank;
+            in_vblank = cpu.bus.ppu.status_register & PPUStatusFlags::VerticalBlank as u8 != 0;
+            in_vblank && !was_in_vblank
+        })
+    }
 }
 
 impl CPU {
@@ -268,7 +393,8 @@ impl CPU {
         self.program_counter = self.program_counter.wrapping_add(1);
 
         self.addr_abs = (hi << 8) | lo;
-        self.addr_abs += self.x_register as u16;
+        self.addr_abs = self.addr_abs.wrapping_add(self.x_register as u16);
+        self.dummy_indexed_read(hi << 8);
 
         // If the addition of the offset causes a change in the high byte, an additional cycle is required
         if (self.addr_abs & 0xFF00) != (hi << 8) {
@@ -286,7 +412,8 @@ impl CPU {
         self.program_counter = self.program_counter.wrapping_add(1);
 
         self.addr_abs = (hi << 8) | lo;
-        self.addr_abs += self.y_register as u16;
+        self.addr_abs = self.addr_abs.wrapping_add(self.y_register as u16);
+        self.dummy_indexed_read(hi << 8);
 
         // If the addition of the offset causes a change in the high byte, an additional cycle is required
         if (self.addr_abs & 0xFF00) != (hi << 8) {
@@ -335,7 +462,8 @@ impl CPU {
         let hi = self.read((t + 1) & 0x00FF, false) as u16;
 
         self.addr_abs = (hi << 8) | lo;
-        self.addr_abs += self.y_register as u16;
+        self.addr_abs = self.addr_abs.wrapping_add(self.y_register as u16);
+        self.dummy_indexed_read(hi << 8);
 
         // If the addition of the offset causes a change in the high byte, an additional cycle is required
         if (self.addr_abs & 0xFF00) != (hi << 8) {
@@ -345,6 +473,21 @@ impl CPU {
         }
     }
 
+    // Indexed modes read from the address before the carry into the high byte is fixed up.
+    // Loads only do this when a page is crossed, stores and read-modify-write always do.
+    fn dummy_indexed_read(&mut self, base_high: u16) {
+        let unfixed = base_high | (self.addr_abs & 0x00FF);
+        let always = matches!(
+            references::INSTRUCTION_LOOKUP[self.opcode as usize].operate,
+            Opcode::STA | Opcode::STX | Opcode::STY | Opcode::ASL | Opcode::LSR
+                | Opcode::ROL | Opcode::ROR | Opcode::INC | Opcode::DEC
+        );
+
+        if unfixed != self.addr_abs || always {
+            self.read(unfixed, false);
+        }
+    }
+
     fn rel(&mut self) -> u8 {
         self.addr_rel = self.read(self.program_counter, false) as u16;
         self.program_counter = self.program_counter.wrapping_add(1);
@@ -356,6 +499,12 @@ impl CPU {
 
         0
     }
+    // Read-modify-write instructions write the unmodified value back before the result
+    fn write_modified(&mut self, value: u8) {
+        self.write(self.addr_abs, self.fetched);
+        self.write(self.addr_abs, value);
+    }
+
     // fetches data from memory using the address mode
     fn fetch(&mut self) -> u8 {
         if references::INSTRUCTION_LOOKUP[self.opcode as usize].addrmode != AddressingMode::IMP {
@@ -672,7 +821,7 @@ impl CPU {
         if references::INSTRUCTION_LOOKUP[self.opcode as usize].addrmode == AddressingMode::IMP {
             self.accumulator = temp as u8;
         } else {
-            self.write(self.addr_abs, temp as u8);
+            self.write_modified(temp as u8);
         }
         0
     }
@@ -686,7 +835,7 @@ impl CPU {
         if references::INSTRUCTION_LOOKUP[self.opcode as usize].addrmode == AddressingMode::IMP {
             self.accumulator = temp as u8;
         } else {
-            self.write(self.addr_abs, temp as u8);
+            self.write_modified(temp as u8);
         }
         0
     }
@@ -700,7 +849,7 @@ impl CPU {
         if references::INSTRUCTION_LOOKUP[self.opcode as usize].addrmode == AddressingMode::IMP {
             self.accumulator = temp as u8;
         } else {
-            self.write(self.addr_abs, temp as u8);
+            self.write_modified(temp as u8);
         }
         0
     }
@@ -714,7 +863,7 @@ impl CPU {
         if references::INSTRUCTION_LOOKUP[self.opcode as usize].addrmode == AddressingMode::IMP {
             self.accumulator = temp as u8;
         } else {
-            self.write(self.addr_abs, temp as u8);
+            self.write_modified(temp as u8);
         }
         0
     }
@@ -749,7 +898,7 @@ impl CPU {
     fn inc(&mut self) -> u8 {
         self.fetch();
         let temp: u16 = self.fetched as u16 + 1;
-        self.write(self.addr_abs, temp as u8);
+        self.write_modified(temp as u8);
         self.set_flag(StatusFlag::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(StatusFlag::N, (temp & 0x0080) != 0);
         0
@@ -772,7 +921,7 @@ impl CPU {
     fn dec(&mut self) -> u8 {
         self.fetch();
         let temp = self.fetched.wrapping_sub(1) as u16;
-        self.write(self.addr_abs, temp as u8);
+        self.write_modified(temp as u8);
         self.set_flag(StatusFlag::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(StatusFlag::N, (temp & 0x0080) != 0);
         0
@@ -794,7 +943,7 @@ impl CPU {
 
     fn eor(&mut self) -> u8 {
         self.fetch();
-        self.accumulator = self.accumulator ^ self.fetched;
+        self.accumulator ^= self.fetched;
         self.set_flag(StatusFlag::Z, self.accumulator == 0x00);
         self.set_flag(StatusFlag::N, (self.accumulator & 0x80) != 0);
         1
@@ -802,7 +951,7 @@ impl CPU {
 
     fn ora(&mut self) -> u8 {
         self.fetch();
-        self.accumulator = self.accumulator | self.fetched;
+        self.accumulator |= self.fetched;
         self.set_flag(StatusFlag::Z, self.accumulator == 0x00);
         self.set_flag(StatusFlag::N, (self.accumulator & 0x80) != 0);
         1
@@ -823,8 +972,19 @@ impl CPU {
         0
     }
 
+    // Status byte as it lands on the stack. B only exists there: set by BRK/PHP, clear for IRQ/NMI.
+    // Bit 5 isn't a real flag either and always reads back as 1.
+    fn pushed_status(&self, break_flag: bool) -> u8 {
+        let status = (self.status | StatusFlag::U as u8) & !(StatusFlag::B as u8);
+        if break_flag {
+            status | StatusFlag::B as u8
+        } else {
+            status
+        }
+    }
+
     fn php(&mut self) -> u8 {
-        self.write(0x0100 + self.stack_pointer as u16, self.status | StatusFlag::B as u8 | StatusFlag::U as u8);
+        self.write(0x0100 + self.stack_pointer as u16, self.pushed_status(true));
         self.set_flag(StatusFlag::B, false);
         self.set_flag(StatusFlag::U, false);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
@@ -853,11 +1013,12 @@ impl CPU {
             self.write(0x0100 + self.stack_pointer as u16, (self.program_counter & 0x00FF) as u8);
             self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
+            // I is set after the push, so RTI brings back the mask the handler interrupted
+            self.write(0x0100 + self.stack_pointer as u16, self.pushed_status(false));
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
             self.set_flag(StatusFlag::B, false);
             self.set_flag(StatusFlag::U, true);
             self.set_flag(StatusFlag::I, true);
-            self.write(0x0100 + self.stack_pointer as u16, self.status);
-            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
             self.addr_abs = 0xFFFE;
             let lo = self.read(self.addr_abs, false) as u16;
@@ -876,11 +1037,11 @@ impl CPU {
         self.write(0x0100 + self.stack_pointer as u16, (self.program_counter & 0x00FF) as u8);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
+        self.write(0x0100 + self.stack_pointer as u16, self.pushed_status(false));
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
         self.set_flag(StatusFlag::B, false);
         self.set_flag(StatusFlag::U, true);
         self.set_flag(StatusFlag::I, true);
-        self.write(0x0100 + self.stack_pointer as u16, self.status);
-        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
         self.addr_abs = 0xFFFA;
         let lo = self.read(self.addr_abs, false) as u16;
@@ -920,16 +1081,14 @@ impl CPU {
     fn brk(&mut self) -> u8 {
         self.program_counter = self.program_counter.wrapping_add(1);
 
-        self.set_flag(StatusFlag::I, true);
         self.write(0x0100 + self.stack_pointer as u16, ((self.program_counter >> 8) & 0x00FF) as u8);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
         self.write(0x0100 + self.stack_pointer as u16, (self.program_counter & 0x00FF) as u8);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
-        self.set_flag(StatusFlag::B, true);
-        self.write(0x0100 + self.stack_pointer as u16, self.status);
+        self.write(0x0100 + self.stack_pointer as u16, self.pushed_status(true));
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
-        self.set_flag(StatusFlag::B, false);
+        self.set_flag(StatusFlag::I, true);
 
         self.addr_abs = 0xFFFE;
         let lo = self.read(self.addr_abs, false) as u16;
@@ -953,6 +1112,15 @@ impl CPU {
     fn xxx(&mut self) -> u8 {
         0
     }
+
+    fn jam(&mut self) -> u8 {
+        // The real CPU locks up, keep the PC pointing at the offending opcode
+        self.program_counter = self.program_counter.wrapping_sub(1);
+        self.halted = true;
+        // Let the instruction complete so single stepping doesn't spin on a halted CPU
+        self.cycles = 1;
+        0
+    }
     
     pub fn reset(&mut self) {
         self.addr_abs = 0xFFFC;
@@ -970,12 +1138,591 @@ impl CPU {
         self.addr_abs = 0x0000;
         self.fetched = 0x00;
 
+        self.halted = false;
+        self.dma_stall = 0;
         self.cycles = 8;
     }
 
+    // One line per instruction, in a nestest-like layout:
+    // C000  4C F5 C5  JMP $C5F5      A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    pub fn trace(&self) -> String {
+        let pc = self.program_counter;
+        let (instruction, _) = self.disassemble(pc);
+        let (scanline, dot) = self.bus.ppu.position();
+
+        format!(
+            "{:04X}  {} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc,
+            instruction,
+            self.accumulator,
+            self.x_register,
+            self.y_register,
+            self.status,
+            self.stack_pointer,
+            scanline,
+            dot,
+            self.bus.cpu_cycle_count,
+        )
+    }
+
+    // Instruction bytes, mnemonic and operand at addr, read without side effects.
+    // Returns the padded text and the instruction length.
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        let opcode = self.bus.peek(addr);
+        let instruction = &references::INSTRUCTION_LOOKUP[opcode as usize];
+        let low = self.bus.peek(addr.wrapping_add(1));
+        let high = self.bus.peek(addr.wrapping_add(2));
+        let word = (high as u16) << 8 | low as u16;
+
+        let (length, operand) = match instruction.addrmode {
+            AddressingMode::IMP => (1, String::new()),
+            AddressingMode::IMM => (2, format!("#${:02X}", low)),
+            AddressingMode::ZP0 => (2, format!("${:02X}", low)),
+            AddressingMode::ZPX => (2, format!("${:02X},X", low)),
+            AddressingMode::ZPY => (2, format!("${:02X},Y", low)),
+            AddressingMode::REL => {
+                let target = addr.wrapping_add(2).wrapping_add(low as i8 as u16);
+                (2, format!("${:04X}", target))
+            }
+            AddressingMode::ABS => (3, format!("${:04X}", word)),
+            AddressingMode::ABX => (3, format!("${:04X},X", word)),
+            AddressingMode::ABY => (3, format!("${:04X},Y", word)),
+            AddressingMode::IND => (3, format!("(${:04X})", word)),
+            AddressingMode::IZX => (2, format!("(${:02X},X)", low)),
+            AddressingMode::IZY => (2, format!("(${:02X}),Y", low)),
+        };
+
+        let bytes = [opcode, low, high][..length]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        (format!("{:<8}  {:?} {:<10}", bytes, instruction.operate, operand), length as u16)
+    }
+
+    fn write_trace(&mut self) {
+        let line = self.trace();
+        let Some(log) = &mut self.trace_log else {
+            return;
+        };
+
+        // Full or failing logs are closed, which flushes what was buffered
+        if !log.write_line(&line) {
+            if let Some(log) = self.trace_log.take() {
+                let _ = log.finish();
+            }
+        }
+    }
+
+    pub fn is_opcode_breakpoint(&self, opcode: u8) -> bool {
+        self.opcode_breakpoints.contains(&opcode)
+            || (self.break_on_illegal
+                && matches!(
+                    references::INSTRUCTION_LOOKUP[opcode as usize].operate,
+                    Opcode::XXX | Opcode::JAM
+                ))
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        let mut writer = StateWriter::new(self.bus.cartridge.crc32);
+        writer.write_u8(self.accumulator);
+        writer.write_u8(self.x_register);
+        writer.write_u8(self.y_register);
+        writer.write_u8(self.stack_pointer);
+        writer.write_u16(self.program_counter);
+        writer.write_u8(self.status);
+        writer.write_u8(self.fetched);
+        writer.write_u16(self.addr_abs);
+        writer.write_u16(self.addr_rel);
+        writer.write_u8(self.opcode);
+        writer.write_u8(self.cycles);
+        writer.write_u32(self.system_clock_counter);
+        writer.write_bool(self.halted);
+        writer.write_u16(self.dma_stall);
+        self.bus.save_state(&mut writer);
+        writer.finish()
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        let mut reader = StateReader::new(state)?;
+        // Restoring RAM and bank registers meant for another game would leave it in garbage
+        if reader.rom_crc32 != self.bus.cartridge.crc32 && !self.ignore_state_rom_mismatch {
+            return Err(format!(
+                "Save state is for ROM {:08X}, the loaded ROM is {:08X}",
+                reader.rom_crc32, self.bus.cartridge.crc32
+            ));
+        }
+
+        // Fields are applied as they're decoded, so a state that fails partway through (truncated,
+        // wrong mapper) is rolled back to the machine as it was rather than left half loaded
+        let snapshot = self.save_state();
+        if let Err(error) = self.apply_state(&mut reader) {
+            let mut reader = StateReader::new(&snapshot)?;
+            self.apply_state(&mut reader)?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Loads a state made with an earlier build of the loaded ROM, for hot reload. The ROM CRC is
+    // bound to differ, everything else still has to match.
+    pub fn load_state_from_previous_build(&mut self, state: &SaveState) -> Result<(), String> {
+        let ignore_state_rom_mismatch = std::mem::replace(&mut self.ignore_state_rom_mismatch, true);
+        let result = self.load_state(state);
+        self.ignore_state_rom_mismatch = ignore_state_rom_mismatch;
+        result
+    }
+
+    fn apply_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.accumulator = reader.read_u8()?;
+        self.x_register = reader.read_u8()?;
+        self.y_register = reader.read_u8()?;
+        self.stack_pointer = reader.read_u8()?;
+        self.program_counter = reader.read_u16()?;
+        self.status = reader.read_u8()?;
+        self.fetched = reader.read_u8()?;
+        self.addr_abs = reader.read_u16()?;
+        self.addr_rel = reader.read_u16()?;
+        self.opcode = reader.read_u8()?;
+        self.cycles = reader.read_u8()?;
+        self.system_clock_counter = reader.read_u32()?;
+        self.halted = reader.read_bool()?;
+        self.dma_stall = reader.read_u16()?;
+        self.breakpoint_hit = None;
+        self.bus.load_state(reader)
+    }
+
     pub fn complete(&mut self) -> bool {
-        self.cycles == 0
+        self.cycles == 0 && self.dma_stall == 0
     }
 
 
 }    
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Mirroring, Overrides};
+    use crate::mapper::{BankWindow, Mapper, MapperDebugInfo};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn test_cpu(mapper: u8) -> CPU {
+        CPU::new(Cartridge::from_parts(vec![0xEA; 0x8000], Vec::new(), mapper, Mirroring::Horizontal))
+    }
+
+    // NROM board with the program at $8000, PC already pointing at it
+    fn program_cpu(program: &[u8]) -> CPU {
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..program.len()].copy_from_slice(program);
+        let mut cpu = CPU::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal));
+        cpu.program_counter = 0x8000;
+        cpu
+    }
+
+    #[test]
+    fn cpu_opcode_breakpoint_halts_at_first_brk() {
+        let mut cpu = program_cpu(&[0xEA, 0xEA, 0x00, 0xEA, 0x00]);
+        cpu.opcode_breakpoints.insert(0x00);
+        assert!(!cpu.run_until(|_| false));
+        assert_eq!(cpu.breakpoint_hit, Some((0x8002, 0x00)));
+    }
+
+    #[test]
+    fn cpu_scanline_breakpoint_stops_at_that_line() {
+        let mut cpu = program_cpu(&[]);
+        cpu.bus.ppu.break_scanline = Some(100);
+        assert!(!cpu.run_until(|_| false));
+        assert!(cpu.breakpoint_hit.is_some());
+        assert_eq!((cpu.bus.ppu.frame_count, cpu.bus.ppu.scanline, cpu.bus.ppu.cycle), (0, 100, 1));
+
+        // Once cleared it stops at the same line a frame later, not on the next one
+        cpu.breakpoint_hit = None;
+        assert!(!cpu.run_until(|_| false));
+        assert_eq!((cpu.bus.ppu.frame_count, cpu.bus.ppu.scanline, cpu.bus.ppu.cycle), (1, 100, 1));
+    }
+
+    #[test]
+    fn cpu_load_state_rolls_back_truncated_state() {
+        let mut cpu = test_cpu(0);
+        cpu.accumulator = 0x12;
+        cpu.bus.mem_write(0x0010, 0x34);
+        let mut state = cpu.save_state();
+        state.data.truncate(state.data.len() - 1);
+
+        cpu.accumulator = 0x56;
+        cpu.bus.mem_write(0x0010, 0x78);
+        assert!(cpu.load_state(&state).is_err());
+        assert_eq!(cpu.accumulator, 0x56);
+        assert_eq!(cpu.bus.mem_read(0x0010), 0x78);
+    }
+
+    #[test]
+    fn cpu_load_state_rejects_other_rom() {
+        let mut source = program_cpu(&[0xA9, 0x01]);
+        source.bus.mem_write(0x0010, 0x34);
+        let state = source.save_state();
+
+        let mut cpu = program_cpu(&[0xA9, 0x02]);
+        assert_ne!(cpu.bus.cartridge.crc32, source.bus.cartridge.crc32);
+        let error = cpu.load_state(&state).unwrap_err();
+        assert!(error.starts_with("Save state is for ROM"), "{}", error);
+        assert_eq!(cpu.bus.mem_read(0x0010), 0x00);
+
+        cpu.ignore_state_rom_mismatch = true;
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.bus.mem_read(0x0010), 0x34);
+    }
+
+    #[test]
+    fn cpu_load_state_rolls_back_mapper_mismatch() {
+        let mut source = test_cpu(0);
+        source.accumulator = 0x12;
+        source.bus.mem_write(0x0010, 0x34);
+        let state = source.save_state();
+
+        let mut cpu = test_cpu(7);
+        cpu.ignore_state_rom_mismatch = true;
+        cpu.accumulator = 0x56;
+        cpu.bus.mem_write(0x0010, 0x78);
+        let error = cpu.load_state(&state).unwrap_err();
+        assert!(error.contains("mapper"), "{}", error);
+        assert_eq!(cpu.accumulator, 0x56);
+        assert_eq!(cpu.bus.mem_read(0x0010), 0x78);
+    }
+
+    // Cycles from the start of STA $4014 until the CPU is free again, with the tick count chosen so
+    // the DMC's second fetch lands ~300 cycles into the OAM DMA
+    fn oam_dma_instruction_cycles(dmc_enabled: bool) -> u64 {
+        let mut prg = vec![0xEA; 0x8000];
+        prg[0..3].copy_from_slice(&[0x8D, 0x14, 0x40]);
+        let mut cpu = CPU::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal));
+        if dmc_enabled {
+            // Slowest rate and a 17 byte sample at $C000. The first byte is fetched straight away,
+            // the next one 8 output clocks of 428 cycles later.
+            cpu.bus.mem_write(0x4010, 0x00);
+            cpu.bus.mem_write(0x4012, 0x00);
+            cpu.bus.mem_write(0x4013, 0x01);
+            cpu.bus.mem_write(0x4015, 0x10);
+        }
+        for _ in 0..8 * 428 - 300 {
+            cpu.bus.tick(0);
+        }
+        cpu.bus.take_dma_stall();
+
+        cpu.program_counter = 0x8000;
+        let start = cpu.bus.cpu_cycle_count;
+        loop {
+            cpu.clock();
+            if cpu.complete() {
+                break;
+            }
+        }
+        cpu.bus.cpu_cycle_count - start
+    }
+
+    #[test]
+    fn cpu_dmc_fetch_during_oam_dma_adds_two_cycles() {
+        // STA absolute is 4 cycles, the OAM DMA 513 plus one to align on an odd cycle
+        let without_dmc = oam_dma_instruction_cycles(false);
+        assert!(without_dmc == 517 || without_dmc == 518, "{}", without_dmc);
+        assert_eq!(oam_dma_instruction_cycles(true), without_dmc + 2);
+    }
+
+    #[test]
+    fn cpu_jam_halts_until_reset() {
+        let mut cpu = program_cpu(&[0xEA, 0x02, 0xEA]);
+        cpu.run_until(|cpu| cpu.halted);
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        // The PPU keeps running while the CPU is stuck
+        let position = cpu.bus.ppu.position();
+        for _ in 0..1000 {
+            cpu.clock();
+        }
+        assert!(cpu.halted);
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_ne!(cpu.bus.ppu.position(), position);
+
+        cpu.reset();
+        assert!(!cpu.halted);
+    }
+
+    // PPU clocks from one completed frame to the next
+    fn ppu_clocks_per_frame(cpu: &mut CPU) -> u32 {
+        while !cpu.bus.ppu.poll_frame_complete() {
+            cpu.clock();
+        }
+        let mut clocks = 0;
+        while !cpu.bus.ppu.poll_frame_complete() {
+            cpu.clock();
+            clocks += 1;
+        }
+        clocks
+    }
+
+    // iNES image with byte 9 flagging a PAL ROM
+    fn pal_cartridge() -> Cartridge {
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x00, 0x00, 0x00, 0x01];
+        image.resize(16 + 0x4000 + 0x2000, 0xEA);
+        Cartridge::from_bytes(image, false, Overrides::default()).unwrap()
+    }
+
+    #[test]
+    fn cpu_region_override_wins_over_header() {
+        let cartridge = pal_cartridge();
+        assert_eq!(cartridge.region, Region::Pal);
+
+        let mut cpu = CPU::new(cartridge);
+        cpu.bus.set_region(Region::Ntsc);
+        assert_eq!(ppu_clocks_per_frame(&mut cpu), 262 * 341);
+    }
+
+    fn write_chr(cpu: &mut CPU, data: &[u8]) {
+        cpu.bus.mem_write(0x2006, 0x00);
+        cpu.bus.mem_write(0x2006, 0x00);
+        for &byte in data {
+            cpu.bus.mem_write(0x2007, byte);
+        }
+    }
+
+    #[test]
+    fn cpu_save_state_restores_chr_ram() {
+        let mut cpu = test_cpu(0);
+        cpu.bus.ppu.warm_up_enabled = false;
+        cpu.bus.ppu.reset();
+        let tiles: Vec<u8> = (0..64).map(|i| i * 3 + 1).collect();
+        write_chr(&mut cpu, &tiles);
+        let state = cpu.save_state();
+
+        write_chr(&mut cpu, &[0; 64]);
+        assert_eq!(cpu.bus.mapper.borrow_mut().ppu_read(0x0000), Some(0));
+        cpu.load_state(&state).unwrap();
+        let restored: Vec<u8> = (0..64).map(|addr| cpu.bus.mapper.borrow_mut().ppu_read(addr).unwrap()).collect();
+        assert_eq!(restored, tiles);
+    }
+
+    #[test]
+    fn cpu_pal_frame_has_312_scanlines() {
+        let mut cpu = CPU::new(pal_cartridge());
+
+        let mut scanlines = HashSet::new();
+        while !cpu.bus.ppu.poll_frame_complete() {
+            cpu.clock();
+        }
+        while !cpu.bus.ppu.poll_frame_complete() {
+            cpu.clock();
+            scanlines.insert(cpu.bus.ppu.scanline);
+        }
+        assert_eq!(scanlines.len(), 312);
+
+        let clocks = ppu_clocks_per_frame(&mut cpu);
+        assert_eq!(clocks, 312 * 341);
+        let frame_rate = Region::Pal.ppu_clock_hz() / clocks as f64;
+        assert!((frame_rate - 50.007).abs() < 0.001, "{}", frame_rate);
+    }
+
+    #[test]
+    fn cpu_step_instruction_gives_up_at_the_cap() {
+        let mut cpu = program_cpu(&[0xEA, 0xEA, 0xEA]);
+        for pc in [0x8001, 0x8002, 0x8003] {
+            assert!(cpu.step_instruction());
+            assert_eq!(cpu.program_counter, pc);
+        }
+
+        // An instruction that never completes: a halted CPU no longer counts its cycles down
+        cpu.halted = true;
+        cpu.cycles = 1;
+        let start = cpu.system_clock_counter;
+        assert!(!cpu.step_instruction());
+        assert_eq!(cpu.system_clock_counter - start, MAX_STEP_CLOCKS);
+    }
+
+    #[test]
+    fn cpu_frozen_address_survives_program_writes() {
+        // LDA #$09, STA $75, INC $75, STA $0875 (a mirror of $0075)
+        let mut cpu = program_cpu(&[0xA9, 0x09, 0x85, 0x75, 0xE6, 0x75, 0x8D, 0x75, 0x08]);
+        assert!(cpu.bus.freeze(0x0075, 0x03));
+        assert!(!cpu.bus.freeze(0x8000, 0x03));
+        for _ in 0..4 {
+            assert!(cpu.step_instruction());
+            assert_eq!(cpu.bus.mem_read(0x0075), 0x03);
+        }
+
+        cpu.bus.thaw(0x0075);
+        cpu.program_counter = 0x8002;
+        cpu.step_instruction();
+        assert_eq!(cpu.bus.mem_read(0x0075), 0x09);
+    }
+
+    // Board that serves PRG from $8000 and logs every write to its registers
+    struct RecordingMapper {
+        prg: Vec<u8>,
+        writes: Rc<RefCell<Vec<(u16, u8)>>>,
+    }
+
+    impl Mapper for RecordingMapper {
+        fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+            (addr >= 0x8000).then(|| self.prg[(addr & 0x7FFF) as usize])
+        }
+        fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+            if addr < 0x8000 {
+                return false;
+            }
+            self.writes.borrow_mut().push((addr, data));
+            true
+        }
+        fn ppu_read(&mut self, _addr: u16) -> Option<u8> {
+            None
+        }
+        fn ppu_write(&mut self, _addr: u16, _data: u8) -> bool {
+            false
+        }
+        fn mirroring(&self) -> Option<Mirroring> {
+            None
+        }
+        fn irq_pending(&mut self) -> bool {
+            false
+        }
+        fn save_state(&self, _writer: &mut StateWriter) {}
+        fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), String> {
+            Ok(())
+        }
+        fn debug_banks(&self) -> MapperDebugInfo {
+            MapperDebugInfo {
+                cpu_windows: [BankWindow::OPEN_BUS; 4],
+                ppu_windows: [BankWindow::OPEN_BUS; 8],
+                registers: Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_rmw_writes_mapper_register_twice() {
+        // INC $8000 reads its own opcode byte back as the operand
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..3].copy_from_slice(&[0xEE, 0x00, 0x80]);
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let mapper = RecordingMapper { prg: prg.clone(), writes: writes.clone() };
+        let mut cpu = test_cpu(0);
+        cpu.bus = Bus::with_mapper(mapper, prg, Vec::new());
+        cpu.program_counter = 0x8000;
+
+        assert!(cpu.step_instruction());
+        // The unmodified value goes back out first, then the result
+        assert_eq!(*writes.borrow(), [(0x8000, 0xEE), (0x8000, 0xEF)]);
+    }
+
+    #[test]
+    fn cpu_step_out_returns_past_isr() {
+        // JSR $8010; NOP. The subroutine hits a BRK whose handler at $8020 returns with RTI before its RTS.
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..3].copy_from_slice(&[0x20, 0x10, 0x80]);
+        prg[0x10..0x13].copy_from_slice(&[0x00, 0xEA, 0x60]);
+        prg[0x20] = 0x40;
+        prg[0x7FFE..].copy_from_slice(&[0x20, 0x80]);
+        let mut cpu = CPU::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal));
+        cpu.program_counter = 0x8000;
+        cpu.stack_pointer = 0xFD;
+
+        assert!(cpu.step_instruction());
+        assert_eq!(cpu.program_counter, 0x8010);
+        assert!(cpu.step_out());
+        assert_eq!(cpu.program_counter, 0x8003);
+        assert_eq!(cpu.stack_pointer, 0xFD);
+    }
+
+    // Status byte the interrupt pushed, once the handler at $FF00 is reached
+    fn pushed_interrupt_status(program: &[u8]) -> u8 {
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7FFE..].copy_from_slice(&[0x00, 0xFF]);
+        let mut cpu = CPU::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal));
+        cpu.program_counter = 0x8000;
+        cpu.stack_pointer = 0xFD;
+
+        assert!(cpu.run_until(|cpu| cpu.program_counter == 0xFF00));
+        assert_eq!(cpu.stack_pointer, 0xFA);
+        cpu.bus.mem_read(0x0100 + cpu.stack_pointer as u16 + 1)
+    }
+
+    #[test]
+    fn cpu_pushed_break_bit_tells_brk_from_irq() {
+        assert_eq!(pushed_interrupt_status(&[0x00]) & 0x30, 0x30);
+        // CLI, then NOPs until the APU frame counter raises its IRQ
+        assert_eq!(pushed_interrupt_status(&[0x58]) & 0x30, 0x20);
+    }
+
+    // NROM-128 iNES file with CHR RAM, NOPs everywhere and the given reset vector
+    fn nrom_file(reset_vector: u16) -> Vec<u8> {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[0x3FFC..0x3FFE].copy_from_slice(&reset_vector.to_le_bytes());
+        [vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00], vec![0x00; 10], prg].concat()
+    }
+
+    #[test]
+    fn cpu_hot_reload_picks_up_new_reset_vector() {
+        let path = std::env::temp_dir().join(format!("runes-reload-{}.nes", std::process::id()));
+        let path_name = path.to_string_lossy().into_owned();
+        std::fs::write(&path, nrom_file(0x8000)).unwrap();
+        let mut cpu = CPU::new(Cartridge::new(&path_name).unwrap());
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+        cpu.bus.mem_write(0x0010, 0x42);
+
+        // Rebuilt with the entry point moved
+        std::fs::write(&path, nrom_file(0x8100)).unwrap();
+        let rebuilt = Cartridge::new(&path_name).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(rebuilt.same_layout(&cpu.bus.cartridge));
+        // Growing to 32KB of PRG needs a power cycle instead
+        let grown = Cartridge::from_parts(vec![0xEA; 0x8000], Vec::new(), 0, Mirroring::Horizontal);
+        assert!(!grown.same_layout(&cpu.bus.cartridge));
+
+        let state = cpu.save_state();
+        let mut cpu = CPU::new(rebuilt);
+        assert!(cpu.load_state(&state).is_err());
+        cpu.load_state_from_previous_build(&state).unwrap();
+        assert!(!cpu.ignore_state_rom_mismatch);
+        assert_eq!(cpu.bus.mem_read(0x0010), 0x42);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8100);
+    }
+
+    #[test]
+    fn cpu_frame_skip_matches_full_rendering() {
+        // Solid background and a solid sprite zero at (100, 100), then every frame the loop
+        // counts iterations until the hit and stores the low byte at $0200 + frame
+        let source = "sei / lda #$40 / sta $4017
+            bit $2002 / bpl $8006 / bit $2002 / bpl $800B
+            lda #$3F / sta $2006 / lda #$00 / sta $2006 / lda #$0F / sta $2007 / lda #$30 / sta $2007
+            lda #$3F / sta $2006 / lda #$11 / sta $2006 / lda #$16 / sta $2007
+            lda #$00 / sta $2006 / lda #$10 / sta $2006 / ldx #8 / lda #$FF
+            sta $2007 / dex / bne $8041
+            lda #$20 / sta $2006 / lda #$00 / sta $2006 / lda #1 / ldy #4 / ldx #0
+            sta $2007 / dex / bne $8057 / dey / bne $8057
+            lda #0 / sta $2003 / lda #99 / sta $2004 / lda #1 / sta $2004 / lda #0 / sta $2004 / lda #100 / sta $2004
+            lda #0 / sta $2005 / sta $2005 / sta $2000 / lda #$1E / sta $2001
+            bit $2002 / bvs $8089
+            ldx #0 / ldy #0
+            inx / bne $8096 / iny / bit $2002 / bvc $8092
+            stx $10 / sty $11 / ldy $12 / txa / sta $0200,y / inc $12 / jmp $8089";
+        let program = crate::assembler::assemble(source, 0x8000).unwrap();
+
+        let run = |render_enabled: bool| {
+            let mut cpu = program_cpu(&program);
+            cpu.bus.ppu.render_enabled = render_enabled;
+            for frame in 1..=10 {
+                assert!(cpu.run_until(|cpu| cpu.bus.ppu.frame_count == frame));
+            }
+            cpu
+        };
+        let rendered = run(true);
+        let skipped = run(false);
+
+        assert!(rendered.bus.cpu_vram[0x12] >= 5);
+        assert_eq!(rendered.bus.cpu_vram, skipped.bus.cpu_vram);
+        assert_eq!(rendered.bus.ppu.status_register, skipped.bus.ppu.status_register);
+    }
+}
@@ -0,0 +1,20 @@
+pub mod apu;
+#[cfg(test)]
+pub mod assembler;
+pub mod battery;
+pub mod cpu;
+pub mod ppu;
+pub mod bus;
+pub mod opcodes;
+pub mod png;
+pub mod ram_search;
+pub mod ui;
+pub mod cartridge;
+pub mod controller;
+pub mod hash;
+pub mod mapper;
+pub mod recorder;
+pub mod romdb;
+pub mod savestate;
+pub mod test_rom;
+pub mod trace;
@@ -1,16 +1,12 @@
-pub mod cpu;
-pub mod ppu;
-pub mod bus;
-pub mod opcodes;
-pub mod ui;
-pub mod cartridge;
-pub mod renderer;
-
-use cpu::CPU;
-use ui::ui;
-use cartridge::Cartridge;
+use runes::cpu::CPU;
+use runes::ui::ui;
+use runes::cartridge::{Cartridge, Mirroring, Overrides, Region};
+use runes::test_rom::{run_test_rom, TestRomResult};
 
 use std::env;
+use std::path::Path;
+
+const TEST_ROM_TIMEOUT_SECONDS: f64 = 120.0;
 
 fn main() {
     #[cfg(target_os = "linux")]
@@ -24,14 +20,119 @@ fn main() {
         }
     }
 
-    let args: Vec<String> = env::args().collect();
+    let mut cartridge_path = None;
+    let mut region_override = None;
+    let mut sprite_limit = true;
+    let mut test_mode = false;
+    let mut ignore_size_mismatch = false;
+    let mut ignore_state_rom_mismatch = false;
+    let mut overrides = Overrides::default();
+    let mut save_directory = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--region" => match args.next().map(|value| value.parse::<Region>()) {
+                Some(Ok(region)) => region_override = Some(region),
+                _ => {
+                    eprintln!("Expected --region ntsc|pal|dendy");
+                    return;
+                }
+            },
+            "--mapper" => match args.next().map(|value| value.parse::<u8>()) {
+                Some(Ok(mapper)) => overrides.mapper = Some(mapper),
+                _ => {
+                    eprintln!("Expected --mapper <number>");
+                    return;
+                }
+            },
+            "--mirroring" => match args.next().map(|value| value.parse::<Mirroring>()) {
+                Some(Ok(mirroring)) => overrides.mirroring = Some(mirroring),
+                _ => {
+                    eprintln!("Expected --mirroring horizontal|vertical|fourscreen|onescreenlower|onescreenupper");
+                    return;
+                }
+            },
+            "--prg-ram" => match args.next().map(|value| value.parse::<u32>()) {
+                Some(Ok(kb)) => overrides.prg_ram_kb = Some(kb),
+                _ => {
+                    eprintln!("Expected --prg-ram <size in KB>");
+                    return;
+                }
+            },
+            "--save-dir" => match args.next() {
+                Some(directory) => save_directory = Some(directory),
+                None => {
+                    eprintln!("Expected --save-dir <directory>");
+                    return;
+                }
+            },
+            "--no-sprite-limit" => sprite_limit = false,
+            "--test" => test_mode = true,
+            "--ignore-size-mismatch" => ignore_size_mismatch = true,
+            "--ignore-state-rom" => ignore_state_rom_mismatch = true,
+            _ => cartridge_path = Some(arg),
+        }
+    }
 
-    if args.len() < 2 {
-        eprintln!("Usage: runes <path-to-rom>");
+    let Some(cartridge_path) = cartridge_path else {
+        eprintln!("Usage: runes [--region ntsc|pal|dendy] [--no-sprite-limit] [--mapper n] [--mirroring horizontal|vertical|fourscreen|onescreenlower|onescreenupper] [--prg-ram kb] [--save-dir dir] [--test] [--ignore-size-mismatch] [--ignore-state-rom] <path-to-rom>");
         return;
+    };
+
+    // The UI still opens for a ROM that can't be loaded, showing why in place of the picture,
+    // so another ROM can be dropped in. The error is printed either way and the process exits
+    // nonzero: right away for headless test runs, once the window is closed otherwise.
+    let (mut cartridge, load_error) = match Cartridge::load(&cartridge_path, ignore_size_mismatch, overrides) {
+        Ok(cartridge) => (cartridge, None),
+        Err(e) if test_mode => {
+            eprintln!("Failed to load {}: {}", cartridge_path, e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", cartridge_path, e);
+            let placeholder = Cartridge::from_parts(vec![0; 0x4000], Vec::new(), 0, Mirroring::Horizontal);
+            (placeholder, Some(format!("Failed to load {}: {}", cartridge_path, e)))
+        }
+    };
+
+    if let Some(directory) = save_directory {
+        cartridge.save_directory = directory.into();
     }
 
-    let cartridge_path = &args[1];
-    let cpu = CPU::new(Cartridge::new(cartridge_path).unwrap());
-    ui(cpu).unwrap();
+    let mut cpu = CPU::new(cartridge);
+    // A manual override always wins over the region detected from the header
+    if let Some(region) = region_override {
+        cpu.bus.set_region(region);
+    }
+    cpu.bus.ppu.sprite_limit_enabled = sprite_limit;
+    cpu.ignore_state_rom_mismatch = ignore_state_rom_mismatch;
+
+    // Headless test ROM run, the result from $6000 becomes the exit code
+    if test_mode {
+        let max_frames = (TEST_ROM_TIMEOUT_SECONDS * cpu.bus.region.frame_rate()) as u32;
+        match run_test_rom(&mut cpu, max_frames) {
+            TestRomResult::Passed(message) => {
+                println!("Passed\n{}", message);
+            }
+            TestRomResult::Failed(status, message) => {
+                println!("Failed with status {:02X}\n{}", status, message);
+                std::process::exit(1);
+            }
+            TestRomResult::TimedOut => {
+                println!("Timed out after {} seconds of emulated time", TEST_ROM_TIMEOUT_SECONDS);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let rom_name = Path::new(&cartridge_path)
+        .file_name()
+        .map_or_else(|| cartridge_path.clone(), |name| name.to_string_lossy().into_owned());
+    let failed_to_load = load_error.is_some();
+    ui(cpu, rom_name, load_error).unwrap();
+    if failed_to_load {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,2450 @@
+use crate::cartridge::Mirroring;
+use crate::savestate::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// Shared between the bus (CPU side) and the PPU (pattern table side)
+pub type MapperRef = Rc<RefCell<dyn Mapper>>;
+
+pub trait Mapper {
+    // Cartridge space on the CPU bus ($4020-$FFFF). None means nothing drives the bus.
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+    // cpu_read without side effects on the board, for the debugger's memory views
+    fn peek(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_read(addr)
+    }
+    // Returns whether the mapper took the write (bank registers, PRG RAM)
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool;
+
+    // Pattern table access ($0000-$1FFF). None leaves the PPU bus floating.
+    fn ppu_read(&mut self, addr: u16) -> Option<u8>;
+    // Returns whether the write landed in CHR RAM
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool;
+    // Sprite pattern fetches, for boards that bank sprites apart from the background (MMC5)
+    fn ppu_read_sprite(&mut self, addr: u16) -> Option<u8> {
+        self.ppu_read(addr)
+    }
+
+    // Nametable access ($2000-$2FFF) for boards that map nametables themselves, with the console's
+    // CIRAM to map from. None/false leaves it to the PPU's mirroring.
+    fn read_nametable(&mut self, _addr: u16, _ciram: &[u8]) -> Option<u8> {
+        None
+    }
+    fn write_nametable(&mut self, _addr: u16, _data: u8, _ciram: &mut [u8]) -> bool {
+        false
+    }
+
+    // Mirroring selected by the mapper, None keeps the one from the header
+    fn mirroring(&self) -> Option<Mirroring>;
+    // Level of the mapper's IRQ line
+    fn irq_pending(&mut self) -> bool;
+    // Filtered rising edge of PPU A12, roughly once per scanline while rendering
+    fn notify_a12(&mut self) {}
+    // Called once per CPU cycle, for mappers with a cycle counter (FME-7)
+    fn cpu_clock(&mut self) {}
+    // Start of every PPU line, rendering is set on visible lines while the PPU fetches.
+    // For mappers that count lines by watching the fetches (MMC5).
+    fn notify_scanline(&mut self, _rendering: bool) {}
+    // $2000 writes, snooped by boards that need to know the sprite size
+    fn notify_ppu_control(&mut self, _data: u8) {}
+
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String>;
+
+    // PRG RAM that a battery keeps alive between sessions, for boards that have it
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn load_battery_ram(&mut self, _data: &[u8]) {}
+
+    // Current bank layout and registers, for the mapper inspector
+    fn debug_banks(&self) -> MapperDebugInfo;
+}
+
+// Where a CPU or PPU window currently reads from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BankSource {
+    Rom,
+    Ram,
+    OpenBus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BankWindow {
+    pub source: BankSource,
+    pub bank: usize,   // In units of the window size
+    pub offset: usize, // Byte offset of the window's first byte into PRG or CHR
+}
+
+impl BankWindow {
+    pub const OPEN_BUS: BankWindow = BankWindow { source: BankSource::OpenBus, bank: 0, offset: 0 };
+}
+
+impl std::fmt::Display for BankWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source {
+            BankSource::Rom => write!(f, "ROM bank {:3} (${:06X})", self.bank, self.offset),
+            BankSource::Ram => write!(f, "RAM bank {:3} (${:06X})", self.bank, self.offset),
+            BankSource::OpenBus => write!(f, "open bus"),
+        }
+    }
+}
+
+// Bank layout of a board: 8KB CPU windows at $8000/$A000/$C000/$E000, 1KB PPU windows over
+// $0000-$1FFF and the board's own registers as (name, value) pairs
+#[derive(Debug, Clone)]
+pub struct MapperDebugInfo {
+    pub cpu_windows: [BankWindow; 4],
+    pub ppu_windows: [BankWindow; 8],
+    pub registers: Vec<(&'static str, String)>,
+}
+
+impl MapperDebugInfo {
+    // PRG ROM offset behind a CPU address, for disassembly listings and code/data logs
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let window = self.cpu_windows[((addr - 0x8000) / 0x2000) as usize];
+        (window.source == BankSource::Rom).then(|| window.offset + (addr & 0x1FFF) as usize)
+    }
+}
+
+// Windows are resolved through the board's own address translation, so what the inspector shows
+// is what the CPU and PPU actually read
+fn prg_windows(prg: &[u8], prg_index: impl Fn(u16) -> usize) -> [BankWindow; 4] {
+    std::array::from_fn(|window| {
+        if prg.is_empty() {
+            return BankWindow::OPEN_BUS;
+        }
+        let offset = prg_index(0x8000 + window as u16 * 0x2000);
+        BankWindow { source: BankSource::Rom, bank: offset / 0x2000, offset }
+    })
+}
+
+fn chr_windows(chr_is_ram: bool, chr_index: impl Fn(u16) -> Option<usize>) -> [BankWindow; 8] {
+    let source = if chr_is_ram { BankSource::Ram } else { BankSource::Rom };
+    std::array::from_fn(|window| match chr_index(window as u16 * 0x0400) {
+        Some(offset) => BankWindow { source, bank: offset / 0x0400, offset },
+        None => BankWindow::OPEN_BUS,
+    })
+}
+
+// NROM, 16KB or 32KB of fixed PRG and a single fixed 8KB CHR bank (ROM or RAM).
+// PRG RAM at $6000 is sized from the header, like Family Basic, test ROMs report through it.
+pub struct Mapper0 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+}
+
+impl Mapper0 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, prg_ram_size: usize) -> Mapper0 {
+        Mapper0 { prg, chr, chr_is_ram, prg_ram: vec![0; prg_ram_size] }
+    }
+
+    // PRG RAM smaller than 8KB repeats across $6000-$7FFF, a board without any leaves it open bus
+    fn prg_ram_index(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.prg_ram.len())
+    }
+
+    // CHR smaller than 8KB repeats across the pattern tables, like the unconnected address lines do
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper0 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram_index(addr).map(|index| self.prg_ram[index]),
+            // NROM-128 mirrors its 16KB bank into $C000-$FFFF
+            0x8000..=0xFFFF if !self.prg.is_empty() => Some(self.prg[(addr - 0x8000) as usize % self.prg.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !(0x6000..=0x7FFF).contains(&addr) {
+            return false;
+        }
+        match self.prg_ram_index(addr) {
+            Some(index) => {
+                self.prg_ram[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| (addr - 0x8000) as usize % self.prg.len()),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers: Vec::new(),
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        // CHR ROM can't change, only CHR RAM has to be captured
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// MMC3-style bank select ($8000) and bank data ($8001) registers, shared by MMC3 and its
+// simpler predecessor DxROM (mapper 206)
+pub struct Mmc3Banks {
+    pub bank_select: u8,
+    pub registers: [u8; 8], // R0-R5 CHR banks, R6-R7 PRG banks
+}
+
+impl Default for Mmc3Banks {
+    fn default() -> Mmc3Banks {
+        Mmc3Banks { bank_select: 0, registers: [0, 2, 4, 5, 6, 7, 0, 1] }
+    }
+}
+
+impl Mmc3Banks {
+    // Even/odd register pair at $8000-$9FFF
+    pub fn write(&mut self, addr: u16, data: u8) {
+        if addr & 0x0001 == 0 {
+            self.bank_select = data;
+        } else {
+            self.registers[(self.bank_select & 0x07) as usize] = data;
+        }
+    }
+
+    pub fn prg_index(&self, prg_len: usize, addr: u16) -> usize {
+        let bank_count = (prg_len / 0x2000).max(1);
+        let second_last = bank_count.saturating_sub(2);
+        let swap_low_bank = self.bank_select & 0x40 != 0;
+
+        // $8000 and $C000 trade places with the second to last bank depending on the PRG mode
+        let bank = match (addr - 0x8000) / 0x2000 {
+            0 if swap_low_bank => second_last,
+            0 => (self.registers[6] & 0x3F) as usize,
+            1 => (self.registers[7] & 0x3F) as usize,
+            2 if swap_low_bank => (self.registers[6] & 0x3F) as usize,
+            2 => second_last,
+            _ => bank_count - 1,
+        };
+
+        // PRG smaller than one bank (raw test code) mirrors inside it instead of running off the end
+        ((bank % bank_count) * 0x2000 + (addr & 0x1FFF) as usize) % prg_len
+    }
+
+    pub fn chr_index(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        if chr_len == 0 {
+            return None;
+        }
+
+        // CHR inversion swaps the 2KB and 1KB halves of the pattern tables
+        let addr = (if self.bank_select & 0x80 != 0 { addr ^ 0x1000 } else { addr }) & 0x1FFF;
+        let bank = match addr / 0x0400 {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 0x01,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 0x01,
+            slot => self.registers[slot as usize - 2],
+        };
+
+        Some((bank as usize * 0x0400 + (addr & 0x03FF) as usize) % chr_len)
+    }
+
+    pub fn debug_registers(&self) -> Vec<(&'static str, String)> {
+        let registers: Vec<String> = self.registers.iter().map(|register| format!("{:02X}", register)).collect();
+        vec![
+            ("Bank select", format!("${:02X}", self.bank_select)),
+            ("PRG mode", ((self.bank_select >> 6) & 1).to_string()),
+            ("CHR inversion", (self.bank_select >> 7).to_string()),
+            ("R0-R7", registers.join(" ")),
+        ]
+    }
+}
+
+// MMC3 (TxROM): two switchable 8KB PRG banks, 2KB/1KB CHR banks and a scanline counter
+// clocked by rising edges of PPU A12
+pub struct Mapper4 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+
+    banks: Mmc3Banks,
+    mirroring: Mirroring,
+    four_screen: bool, // Hardwired four-screen VRAM ignores $A000
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mapper4 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, mirroring: Mirroring, prg_ram_size: usize) -> Mapper4 {
+        Mapper4 {
+            prg,
+            chr,
+            chr_is_ram,
+            prg_ram: vec![0; prg_ram_size],
+
+            banks: Mmc3Banks::default(),
+            mirroring,
+            four_screen: mirroring == Mirroring::FourScreen,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        self.banks.prg_index(self.prg.len(), addr)
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        self.banks.chr_index(self.chr.len(), addr)
+    }
+
+    // PRG RAM smaller than 8KB repeats across $6000-$7FFF, a board without any leaves it open bus
+    fn prg_ram_index(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.prg_ram.len())
+    }
+}
+
+impl Mapper for Mapper4 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram_index(addr).map(|index| self.prg_ram[index]),
+            0x8000..=0xFFFF if !self.prg.is_empty() => Some(self.prg[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            0x6000..=0x7FFF => {
+                let Some(index) = self.prg_ram_index(addr) else {
+                    return false;
+                };
+                if !self.prg_ram_enabled || self.prg_ram_write_protect {
+                    return false;
+                }
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xFFFF => match addr & 0xE001 {
+                0x8000 | 0x8001 => self.banks.write(addr, data),
+                0xA000 => {
+                    self.mirroring = if data & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+                }
+                0xA001 => {
+                    self.prg_ram_enabled = data & 0x80 != 0;
+                    self.prg_ram_write_protect = data & 0x40 != 0;
+                }
+                0xC000 => self.irq_latch = data,
+                0xC001 => {
+                    self.irq_counter = 0;
+                    self.irq_reload = true;
+                }
+                0xE000 => {
+                    self.irq_enabled = false;
+                    self.irq_pending = false;
+                }
+                _ => self.irq_enabled = true,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if self.four_screen {
+            None
+        } else {
+            Some(self.mirroring)
+        }
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    // The counter reloads when it's 0 or a reload was requested, otherwise counts down.
+    // Reaching 0 with IRQs enabled raises the IRQ until $E000 acknowledges it.
+    fn notify_a12(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        let mut registers = self.banks.debug_registers();
+        registers.extend([
+            ("Mirroring", if self.four_screen { Mirroring::FourScreen } else { self.mirroring }.to_string()),
+            ("PRG RAM enabled", self.prg_ram_enabled.to_string()),
+            ("PRG RAM write protect", self.prg_ram_write_protect.to_string()),
+            ("IRQ latch", self.irq_latch.to_string()),
+            ("IRQ counter", self.irq_counter.to_string()),
+            ("IRQ reload", self.irq_reload.to_string()),
+            ("IRQ enabled", self.irq_enabled.to_string()),
+            ("IRQ pending", self.irq_pending.to_string()),
+        ]);
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers,
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.banks.bank_select);
+        writer.write_bytes(&self.banks.registers);
+        writer.write_bool(self.mirroring == Mirroring::Horizontal);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_bool(self.prg_ram_write_protect);
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.banks.bank_select = reader.read_u8()?;
+        reader.read_into(&mut self.banks.registers)?;
+        self.mirroring = if reader.read_bool()? { Mirroring::Horizontal } else { Mirroring::Vertical };
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.prg_ram_write_protect = reader.read_bool()?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// MMC5 (ExROM), the subset Castlevania III and Just Breed rely on: the four PRG modes with RAM in
+// any window below $E000, CHR banking with separate sprite (A) and background (B) sets for 8x16
+// sprites, ExRAM as a nametable or extended attributes, fill mode, the scanline IRQ and the
+// multiplier. Expansion audio and the vertical split are reported once and otherwise ignored.
+pub struct Mapper5 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    exram: Vec<u8>,
+
+    prg_mode: u8,             // $5100: 32KB, 16KB+16KB, 16KB+8KB+8KB or four 8KB banks
+    chr_mode: u8,             // $5101: 8KB, 4KB, 2KB or 1KB banks
+    prg_ram_protect: [u8; 2], // $5102/$5103, PRG RAM only takes writes with 2 and 1 written
+    exram_mode: u8,           // $5104: nametable, extended attributes, CPU RAM, CPU ROM
+    nametable_mapping: u8,    // $5105, two bits per nametable: CIRAM page 0/1, ExRAM, fill
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5],      // $5113-$5117, bit 7 picks ROM over RAM
+    chr_banks_a: [u16; 8],   // $5120-$5127, sprites in 8x16 mode
+    chr_banks_b: [u16; 4],   // $5128-$512B, background in 8x16 mode
+    chr_upper: u8,           // $5130, bits 8-9 for the next CHR bank write
+    last_chr_set_b: bool,    // 8x8 sprites and $2007 use whichever set was written last
+    sprites_8x16: bool,      // Snooped from $2000
+
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+    multiplicand: u8,
+    multiplier: u8,
+
+    extended_tile: usize, // Nametable offset of the last tile fetched, for extended attributes
+    reported: HashSet<&'static str>,
+}
+
+impl Mapper5 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, prg_ram_size: usize) -> Mapper5 {
+        Mapper5 {
+            prg,
+            chr,
+            chr_is_ram,
+            prg_ram: vec![0; prg_ram_size],
+            exram: vec![0; 0x400],
+
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            // The last bank is mapped at $E000 on power up, where the reset vector has to be
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_chr_set_b: false,
+            sprites_8x16: false,
+
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            extended_tile: 0,
+            reported: HashSet::new(),
+        }
+    }
+
+    // ROM or RAM offset behind a CPU address at $6000-$FFFF
+    fn prg_target(&self, addr: u16) -> (BankSource, usize) {
+        let (register, size, rom) = if addr < 0x8000 {
+            (self.prg_banks[0], 0x2000, false)
+        } else {
+            let (index, size) = match self.prg_mode {
+                0 => (4, 0x8000),
+                1 => (if addr < 0xC000 { 2 } else { 4 }, 0x4000),
+                2 => match addr {
+                    0x8000..=0xBFFF => (2, 0x4000),
+                    0xC000..=0xDFFF => (3, 0x2000),
+                    _ => (4, 0x2000),
+                },
+                _ => (1 + ((addr - 0x8000) / 0x2000) as usize, 0x2000),
+            };
+            // $5117 always maps ROM
+            (self.prg_banks[index], size, index == 4 || self.prg_banks[index] & 0x80 != 0)
+        };
+
+        // Bank numbers count 8KB units, larger windows ignore the low bits
+        let bank = (register & 0x7F) as usize & !(size / 0x2000 - 1);
+        let offset = bank * 0x2000 + (addr as usize & (size - 1));
+        match (rom, self.prg.is_empty(), self.prg_ram.is_empty()) {
+            (true, false, _) => (BankSource::Rom, offset % self.prg.len()),
+            (false, _, false) => (BankSource::Ram, offset % self.prg_ram.len()),
+            _ => (BankSource::OpenBus, 0),
+        }
+    }
+
+    fn chr_index(&self, addr: u16, sprite: bool) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        let addr = addr & 0x1FFF;
+
+        // Extended attributes pick a 4KB bank per background tile
+        if self.exram_mode == 1 && self.in_frame && !sprite {
+            let bank = (self.exram[self.extended_tile] & 0x3F) as usize | (self.chr_upper as usize) << 6;
+            return Some((bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr.len());
+        }
+
+        let background_set = if self.sprites_8x16 && self.in_frame { !sprite } else { self.last_chr_set_b };
+        let (bank, size) = if background_set {
+            // The four B registers cover $0000-$0FFF and repeat at $1000-$1FFF
+            let slot = ((addr & 0x0FFF) / 0x0400) as usize;
+            match self.chr_mode {
+                0 => (self.chr_banks_b[3], 0x2000),
+                1 => (self.chr_banks_b[3], 0x1000),
+                2 => (self.chr_banks_b[slot | 1], 0x0800),
+                _ => (self.chr_banks_b[slot], 0x0400),
+            }
+        } else {
+            let slot = (addr / 0x0400) as usize;
+            match self.chr_mode {
+                0 => (self.chr_banks_a[7], 0x2000),
+                1 => (self.chr_banks_a[slot | 3], 0x1000),
+                2 => (self.chr_banks_a[slot | 1], 0x0800),
+                _ => (self.chr_banks_a[slot], 0x0400),
+            }
+        };
+        Some((bank as usize * size + (addr as usize & (size - 1))) % self.chr.len())
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    // $5204: IRQ pending in bit 7, in-frame in bit 6
+    fn irq_status(&self) -> u8 {
+        (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+    }
+
+    fn report_unimplemented(&mut self, feature: &'static str) {
+        if self.reported.insert(feature) {
+            eprintln!("MMC5: {} is not emulated", feature);
+        }
+    }
+}
+
+impl Mapper for Mapper5 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            // Reading acknowledges the IRQ
+            0x5204 => {
+                let status = self.irq_status();
+                self.irq_pending = false;
+                Some(status)
+            }
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[(addr - 0x5C00) as usize]),
+            0x6000..=0xFFFF => match self.prg_target(addr) {
+                (BankSource::Rom, index) => Some(self.prg[index]),
+                (BankSource::Ram, index) => Some(self.prg_ram[index]),
+                (BankSource::OpenBus, _) => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn peek(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => Some(self.irq_status()),
+            _ => self.cpu_read(addr),
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            0x5000..=0x5015 => self.report_unimplemented("expansion audio"),
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 | 0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[(addr - 0x5120) as usize] = data as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_set_b = false;
+            }
+            0x5128..=0x512B => {
+                self.chr_banks_b[(addr - 0x5128) as usize] = data as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_set_b = true;
+            }
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 if data & 0x80 != 0 => self.report_unimplemented("the vertical split"),
+            0x5203 => self.irq_scanline = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[(addr - 0x5C00) as usize] = data,
+            0x6000..=0xFFFF if self.prg_ram_writable() => match self.prg_target(addr) {
+                (BankSource::Ram, index) => self.prg_ram[index] = data,
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr, false).map(|index| self.chr[index])
+    }
+
+    fn ppu_read_sprite(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr, true).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr, false) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8]) -> Option<u8> {
+        let offset = (addr & 0x03FF) as usize;
+        let attribute = offset >= 0x03C0;
+        if !attribute {
+            self.extended_tile = offset;
+        }
+
+        // Extended attributes give every tile its own palette from ExRAM
+        if attribute && self.exram_mode == 1 && self.in_frame {
+            return Some((self.exram[self.extended_tile] >> 6) * 0x55);
+        }
+
+        let table = (addr >> 10) & 0x03;
+        Some(match (self.nametable_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset],
+            1 => ciram[0x0400 + offset],
+            2 if self.exram_mode < 2 => self.exram[offset],
+            2 => 0,
+            _ if attribute => self.fill_attribute * 0x55,
+            _ => self.fill_tile,
+        })
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8, ciram: &mut [u8]) -> bool {
+        let offset = (addr & 0x03FF) as usize;
+        let table = (addr >> 10) & 0x03;
+        match (self.nametable_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset] = data,
+            1 => ciram[0x0400 + offset] = data,
+            2 if self.exram_mode < 2 => self.exram[offset] = data,
+            _ => {}
+        }
+        true
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    // The first rendered line puts the MMC5 in frame and restarts the counter, every line after
+    // it counts up and raises the IRQ on reaching $5203. The frame ends once the PPU stops fetching.
+    fn notify_scanline(&mut self, rendering: bool) {
+        if !rendering {
+            self.in_frame = false;
+            return;
+        }
+
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            self.irq_pending = false;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_scanline {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn notify_ppu_control(&mut self, data: u8) {
+        self.sprites_8x16 = data & 0x20 != 0;
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        let hex = |banks: &[u16]| banks.iter().map(|bank| format!("{:03X}", bank)).collect::<Vec<_>>().join(" ");
+        let prg_banks: Vec<String> = self.prg_banks.iter().map(|bank| format!("{:02X}", bank)).collect();
+        MapperDebugInfo {
+            cpu_windows: std::array::from_fn(|window| match self.prg_target(0x8000 + window as u16 * 0x2000) {
+                (BankSource::OpenBus, _) => BankWindow::OPEN_BUS,
+                (source, offset) => BankWindow { source, bank: offset / 0x2000, offset },
+            }),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr, false)),
+            registers: vec![
+                ("PRG mode", self.prg_mode.to_string()),
+                ("CHR mode", self.chr_mode.to_string()),
+                ("ExRAM mode", self.exram_mode.to_string()),
+                ("Nametables", format!("${:02X}", self.nametable_mapping)),
+                ("PRG banks", prg_banks.join(" ")),
+                ("CHR A", hex(&self.chr_banks_a)),
+                ("CHR B", hex(&self.chr_banks_b)),
+                ("8x16 sprites", self.sprites_8x16.to_string()),
+                ("IRQ scanline", self.irq_scanline.to_string()),
+                ("Scanline counter", self.scanline_counter.to_string()),
+                ("In frame", self.in_frame.to_string()),
+                ("IRQ enabled", self.irq_enabled.to_string()),
+                ("IRQ pending", self.irq_pending.to_string()),
+                ("Multiplier", format!("{} x {}", self.multiplicand, self.multiplier)),
+            ],
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_bytes(&self.exram);
+        writer.write_u8(self.prg_mode);
+        writer.write_u8(self.chr_mode);
+        writer.write_bytes(&self.prg_ram_protect);
+        writer.write_u8(self.exram_mode);
+        writer.write_u8(self.nametable_mapping);
+        writer.write_u8(self.fill_tile);
+        writer.write_u8(self.fill_attribute);
+        writer.write_bytes(&self.prg_banks);
+        for bank in self.chr_banks_a.iter().chain(&self.chr_banks_b) {
+            writer.write_u16(*bank);
+        }
+        writer.write_u8(self.chr_upper);
+        writer.write_bool(self.last_chr_set_b);
+        writer.write_bool(self.sprites_8x16);
+        writer.write_u8(self.irq_scanline);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_bool(self.in_frame);
+        writer.write_u8(self.scanline_counter);
+        writer.write_u8(self.multiplicand);
+        writer.write_u8(self.multiplier);
+        writer.write_u16(self.extended_tile as u16);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        reader.read_into(&mut self.exram)?;
+        self.prg_mode = reader.read_u8()?;
+        self.chr_mode = reader.read_u8()?;
+        reader.read_into(&mut self.prg_ram_protect)?;
+        self.exram_mode = reader.read_u8()?;
+        self.nametable_mapping = reader.read_u8()?;
+        self.fill_tile = reader.read_u8()?;
+        self.fill_attribute = reader.read_u8()?;
+        reader.read_into(&mut self.prg_banks)?;
+        for bank in self.chr_banks_a.iter_mut().chain(&mut self.chr_banks_b) {
+            *bank = reader.read_u16()?;
+        }
+        self.chr_upper = reader.read_u8()?;
+        self.last_chr_set_b = reader.read_bool()?;
+        self.sprites_8x16 = reader.read_bool()?;
+        self.irq_scanline = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.in_frame = reader.read_bool()?;
+        self.scanline_counter = reader.read_u8()?;
+        self.multiplicand = reader.read_u8()?;
+        self.multiplier = reader.read_u8()?;
+        self.extended_tile = reader.read_u16()? as usize % self.exram.len();
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// AxROM: one switchable 32KB PRG bank, 8KB CHR RAM and single-screen mirroring picked by the same register
+pub struct Mapper7 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    bank: u8,
+    mirroring: Mirroring,
+    bus_conflicts: bool, // AMROM: the written value is ANDed with the ROM byte at the address
+}
+
+impl Mapper7 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, bus_conflicts: bool) -> Mapper7 {
+        Mapper7 {
+            prg,
+            chr,
+            chr_is_ram,
+            bank: 0,
+            mirroring: Mirroring::OneScreenLower,
+            bus_conflicts,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        (self.bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.prg_index(addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+
+        let data = if self.bus_conflicts && !self.prg.is_empty() {
+            data & self.prg[self.prg_index(addr)]
+        } else {
+            data
+        };
+
+        self.bank = data & 0x0F;
+        self.mirroring = if data & 0x10 != 0 { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower };
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers: vec![
+                ("PRG bank", self.bank.to_string()),
+                ("Mirroring", self.mirroring.to_string()),
+            ],
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.bank);
+        writer.write_bool(self.mirroring == Mirroring::OneScreenUpper);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.bank = reader.read_u8()?;
+        self.mirroring = if reader.read_bool()? { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower };
+        Ok(())
+    }
+}
+
+// Color Dreams, one latch at $8000-$FFFF: PRG 32KB bank in bits 0-1, CHR 8KB bank in bits 4-7
+pub struct Mapper11 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Mapper11 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool) -> Mapper11 {
+        Mapper11 { prg, chr, chr_is_ram, prg_bank: 0, chr_bank: 0 }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        (self.prg_bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((self.chr_bank as usize * 0x2000 + (addr & 0x1FFF) as usize) % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper11 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.prg_index(addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+
+        // The latch sees the ROM driving the bus at the same time
+        let data = if self.prg.is_empty() { data } else { data & self.prg[self.prg_index(addr)] };
+        self.prg_bank = data & 0x03;
+        self.chr_bank = data >> 4;
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers: vec![
+                ("PRG bank", self.prg_bank.to_string()),
+                ("CHR bank", self.chr_bank.to_string()),
+            ],
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.prg_bank);
+        writer.write_u8(self.chr_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.prg_bank = reader.read_u8()?;
+        self.chr_bank = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+// UNROM 512: 16KB PRG bank at $8000 from bits 0-4 of a latch, last bank fixed at $C000, 8KB CHR RAM
+// bank in bits 5-6 and the single-screen page in bit 7 when the board has mirroring control.
+// The self-flashable board takes the latch at $C000-$FFFF only, $8000-$BFFF writes go to the flash
+// chip, which isn't emulated.
+pub struct Mapper30 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    chr_bank: u8,
+    mirroring: Option<Mirroring>,
+    mirroring_control: bool, // Header four-screen bit without the vertical bit
+    flashable: bool,         // Header battery bit, the flash chip also means no bus conflicts
+}
+
+impl Mapper30 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, mirroring_control: bool, flashable: bool) -> Mapper30 {
+        Mapper30 {
+            prg,
+            chr,
+            chr_is_ram,
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring: if mirroring_control { Some(Mirroring::OneScreenLower) } else { None },
+            mirroring_control,
+            flashable,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = self.prg.len() / 0x4000;
+        let bank = if addr >= 0xC000 { banks.saturating_sub(1) } else { self.prg_bank as usize };
+        (bank * 0x4000 + (addr & 0x3FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((self.chr_bank as usize * 0x2000 + (addr & 0x1FFF) as usize) % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper30 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.prg_index(addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 || (self.flashable && addr < 0xC000) {
+            return false;
+        }
+
+        let data = if !self.flashable && !self.prg.is_empty() {
+            data & self.prg[self.prg_index(addr)]
+        } else {
+            data
+        };
+
+        self.prg_bank = data & 0x1F;
+        self.chr_bank = (data >> 5) & 0x03;
+        if self.mirroring_control {
+            self.mirroring = Some(if data & 0x80 != 0 { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower });
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        let mut registers = vec![
+            ("PRG bank", self.prg_bank.to_string()),
+            ("CHR bank", self.chr_bank.to_string()),
+        ];
+        if let Some(mirroring) = self.mirroring {
+            registers.push(("Mirroring", mirroring.to_string()));
+        }
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers,
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.prg_bank);
+        writer.write_u8(self.chr_bank);
+        writer.write_bool(self.mirroring == Some(Mirroring::OneScreenUpper));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.prg_bank = reader.read_u8()?;
+        self.chr_bank = reader.read_u8()?;
+        let upper = reader.read_bool()?;
+        if self.mirroring_control {
+            self.mirroring = Some(if upper { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower });
+        }
+        Ok(())
+    }
+}
+
+// GxROM, one latch at $8000-$FFFF: PRG 32KB bank in bits 4-5, CHR 8KB bank in bits 0-1
+pub struct Mapper66 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Mapper66 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool) -> Mapper66 {
+        Mapper66 { prg, chr, chr_is_ram, prg_bank: 0, chr_bank: 0 }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        (self.prg_bank as usize * 0x8000 + (addr & 0x7FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((self.chr_bank as usize * 0x2000 + (addr & 0x1FFF) as usize) % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper66 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.prg_index(addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+
+        // Discrete latch with no ROM /OE gating, so writes conflict with the ROM byte
+        let data = if self.prg.is_empty() { data } else { data & self.prg[self.prg_index(addr)] };
+        self.prg_bank = (data >> 4) & 0x03;
+        self.chr_bank = data & 0x03;
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers: vec![
+                ("PRG bank", self.prg_bank.to_string()),
+                ("CHR bank", self.chr_bank.to_string()),
+            ],
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.prg_bank);
+        writer.write_u8(self.chr_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.prg_bank = reader.read_u8()?;
+        self.chr_bank = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+// Camerica/Codemasters: 16KB PRG bank at $8000 switched by $C000-$FFFF writes, last bank fixed at $C000
+pub struct Mapper71 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    bank: u8,
+    mirroring: Option<Mirroring>,
+    mirroring_control: bool, // Fire Hawk board (submapper 1): $8000-$9FFF picks the single-screen page
+}
+
+impl Mapper71 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, mirroring_control: bool) -> Mapper71 {
+        Mapper71 {
+            prg,
+            chr,
+            chr_is_ram,
+            bank: 0,
+            mirroring: if mirroring_control { Some(Mirroring::OneScreenLower) } else { None },
+            mirroring_control,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = self.prg.len() / 0x4000;
+        let bank = if addr >= 0xC000 { banks.saturating_sub(1) } else { self.bank as usize };
+        (bank * 0x4000 + (addr & 0x3FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.chr.len())
+    }
+}
+
+impl Mapper for Mapper71 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.prg_index(addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            0xC000..=0xFFFF => {
+                self.bank = data & 0x0F;
+                true
+            }
+            0x8000..=0x9FFF if self.mirroring_control => {
+                self.mirroring = Some(if data & 0x10 != 0 { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        let mut registers = vec![("PRG bank", self.bank.to_string())];
+        if let Some(mirroring) = self.mirroring {
+            registers.push(("Mirroring", mirroring.to_string()));
+        }
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers,
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.bank);
+        writer.write_bool(self.mirroring == Some(Mirroring::OneScreenUpper));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.bank = reader.read_u8()?;
+        let upper = reader.read_bool()?;
+        if self.mirroring_control {
+            self.mirroring = Some(if upper { Mirroring::OneScreenUpper } else { Mirroring::OneScreenLower });
+        }
+        Ok(())
+    }
+}
+
+// Sunsoft FME-7: command/parameter pair at $8000/$A000 for eight 1KB CHR banks, four 8KB PRG banks
+// (the one at $6000 can be RAM), mirroring and a 16 bit IRQ counter clocked by the CPU
+pub struct Mapper69 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4], // $6000, $8000, $A000, $C000, $E000 is fixed to the last bank
+    prg_ram_selected: bool,
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mapper69 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool, prg_ram_size: usize) -> Mapper69 {
+        Mapper69 {
+            prg,
+            chr,
+            chr_is_ram,
+            prg_ram: vec![0; prg_ram_size],
+
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            prg_ram_selected: false,
+            prg_ram_enabled: false,
+            mirroring: Mirroring::Vertical,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / 0x2000).max(1);
+        let bank = match addr {
+            0xE000..=0xFFFF => bank_count - 1,
+            _ => self.prg_banks[((addr - 0x6000) / 0x2000) as usize] as usize,
+        };
+        ((bank % bank_count) * 0x2000 + (addr & 0x1FFF) as usize) % self.prg.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        let bank = self.chr_banks[((addr & 0x1FFF) / 0x0400) as usize] as usize;
+        Some((bank * 0x0400 + (addr & 0x03FF) as usize) % self.chr.len())
+    }
+
+    // PRG RAM smaller than 8KB repeats across $6000-$7FFF, a board without any leaves it open bus
+    fn prg_ram_index(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some((addr & 0x1FFF) as usize % self.prg_ram.len())
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_selected = data & 0x40 != 0;
+                self.prg_banks[0] = data & 0x3F;
+            }
+            0x9..=0xB => self.prg_banks[(self.command - 0x8) as usize] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::OneScreenLower,
+                    _ => Mirroring::OneScreenUpper,
+                };
+            }
+            // Any write to the IRQ control acknowledges a pending IRQ
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8),
+        }
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_selected => {
+                if self.prg_ram_enabled {
+                    self.prg_ram_index(addr).map(|index| self.prg_ram[index])
+                } else {
+                    None
+                }
+            }
+            0x6000..=0xFFFF if !self.prg.is_empty() => Some(self.prg[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            0x6000..=0x7FFF => {
+                let Some(index) = self.prg_ram_index(addr) else {
+                    return false;
+                };
+                if !self.prg_ram_selected || !self.prg_ram_enabled {
+                    return false;
+                }
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            // $C000-$FFFF is the 5B audio chip on Gimmick!, not emulated
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.chr_index(addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.chr_index(addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    // Counts down every CPU cycle while enabled, the IRQ fires when it wraps from $0000 to $FFFF
+    fn cpu_clock(&mut self) {
+        if !self.irq_counter_enabled {
+            return;
+        }
+
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        let chr_banks: Vec<String> = self.chr_banks.iter().map(|bank| format!("{:02X}", bank)).collect();
+        let prg_6000 = match (self.prg_ram_selected, self.prg_ram_enabled) {
+            (true, true) => "RAM".to_string(),
+            (true, false) => "open bus".to_string(),
+            (false, _) => format!("ROM bank {}", self.prg_banks[0]),
+        };
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.prg_index(addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.chr_index(addr)),
+            registers: vec![
+                ("Command", format!("${:X}", self.command)),
+                ("CHR banks", chr_banks.join(" ")),
+                ("$6000", prg_6000),
+                ("Mirroring", self.mirroring.to_string()),
+                ("IRQ counter", format!("${:04X}", self.irq_counter)),
+                ("IRQ enabled", self.irq_enabled.to_string()),
+                ("IRQ counter enabled", self.irq_counter_enabled.to_string()),
+                ("IRQ pending", self.irq_pending.to_string()),
+            ],
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.command);
+        writer.write_bytes(&self.chr_banks);
+        writer.write_bytes(&self.prg_banks);
+        writer.write_bool(self.prg_ram_selected);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_u8(match self.mirroring {
+            Mirroring::Vertical => 0,
+            Mirroring::Horizontal => 1,
+            Mirroring::OneScreenLower => 2,
+            _ => 3,
+        });
+        writer.write_u16(self.irq_counter);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_counter_enabled);
+        writer.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.command = reader.read_u8()?;
+        reader.read_into(&mut self.chr_banks)?;
+        reader.read_into(&mut self.prg_banks)?;
+        self.prg_ram_selected = reader.read_bool()?;
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.mirroring = match reader.read_u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::OneScreenLower,
+            _ => Mirroring::OneScreenUpper,
+        };
+        self.irq_counter = reader.read_u16()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_counter_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// Namco 108 / DxROM: MMC3's bank registers without the PRG/CHR mode bits, IRQ, PRG RAM or mirroring control
+pub struct Mapper206 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    banks: Mmc3Banks,
+}
+
+impl Mapper206 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, chr_is_ram: bool) -> Mapper206 {
+        Mapper206 { prg, chr, chr_is_ram, banks: Mmc3Banks::default() }
+    }
+}
+
+impl Mapper for Mapper206 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return None;
+        }
+        Some(self.prg[self.banks.prg_index(self.prg.len(), addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !(0x8000..=0x9FFF).contains(&addr) {
+            return false;
+        }
+
+        // Only the register number exists, the mode bits aren't there so banking stays in MMC3's mode 0.
+        // The bank registers are also narrower: 6 bits for CHR, 4 for PRG.
+        if addr & 0x0001 == 0 {
+            self.banks.write(addr, data & 0x07);
+        } else {
+            let mask = if self.banks.bank_select >= 6 { 0x0F } else { 0x3F };
+            self.banks.write(addr, data & mask);
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+        self.banks.chr_index(self.chr.len(), addr).map(|index| self.chr[index])
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        if !self.chr_is_ram {
+            return false;
+        }
+
+        match self.banks.chr_index(self.chr.len(), addr) {
+            Some(index) => {
+                self.chr[index] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn debug_banks(&self) -> MapperDebugInfo {
+        MapperDebugInfo {
+            cpu_windows: prg_windows(&self.prg, |addr| self.banks.prg_index(self.prg.len(), addr)),
+            ppu_windows: chr_windows(self.chr_is_ram, |addr| self.banks.chr_index(self.chr.len(), addr)),
+            registers: self.banks.debug_registers(),
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.chr_is_ram {
+            writer.write_bytes(&self.chr);
+        }
+        writer.write_u8(self.banks.bank_select);
+        writer.write_bytes(&self.banks.registers);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.chr_is_ram {
+            reader.read_into(&mut self.chr)?;
+        }
+        self.banks.bank_select = reader.read_u8()?;
+        reader.read_into(&mut self.banks.registers)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ROM made of banks of the given size, each filled with its own bank number
+    fn numbered_banks(count: usize, size: usize) -> Vec<u8> {
+        (0..count).flat_map(|bank| vec![bank as u8; size]).collect()
+    }
+
+    // 128KB PRG in 8KB banks and 64KB CHR in 1KB banks
+    fn mmc3() -> Mapper4 {
+        Mapper4::new(numbered_banks(16, 0x2000), numbered_banks(64, 0x0400), false, Mirroring::Vertical, 0x2000)
+    }
+
+    fn prg_banks(mapper: &mut dyn Mapper) -> [u8; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mapper.cpu_read(addr).unwrap())
+    }
+
+    fn chr_banks(mapper: &mut dyn Mapper) -> [u8; 8] {
+        std::array::from_fn(|slot| mapper.ppu_read(slot as u16 * 0x0400).unwrap())
+    }
+
+    #[test]
+    fn mapper_mmc3_bank_select_and_windows() {
+        let mut mapper = mmc3();
+        for (register, bank) in [(0, 8), (1, 12), (2, 20), (3, 21), (4, 22), (5, 23), (6, 3), (7, 5)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        assert_eq!(prg_banks(&mut mapper), [3, 5, 14, 15]);
+        // R0 and R1 select 2KB banks, ignoring their low bit
+        assert_eq!(chr_banks(&mut mapper), [8, 9, 12, 13, 20, 21, 22, 23]);
+
+        // PRG mode 1 swaps $8000 with $C000, CHR inversion swaps the pattern table halves
+        mapper.cpu_write(0x8000, 0xC0);
+        assert_eq!(prg_banks(&mut mapper), [14, 5, 3, 15]);
+        assert_eq!(chr_banks(&mut mapper), [20, 21, 22, 23, 8, 9, 12, 13]);
+
+        // Any even address in $8000-$9FFF is bank select, odd is bank data
+        mapper.cpu_write(0x9FFE, 0xC6);
+        mapper.cpu_write(0x9FFF, 0x07);
+        assert_eq!(prg_banks(&mut mapper), [14, 5, 7, 15]);
+    }
+
+    // Clocks the scanline counter, returning the clock numbers (from 1) that had the IRQ raised
+    fn irq_clocks(mapper: &mut Mapper4, clocks: usize) -> Vec<usize> {
+        (1..=clocks)
+            .filter(|_| {
+                mapper.notify_a12();
+                mapper.irq_pending()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mapper_mmc3_irq_counter() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xC000, 3);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        // The first clock reloads to 3, then it counts down to 0 on the 4th
+        assert_eq!(irq_clocks(&mut mapper, 3), []);
+        assert_eq!(irq_clocks(&mut mapper, 1), [1]);
+        // Held until acknowledged, $E000 also disables it
+        assert!(mapper.irq_pending());
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq_pending());
+        assert_eq!(irq_clocks(&mut mapper, 8), []);
+
+        // Re-enabled and acknowledged each time, 0 reloads to 3 and every 4th clock raises it again
+        mapper.cpu_write(0xE001, 0);
+        let mut raised = Vec::new();
+        for clock in 1..=8 {
+            mapper.notify_a12();
+            if mapper.irq_pending() {
+                raised.push(clock);
+                mapper.cpu_write(0xE000, 0);
+                mapper.cpu_write(0xE001, 0);
+            }
+        }
+        assert_eq!(raised, [4, 8]);
+    }
+
+    #[test]
+    fn mapper_mmc3_irq_reload_and_zero_latch() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xC000, 5);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+        assert_eq!(irq_clocks(&mut mapper, 2), []);
+
+        // $C001 mid-count reloads from the new latch on the next clock
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        assert_eq!(irq_clocks(&mut mapper, 2), []);
+        assert_eq!(irq_clocks(&mut mapper, 1), [1]);
+
+        // A latch of 0 raises the IRQ on every clock
+        mapper.cpu_write(0xE000, 0);
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+        for clock in 0..4 {
+            mapper.notify_a12();
+            assert!(mapper.irq_pending(), "clock {}", clock);
+            mapper.cpu_write(0xE000, 0);
+            mapper.cpu_write(0xE001, 0);
+        }
+    }
+
+    #[test]
+    fn mapper_mmc5_multiplier() {
+        let mut mapper = Mapper5::new(numbered_banks(16, 0x2000), Vec::new(), true, 0x2000);
+        // Both factors power up as $FF
+        assert_eq!((mapper.cpu_read(0x5205), mapper.cpu_read(0x5206)), (Some(0x01), Some(0xFE)));
+
+        mapper.cpu_write(0x5205, 200);
+        mapper.cpu_write(0x5206, 100);
+        assert_eq!((mapper.cpu_read(0x5205), mapper.cpu_read(0x5206)), (Some(0x20), Some(0x4E)));
+        assert_eq!((mapper.peek(0x5205), mapper.peek(0x5206)), (Some(0x20), Some(0x4E)));
+    }
+
+    #[test]
+    fn mapper_mmc5_prg_modes() {
+        let mut mapper = Mapper5::new(numbered_banks(16, 0x2000), Vec::new(), true, 0x10000);
+        for (addr, data) in [(0x5114, 0x81), (0x5115, 0x83), (0x5116, 0x85), (0x5117, 0x87)] {
+            mapper.cpu_write(addr, data);
+        }
+        // Larger windows drop the low bits of their bank number
+        for (mode, banks) in [(0, [4, 5, 6, 7]), (1, [2, 3, 6, 7]), (2, [2, 3, 5, 7]), (3, [1, 3, 5, 7])] {
+            mapper.cpu_write(0x5100, mode);
+            assert_eq!(prg_banks(&mut mapper), banks, "mode {}", mode);
+        }
+
+        // Without bit 7 a window maps PRG RAM, except at $E000
+        mapper.cpu_write(0x5102, 0x02);
+        mapper.cpu_write(0x5103, 0x01);
+        mapper.cpu_write(0x5113, 0x02);
+        mapper.cpu_write(0x6000, 0x99);
+        mapper.cpu_write(0x5114, 0x02);
+        mapper.cpu_write(0x5117, 0x07);
+        assert_eq!(prg_banks(&mut mapper), [0x99, 3, 5, 7]);
+    }
+
+    #[test]
+    fn mapper_mmc5_peek_leaves_irq_pending() {
+        let mut mapper = Mapper5::new(numbered_banks(16, 0x2000), Vec::new(), true, 0x2000);
+        mapper.cpu_write(0x5203, 2);
+        mapper.cpu_write(0x5204, 0x80);
+        for _ in 0..3 {
+            mapper.notify_scanline(true);
+        }
+        assert!(mapper.irq_pending());
+
+        assert_eq!(mapper.peek(0x5204), Some(0xC0));
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.cpu_read(0x5204), Some(0xC0));
+        assert!(!mapper.irq_pending());
+        assert_eq!(mapper.peek(0x5204), Some(0x40));
+    }
+
+    #[test]
+    fn mapper_axrom_one_screen_select() {
+        let mut mapper = Mapper7::new(numbered_banks(8, 0x8000), vec![0; 0x2000], true, false);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        mapper.cpu_write(0x8000, 0x13);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenUpper));
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(3));
+
+        // Bit 4 alone picks the page, the bank follows the low bits
+        mapper.cpu_write(0xC000, 0x05);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+    }
+
+    #[test]
+    fn mapper_amrom_bus_conflicts() {
+        // Bank numbers everywhere except for one $FF byte in bank 0
+        let mut prg = numbered_banks(8, 0x8000);
+        prg[0x0100] = 0xFF;
+        let mut mapper = Mapper7::new(prg.clone(), vec![0; 0x2000], true, true);
+
+        // Bank 0's zeros swallow the write
+        mapper.cpu_write(0x8000, 0x17);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // Writing over the $FF byte gets the full value through
+        mapper.cpu_write(0x8100, 0x13);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenUpper));
+
+        // ANDed with bank 3's bytes, 0x15 becomes bank 1
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), Some(1));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // ANROM/AOROM take the same write as is
+        let mut mapper = Mapper7::new(prg, vec![0; 0x2000], true, false);
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenUpper));
+    }
+
+    // 32KB PRG banks of $FF, so latch writes pass through, with the bank number in their first byte
+    fn latch_prg(banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|bank| [vec![bank as u8], vec![0xFF; 0x7FFF]].concat()).collect()
+    }
+
+    #[test]
+    fn mapper_color_dreams_windows_and_bus_conflicts() {
+        let mut mapper = Mapper11::new(latch_prg(4), numbered_banks(16, 0x2000), false);
+        // PRG in bits 0-1, CHR in bits 4-7
+        mapper.cpu_write(0x8001, 0xA3);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.ppu_read(0x0000), Some(10));
+        assert_eq!(mapper.ppu_read(0x1FFF), Some(10));
+
+        // The ROM's 3 at $8000 masks $F2 down to PRG bank 2, CHR bank 0
+        mapper.cpu_write(0x8000, 0xF2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(2));
+        assert_eq!(mapper.ppu_read(0x0000), Some(0));
+    }
+
+    #[test]
+    fn mapper_gxrom_windows_and_bus_conflicts() {
+        let mut mapper = Mapper66::new(latch_prg(4), numbered_banks(4, 0x2000), false);
+        // PRG in bits 4-5, CHR in bits 0-1
+        mapper.cpu_write(0x8001, 0x32);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.ppu_read(0x0000), Some(2));
+        assert_eq!(mapper.ppu_read(0x1FFF), Some(2));
+
+        // The ROM's 3 at $8000 only lets the CHR bits through
+        mapper.cpu_write(0x8000, 0x13);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.ppu_read(0x0000), Some(3));
+    }
+
+    #[test]
+    fn mapper_unrom_512_bank_fields() {
+        let mut mapper = Mapper30::new(numbered_banks(32, 0x4000), numbered_banks(4, 0x2000), true, true, true);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // PRG in bits 0-4, CHR in bits 5-6, the one-screen page in bit 7
+        mapper.cpu_write(0xC000, 0xF7);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x17));
+        assert_eq!(mapper.cpu_read(0xC000), Some(31));
+        assert_eq!(mapper.ppu_read(0x0000), Some(3));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenUpper));
+
+        mapper.cpu_write(0xFFFF, 0x29);
+        assert_eq!(mapper.cpu_read(0x8000), Some(9));
+        assert_eq!(mapper.ppu_read(0x1FFF), Some(1));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // The flash chip takes commands below $C000 instead of bank writes
+        assert!(!mapper.cpu_write(0x8000, 0x03));
+        assert_eq!(mapper.cpu_read(0x8000), Some(9));
+
+        // Without the mirroring control bit the header mirroring stays
+        let mut mapper = Mapper30::new(numbered_banks(32, 0x4000), numbered_banks(4, 0x2000), true, false, true);
+        mapper.cpu_write(0xC000, 0x80);
+        assert_eq!(mapper.mirroring(), None);
+    }
+
+    #[test]
+    fn mapper_unrom_512_bus_conflicts() {
+        // The fixed bank's 31s mask off the CHR and mirroring bits
+        let mut mapper = Mapper30::new(numbered_banks(32, 0x4000), numbered_banks(4, 0x2000), true, true, false);
+        mapper.cpu_write(0xC000, 0xE5);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.ppu_read(0x0000), Some(0));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // Writes to $8000-$BFFF are ANDed with the switched bank, 5 lets bits 0 and 2 through
+        assert!(mapper.cpu_write(0x8000, 0xFE));
+        assert_eq!(mapper.cpu_read(0x8000), Some(4));
+    }
+
+    #[test]
+    fn mapper_camerica_banking() {
+        let mut mapper = Mapper71::new(numbered_banks(8, 0x4000), vec![0; 0x2000], true, false);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(7));
+        assert_eq!(mapper.mirroring(), None);
+
+        // Only $C000-$FFFF selects the bank, the last bank stays fixed
+        mapper.cpu_write(0xF123, 5);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.cpu_read(0xBFFF), Some(5));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(7));
+        assert!(!mapper.cpu_write(0x8000, 2));
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+        assert_eq!(mapper.mirroring(), None);
+    }
+
+    #[test]
+    fn mapper_camerica_fire_hawk_mirroring() {
+        let mut mapper = Mapper71::new(numbered_banks(8, 0x4000), vec![0; 0x2000], true, true);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        mapper.cpu_write(0x9000, 0x10);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenUpper));
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // $A000-$BFFF does nothing on this board either
+        assert!(!mapper.cpu_write(0xA000, 0x10));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+    }
+
+    // Command to $8000, parameter to $A000
+    fn fme7_write(mapper: &mut Mapper69, command: u8, data: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, data);
+    }
+
+    #[test]
+    fn mapper_fme7_command_decode() {
+        let mut mapper = Mapper69::new(numbered_banks(32, 0x2000), numbered_banks(256, 0x0400), false, 0x2000);
+        for slot in 0..8 {
+            fme7_write(&mut mapper, slot, 0x40 + slot);
+        }
+        assert_eq!(chr_banks(&mut mapper), [0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47]);
+
+        fme7_write(&mut mapper, 0x8, 0x04);
+        fme7_write(&mut mapper, 0x9, 0x05);
+        fme7_write(&mut mapper, 0xA, 0x06);
+        // Only the low nibble of the command register counts
+        fme7_write(&mut mapper, 0xFB, 0x47);
+        assert_eq!(mapper.cpu_read(0x6000), Some(4));
+        assert_eq!(prg_banks(&mut mapper), [5, 6, 7, 31]);
+
+        // Bit 6 of command 8 puts RAM at $6000, bit 7 enables it
+        fme7_write(&mut mapper, 0x8, 0xC0);
+        mapper.cpu_write(0x6000, 0x99);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x99));
+        fme7_write(&mut mapper, 0x8, 0x40);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+
+        for (data, mirroring) in [
+            (0, Mirroring::Vertical),
+            (1, Mirroring::Horizontal),
+            (2, Mirroring::OneScreenLower),
+            (3, Mirroring::OneScreenUpper),
+        ] {
+            fme7_write(&mut mapper, 0xC, data);
+            assert_eq!(mapper.mirroring(), Some(mirroring));
+        }
+    }
+
+    #[test]
+    fn mapper_fme7_cpu_clocked_irq() {
+        let mut mapper = Mapper69::new(numbered_banks(32, 0x2000), Vec::new(), true, 0);
+        fme7_write(&mut mapper, 0xE, 0x03);
+        fme7_write(&mut mapper, 0xF, 0x00);
+
+        // Counting without IRQs enabled never raises it
+        fme7_write(&mut mapper, 0xD, 0x80);
+        for _ in 0..4 {
+            mapper.cpu_clock();
+        }
+        assert!(!mapper.irq_pending());
+
+        // Fires on the clock that takes the counter from 0 to $FFFF
+        fme7_write(&mut mapper, 0xE, 0x03);
+        fme7_write(&mut mapper, 0xF, 0x00);
+        fme7_write(&mut mapper, 0xD, 0x81);
+        for clock in 1..=3 {
+            mapper.cpu_clock();
+            assert!(!mapper.irq_pending(), "clock {}", clock);
+        }
+        mapper.cpu_clock();
+        assert!(mapper.irq_pending());
+
+        // Any $D write acknowledges it, and a stopped counter doesn't move
+        fme7_write(&mut mapper, 0xD, 0x01);
+        assert!(!mapper.irq_pending());
+        for _ in 0..0x10000 {
+            mapper.cpu_clock();
+        }
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn mapper_dxrom_bank_switching() {
+        let mut mapper = Mapper206::new(numbered_banks(16, 0x2000), numbered_banks(64, 0x0400), false);
+        for (register, bank) in [(0, 8), (1, 12), (2, 20), (3, 21), (4, 22), (5, 23), (6, 3), (7, 5)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        assert_eq!(prg_banks(&mut mapper), [3, 5, 14, 15]);
+        assert_eq!(chr_banks(&mut mapper), [8, 9, 12, 13, 20, 21, 22, 23]);
+
+        // No mode bits, so the MMC3 PRG swap and CHR inversion never happen
+        mapper.cpu_write(0x8000, 0xC6);
+        assert_eq!(prg_banks(&mut mapper), [3, 5, 14, 15]);
+        assert_eq!(chr_banks(&mut mapper), [8, 9, 12, 13, 20, 21, 22, 23]);
+
+        // PRG registers only have 4 bits, CHR registers 6
+        mapper.cpu_write(0x8001, 0x17);
+        assert_eq!(prg_banks(&mut mapper), [7, 5, 14, 15]);
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0x8001, 0xC1);
+        assert_eq!(chr_banks(&mut mapper)[4], 1);
+
+        // Nothing above $9FFF
+        assert!(!mapper.cpu_write(0xA000, 0x01));
+        assert!(!mapper.cpu_write(0xE001, 0x01));
+    }
+
+    // What the CPU and PPU see through the board: $6000, the PRG windows, the CHR slots and the mirroring
+    fn visible_state(mapper: &mut dyn Mapper) -> (Option<u8>, [u8; 4], [u8; 8], Option<Mirroring>) {
+        (mapper.cpu_read(0x6000), prg_banks(mapper), chr_banks(mapper), mapper.mirroring())
+    }
+
+    // Configures the board, saves, reconfigures it and restores the save, which has to bring back what was saved
+    #[track_caller]
+    fn assert_state_round_trip(mut mapper: impl Mapper, saved: &[(u16, u8)], changed: &[(u16, u8)]) {
+        for &(addr, data) in saved {
+            mapper.cpu_write(addr, data);
+        }
+        let expected = visible_state(&mut mapper);
+        let mut writer = StateWriter::new(0);
+        mapper.save_state(&mut writer);
+        let state = writer.finish();
+
+        for &(addr, data) in changed {
+            mapper.cpu_write(addr, data);
+        }
+        assert_ne!(visible_state(&mut mapper), expected);
+
+        let mut reader = StateReader::new(&state).unwrap();
+        mapper.load_state(&mut reader).unwrap();
+        assert_eq!(visible_state(&mut mapper), expected);
+    }
+
+    #[test]
+    fn mapper_state_round_trips() {
+        let chr_1k = || numbered_banks(64, 0x0400);
+        let chr_8k = || numbered_banks(4, 0x2000);
+
+        assert_state_round_trip(
+            Mapper0::new(numbered_banks(2, 0x4000), chr_8k(), false, 0x2000),
+            &[(0x6000, 0x11)],
+            &[(0x6000, 0x22)],
+        );
+        assert_state_round_trip(
+            Mapper4::new(numbered_banks(16, 0x2000), chr_1k(), false, Mirroring::Vertical, 0x2000),
+            &[(0x8000, 6), (0x8001, 3), (0x8000, 0), (0x8001, 8), (0xA000, 1), (0x6000, 0x11)],
+            &[(0x8000, 0x46), (0x8001, 9), (0x8000, 0x80), (0x8001, 20), (0xA000, 0), (0x6000, 0x22)],
+        );
+        assert_state_round_trip(
+            Mapper5::new(numbered_banks(16, 0x2000), chr_1k(), false, 0x2000),
+            &[(0x5114, 0x83), (0x5120, 5), (0x5102, 2), (0x5103, 1), (0x6000, 0x11)],
+            &[(0x5114, 0x86), (0x5120, 9), (0x5100, 0), (0x6000, 0x22)],
+        );
+        assert_state_round_trip(
+            Mapper7::new(numbered_banks(8, 0x8000), chr_8k(), false, false),
+            &[(0x8000, 0x13)],
+            &[(0x8000, 0x05)],
+        );
+        assert_state_round_trip(
+            Mapper11::new(latch_prg(4), numbered_banks(16, 0x2000), false),
+            &[(0x8001, 0x21)],
+            &[(0x8001, 0x32)],
+        );
+        assert_state_round_trip(
+            Mapper30::new(numbered_banks(32, 0x4000), chr_8k(), false, true, true),
+            &[(0xC000, 0xA5)],
+            &[(0xC000, 0x42)],
+        );
+        assert_state_round_trip(
+            Mapper66::new(latch_prg(4), chr_8k(), false),
+            &[(0x8001, 0x12)],
+            &[(0x8001, 0x21)],
+        );
+        assert_state_round_trip(
+            Mapper69::new(numbered_banks(32, 0x2000), chr_1k(), false, 0x2000),
+            &[(0x8000, 0x9), (0xA000, 4), (0x8000, 0x0), (0xA000, 7), (0x8000, 0xC), (0xA000, 1)],
+            &[(0x8000, 0x9), (0xA000, 6), (0x8000, 0x0), (0xA000, 3), (0x8000, 0xC), (0xA000, 3)],
+        );
+        assert_state_round_trip(
+            Mapper71::new(numbered_banks(8, 0x4000), chr_8k(), false, true),
+            &[(0xC000, 3), (0x9000, 0x10)],
+            &[(0xC000, 6), (0x9000, 0x00)],
+        );
+        assert_state_round_trip(
+            Mapper206::new(numbered_banks(16, 0x2000), chr_1k(), false),
+            &[(0x8000, 6), (0x8001, 3), (0x8000, 2), (0x8001, 9)],
+            &[(0x8000, 6), (0x8001, 7), (0x8000, 2), (0x8001, 30)],
+        );
+    }
+
+    // Bank numbers of the CPU and PPU windows, and a register's value, as the bank inspector shows them
+    fn window_banks(info: &MapperDebugInfo) -> (Vec<usize>, Vec<usize>) {
+        let banks = |windows: &[BankWindow]| windows.iter().map(|window| window.bank).collect();
+        (banks(&info.cpu_windows), banks(&info.ppu_windows))
+    }
+
+    fn register<'a>(info: &'a MapperDebugInfo, name: &str) -> Option<&'a str> {
+        info.registers.iter().find(|(register, _)| *register == name).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn mapper_debug_banks_follow_registers() {
+        let chr_1k = || numbered_banks(64, 0x0400);
+        let chr_8k = || numbered_banks(4, 0x2000);
+
+        // NROM-128 shows its one 16KB bank twice, over CHR RAM
+        let info = Mapper0::new(numbered_banks(1, 0x4000), vec![0; 0x2000], true, 0x2000).debug_banks();
+        assert_eq!(window_banks(&info), (vec![0, 1, 0, 1], (0..8).collect()));
+        assert!(info.ppu_windows.iter().all(|window| window.source == BankSource::Ram));
+        assert!(info.registers.is_empty());
+
+        let mut mapper = mmc3();
+        for (register, bank) in [(0, 8), (1, 12), (2, 20), (3, 21), (4, 22), (5, 23), (6, 3), (7, 5)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        mapper.cpu_write(0x8000, 0xC0);
+        mapper.cpu_write(0xA000, 1);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![14, 5, 3, 15], vec![20, 21, 22, 23, 8, 9, 12, 13]));
+        assert_eq!(register(&info, "Bank select"), Some("$C0"));
+        assert_eq!(register(&info, "PRG mode"), Some("1"));
+        assert_eq!(register(&info, "CHR inversion"), Some("1"));
+        assert_eq!(register(&info, "R0-R7"), Some("08 0C 14 15 16 17 03 05"));
+        assert_eq!(register(&info, "Mirroring"), Some("Horizontal"));
+
+        // MMC5 PRG mode 2 with RAM in the $C000 window, CHR mode 1 with 4KB banks
+        let mut mapper = Mapper5::new(numbered_banks(16, 0x2000), chr_1k(), false, 0x10000);
+        let writes = [(0x5100, 2), (0x5115, 0x85), (0x5116, 0x07), (0x5117, 0x0B), (0x5101, 1), (0x5123, 2), (0x5127, 5)];
+        for (addr, data) in writes {
+            mapper.cpu_write(addr, data);
+        }
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![4, 5, 7, 11], vec![8, 9, 10, 11, 20, 21, 22, 23]));
+        let sources = info.cpu_windows.map(|window| window.source);
+        assert_eq!(sources, [BankSource::Rom, BankSource::Rom, BankSource::Ram, BankSource::Rom]);
+        assert_eq!(register(&info, "PRG mode"), Some("2"));
+        assert_eq!(register(&info, "CHR mode"), Some("1"));
+        assert_eq!(register(&info, "PRG banks"), Some("00 FF 85 07 0B"));
+        assert_eq!(register(&info, "CHR A"), Some("000 000 000 002 000 000 000 005"));
+
+        let mut mapper = Mapper7::new(numbered_banks(8, 0x8000), chr_8k(), false, false);
+        mapper.cpu_write(0x8000, 0x13);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![12, 13, 14, 15], (0..8).collect()));
+        assert_eq!(register(&info, "PRG bank"), Some("3"));
+        assert_eq!(register(&info, "Mirroring"), Some("OneScreenUpper"));
+
+        let mut mapper = Mapper11::new(latch_prg(4), numbered_banks(16, 0x2000), false);
+        mapper.cpu_write(0x8001, 0xA3);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![12, 13, 14, 15], (80..88).collect()));
+        assert_eq!(register(&info, "PRG bank"), Some("3"));
+        assert_eq!(register(&info, "CHR bank"), Some("10"));
+
+        let mut mapper = Mapper30::new(numbered_banks(32, 0x4000), chr_8k(), false, true, true);
+        mapper.cpu_write(0xC000, 0xA5);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![10, 11, 62, 63], (8..16).collect()));
+        assert_eq!(register(&info, "PRG bank"), Some("5"));
+        assert_eq!(register(&info, "CHR bank"), Some("1"));
+        assert_eq!(register(&info, "Mirroring"), Some("OneScreenUpper"));
+
+        let mut mapper = Mapper66::new(latch_prg(4), chr_8k(), false);
+        mapper.cpu_write(0x8001, 0x12);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![4, 5, 6, 7], (16..24).collect()));
+        assert_eq!(register(&info, "PRG bank"), Some("1"));
+        assert_eq!(register(&info, "CHR bank"), Some("2"));
+
+        let mut mapper = Mapper69::new(numbered_banks(32, 0x2000), numbered_banks(256, 0x0400), false, 0x2000);
+        for slot in 0..8 {
+            fme7_write(&mut mapper, slot, 0x40 + slot);
+        }
+        for (command, data) in [(0x9, 5), (0xA, 6), (0xB, 7), (0x8, 0xC0), (0xC, 1)] {
+            fme7_write(&mut mapper, command, data);
+        }
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![5, 6, 7, 31], (0x40..0x48).collect()));
+        assert_eq!(register(&info, "Command"), Some("$C"));
+        assert_eq!(register(&info, "CHR banks"), Some("40 41 42 43 44 45 46 47"));
+        assert_eq!(register(&info, "$6000"), Some("RAM"));
+        assert_eq!(register(&info, "Mirroring"), Some("Horizontal"));
+
+        // Without the Fire Hawk mirroring bit there is no mirroring register to show
+        let mut mapper = Mapper71::new(numbered_banks(8, 0x4000), chr_8k(), false, false);
+        mapper.cpu_write(0xC000, 3);
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![6, 7, 14, 15], (0..8).collect()));
+        assert_eq!(register(&info, "PRG bank"), Some("3"));
+        assert_eq!(register(&info, "Mirroring"), None);
+
+        let mut mapper = Mapper206::new(numbered_banks(16, 0x2000), chr_1k(), false);
+        for (register, bank) in [(0, 8), (1, 12), (2, 20), (3, 21), (4, 22), (5, 23), (6, 3), (7, 5)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        let info = mapper.debug_banks();
+        assert_eq!(window_banks(&info), (vec![3, 5, 14, 15], vec![8, 9, 12, 13, 20, 21, 22, 23]));
+        assert_eq!(register(&info, "R0-R7"), Some("08 0C 14 15 16 17 03 05"));
+    }
+}
@@ -60,6 +60,8 @@ pub enum Opcode {
     TXS, // Transfer X to Stack Pointer
     TYA, // Transfer Y to Accumulator
 
+    JAM, // Halts the CPU until reset (Unofficial, also known as KIL)
+
     XXX, // Unknown (Unofficial Opcodes)
     // Unofficial Opcodes are not implemented in this project (yet).
 }
@@ -87,7 +89,7 @@ pub mod references {
     pub static INSTRUCTION_LOOKUP: [Instruction; 256] = [
         Instruction { hexcode: 0x00, operate: Opcode::BRK, addrmode: AddressingMode::IMP, cycles: 7 },
         Instruction { hexcode: 0x01, operate: Opcode::ORA, addrmode: AddressingMode::IZX, cycles: 6 },
-        Instruction { hexcode: 0x02, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x02, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x03, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x04, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 3 },
         Instruction { hexcode: 0x05, operate: Opcode::ORA, addrmode: AddressingMode::ZP0, cycles: 3 },
@@ -104,7 +106,7 @@ pub mod references {
 
         Instruction { hexcode: 0x10, operate: Opcode::BPL, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0x11, operate: Opcode::ORA, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0x12, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x12, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x13, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x14, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0x15, operate: Opcode::ORA, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -121,7 +123,7 @@ pub mod references {
 
         Instruction { hexcode: 0x20, operate: Opcode::JSR, addrmode: AddressingMode::ABS, cycles: 6 },
         Instruction { hexcode: 0x21, operate: Opcode::AND, addrmode: AddressingMode::IZX, cycles: 6 },
-        Instruction { hexcode: 0x22, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x22, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x23, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x24, operate: Opcode::BIT, addrmode: AddressingMode::ZP0, cycles: 3 },
         Instruction { hexcode: 0x25, operate: Opcode::AND, addrmode: AddressingMode::ZP0, cycles: 3 },
@@ -138,7 +140,7 @@ pub mod references {
 
         Instruction { hexcode: 0x30, operate: Opcode::BMI, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0x31, operate: Opcode::AND, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0x32, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x32, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x33, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x34, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0x35, operate: Opcode::AND, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -155,7 +157,7 @@ pub mod references {
 
         Instruction { hexcode: 0x40, operate: Opcode::RTI, addrmode: AddressingMode::IMP, cycles: 6 },
         Instruction { hexcode: 0x41, operate: Opcode::EOR, addrmode: AddressingMode::IZX, cycles: 6 },
-        Instruction { hexcode: 0x42, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x42, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x43, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x44, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 3 },
         Instruction { hexcode: 0x45, operate: Opcode::EOR, addrmode: AddressingMode::ZP0, cycles: 3 },  
@@ -172,7 +174,7 @@ pub mod references {
 
         Instruction { hexcode: 0x50, operate: Opcode::BVC, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0x51, operate: Opcode::EOR, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0x52, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x52, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x53, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x54, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0x55, operate: Opcode::EOR, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -189,7 +191,7 @@ pub mod references {
 
         Instruction { hexcode: 0x60, operate: Opcode::RTS, addrmode: AddressingMode::IMP, cycles: 6 },
         Instruction { hexcode: 0x61, operate: Opcode::ADC, addrmode: AddressingMode::IZX, cycles: 6 },
-        Instruction { hexcode: 0x62, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x62, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x63, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x64, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 3 },
         Instruction { hexcode: 0x65, operate: Opcode::ADC, addrmode: AddressingMode::ZP0, cycles: 3 },
@@ -206,7 +208,7 @@ pub mod references {
 
         Instruction { hexcode: 0x70, operate: Opcode::BVS, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0x71, operate: Opcode::ADC, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0x72, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x72, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x73, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0x74, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0x75, operate: Opcode::ADC, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -240,7 +242,7 @@ pub mod references {
 
         Instruction { hexcode: 0x90, operate: Opcode::BCC, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0x91, operate: Opcode::STA, addrmode: AddressingMode::IZY, cycles: 6 },
-        Instruction { hexcode: 0x92, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0x92, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0x93, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 6 },
         Instruction { hexcode: 0x94, operate: Opcode::STY, addrmode: AddressingMode::ZPX, cycles: 4 },
         Instruction { hexcode: 0x95, operate: Opcode::STA, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -274,7 +276,7 @@ pub mod references {
 
         Instruction { hexcode: 0xB0, operate: Opcode::BCS, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0xB1, operate: Opcode::LDA, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0xB2, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0xB2, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0xB3, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 5 },
         Instruction { hexcode: 0xB4, operate: Opcode::LDY, addrmode: AddressingMode::ZPX, cycles: 4 },
         Instruction { hexcode: 0xB5, operate: Opcode::LDA, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -308,7 +310,7 @@ pub mod references {
 
         Instruction { hexcode: 0xD0, operate: Opcode::BNE, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0xD1, operate: Opcode::CMP, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0xD2, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0xD2, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0xD3, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0xD4, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0xD5, operate: Opcode::CMP, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -342,7 +344,7 @@ pub mod references {
 
         Instruction { hexcode: 0xF0, operate: Opcode::BEQ, addrmode: AddressingMode::REL, cycles: 2 },
         Instruction { hexcode: 0xF1, operate: Opcode::SBC, addrmode: AddressingMode::IZY, cycles: 5 },
-        Instruction { hexcode: 0xF2, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 2 },
+        Instruction { hexcode: 0xF2, operate: Opcode::JAM, addrmode: AddressingMode::IMP, cycles: 2 },
         Instruction { hexcode: 0xF3, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 8 },
         Instruction { hexcode: 0xF4, operate: Opcode::XXX, addrmode: AddressingMode::IMP, cycles: 4 },
         Instruction { hexcode: 0xF5, operate: Opcode::SBC, addrmode: AddressingMode::ZPX, cycles: 4 },
@@ -1,4 +1,8 @@
-use crate::cartridge::Mirroring;
+use crate::cartridge::{Mirroring, Region};
+use crate::mapper::MapperRef;
+use crate::savestate::{StateReader, StateWriter};
+use std::cell::Cell;
+use std::sync::mpsc::Sender;
 
 pub enum PPUStatusFlags {
     SpriteOverflow = (1 << 5),
@@ -20,6 +24,17 @@ pub enum PPUControlFlags {
 const SCREEN_WIDTH: usize = 256;
 const SCREEN_HEIGHT: usize = 240;
 
+// The I/O latch decays back to 0 after roughly one frame without being refreshed
+const OPEN_BUS_DECAY_CYCLES: u32 = 341 * 262;
+
+// Writes to $2000/$2001/$2005/$2006 are ignored for ~29658 CPU cycles after power-on (~33132 on PAL).
+// Counted in PPU cycles.
+const WARM_UP_CYCLES: u32 = 29658 * 3;
+const PAL_WARM_UP_CYCLES: u32 = 33132 * 16 / 5;
+
+// A12 has to stay low this many PPU cycles before a rising edge is counted (MMC3 filter)
+const A12_FILTER_CYCLES: u32 = 8;
+
 // In the format of (R,G,B)
 pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x80, 0x80, 0x80),
@@ -88,8 +103,194 @@ pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x11, 0x11, 0x11),
 ];
 
+// Measured output of an RP2C02 (NTSC) PPU
+pub static PALETTE_2C02: [(u8, u8, u8); 64] = [
+    (0x54, 0x54, 0x54),
+    (0x00, 0x1E, 0x74),
+    (0x08, 0x10, 0x90),
+    (0x30, 0x00, 0x88),
+    (0x44, 0x00, 0x64),
+    (0x5C, 0x00, 0x30),
+    (0x54, 0x04, 0x00),
+    (0x3C, 0x18, 0x00),
+    (0x20, 0x2A, 0x00),
+    (0x08, 0x3A, 0x00),
+    (0x00, 0x40, 0x00),
+    (0x00, 0x3C, 0x00),
+    (0x00, 0x32, 0x3C),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x98, 0x96, 0x98),
+    (0x08, 0x4C, 0xC4),
+    (0x30, 0x32, 0xEC),
+    (0x5C, 0x1E, 0xE4),
+    (0x88, 0x14, 0xB0),
+    (0xA0, 0x14, 0x64),
+    (0x98, 0x22, 0x20),
+    (0x78, 0x3C, 0x00),
+    (0x54, 0x5A, 0x00),
+    (0x28, 0x72, 0x00),
+    (0x08, 0x7C, 0x00),
+    (0x00, 0x76, 0x28),
+    (0x00, 0x66, 0x78),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xEC, 0xEE, 0xEC),
+    (0x4C, 0x9A, 0xEC),
+    (0x78, 0x7C, 0xEC),
+    (0xB0, 0x62, 0xEC),
+    (0xE4, 0x54, 0xEC),
+    (0xEC, 0x58, 0xB4),
+    (0xEC, 0x6A, 0x64),
+    (0xD4, 0x88, 0x20),
+    (0xA0, 0xAA, 0x00),
+    (0x74, 0xC4, 0x00),
+    (0x4C, 0xD0, 0x20),
+    (0x38, 0xCC, 0x6C),
+    (0x38, 0xB4, 0xCC),
+    (0x3C, 0x3C, 0x3C),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xEC, 0xEE, 0xEC),
+    (0xA8, 0xCC, 0xEC),
+    (0xBC, 0xBC, 0xEC),
+    (0xD4, 0xB2, 0xEC),
+    (0xEC, 0xAE, 0xEC),
+    (0xEC, 0xAE, 0xD4),
+    (0xEC, 0xB4, 0xB0),
+    (0xE4, 0xC4, 0x90),
+    (0xCC, 0xD2, 0x78),
+    (0xB4, 0xDE, 0x78),
+    (0xA8, 0xE2, 0x90),
+    (0x98, 0xE2, 0xB4),
+    (0xA0, 0xD6, 0xE4),
+    (0xA0, 0xA2, 0xA0),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+];
+
+// Built-in palettes that can be switched at runtime without a .pal file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PalettePreset {
+    Default,
+    Ppu2C02,
+    Grayscale,
+}
+
+impl PalettePreset {
+    pub const ALL: [PalettePreset; 3] = [PalettePreset::Default, PalettePreset::Ppu2C02, PalettePreset::Grayscale];
+
+    pub fn colors(self) -> [(u8, u8, u8); 64] {
+        match self {
+            PalettePreset::Default => SYSTEM_PALLETE,
+            PalettePreset::Ppu2C02 => PALETTE_2C02,
+            // Luma of the default palette
+            PalettePreset::Grayscale => SYSTEM_PALLETE.map(|(r, g, b)| {
+                let luma = ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8;
+                (luma, luma, luma)
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for PalettePreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PalettePreset::Default => write!(f, "Default"),
+            PalettePreset::Ppu2C02 => write!(f, "2C02"),
+            PalettePreset::Grayscale => write!(f, "Grayscale"),
+        }
+    }
+}
+
+// .pal files are 64 RGB triplets. Files with the 8 emphasis variants appended
+// (512 entries) are accepted too, only the first 64 entries are used.
+pub fn load_pal_file(path: &str) -> Result<[(u8, u8, u8); 64], String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    if data.len() != 64 * 3 && data.len() != 512 * 3 {
+        return Err(format!("Expected a 192 byte palette, got {} bytes", data.len()));
+    }
+
+    let mut palette = [(0, 0, 0); 64];
+    for (index, color) in palette.iter_mut().enumerate() {
+        *color = (data[index * 3], data[index * 3 + 1], data[index * 3 + 2]);
+    }
+    Ok(palette)
+}
+
+pub fn save_pal_file(path: &str, palette: &[(u8, u8, u8); 64]) -> Result<(), String> {
+    let data: Vec<u8> = palette.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+// Where a pixel of the current frame came from, recorded while debug_provenance is set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelProvenance {
+    pub background_color: u8, // 0 is transparent
+    pub sprite: Option<u8>,   // OAM index of the opaque sprite pixel at this position
+    pub sprite_in_front: bool, // The sprite won the priority decision
+    pub sprite_zero_hit: bool,
+}
+
+// Fetched background tile, reused for the remaining pixels of the tile on the same line
+#[derive(Debug, Clone, Copy)]
+struct BackgroundTile {
+    nametable_addr: u16,
+    pattern_row: u16, // Pattern table and fine Y the planes were fetched for
+    plane_low: u8,
+    plane_high: u8,
+    palette_select: u8,
+}
+
+// Register values in effect when a visible scanline started drawing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScanlineRegisters {
+    pub control: u8,
+    pub mask: u8,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub nametable: u8,
+}
+
+// Decoded OAM entry, for debugging views
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpriteInfo {
+    pub x: u8,
+    pub y: u8,
+    pub tile_index: u8,
+    pub palette: u8, // 0-3, sprite sub-palette
+    pub behind_background: bool,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub pattern_addr: u16, // Address of the top tile in 8x16 mode
+    pub on_screen: bool,
+}
+
+// Sprite evaluation walking primary OAM into secondary OAM during dots 65-256, one step per dot
+#[derive(Clone, Copy, Default)]
+struct SpriteEvaluation {
+    sprite: usize, // n, the primary OAM sprite being looked at
+    byte: usize, // m, byte within that sprite
+    secondary_index: usize, // Next secondary OAM byte to write, 32 once it's full
+    data: u8, // Byte read from OAM on the odd dot, handled on the even dot
+    done: bool,
+}
+
+// A sprite selected for the current scanline, with its pattern row already fetched
+#[derive(Clone, Copy)]
+struct ScanlineSprite {
+    x: u8,
+    pattern_addr: u16,
+    plane_low: u8,
+    plane_high: u8,
+    attributes: u8,
+    oam_index: u8,
+}
+
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
+    mapper: MapperRef, // Pattern tables live on the cartridge
     pub vram: Vec<u8>,
     pub oam: [u8; 256],
     pub palette: [u8; 32],
@@ -108,31 +309,83 @@ pub struct PPU {
     // Data Buffer
     pub data_buffer: u8,
 
+    // Open bus: last value driven onto the PPU's I/O bus
+    pub io_latch: u8,
+    io_latch_decay: u32,
+
     pub mirroring: Mirroring,
 
     // Miscs
     pub scanline: u16,
     pub cycle: u16,
-
-    pub frame_complete: bool,
-    pub frame_buffer: Vec<u8>,
+    pub region: Region,
+    odd_frame: bool,
+
+    frame_complete: bool,
+    pub frame_count: u64, // Frames completed since power on
+    // RGBA frames: the PPU draws into the back buffer, which is swapped to the front when a frame completes
+    back_buffer: Vec<u8>,
+    front_buffer: Vec<u8>,
+    completed_frame_ready: bool,
+    // Per-line register snapshots, swapped along with the frame buffers
+    line_registers: [ScanlineRegisters; SCREEN_HEIGHT],
+    completed_line_registers: [ScanlineRegisters; SCREEN_HEIGHT],
     background_index_buffer: Vec<u8>,
+    background_tile: Cell<Option<BackgroundTile>>,
+    pub debug_provenance: bool,
+    // Debugger: stop the CPU or force an NMI when this scanline starts, regardless of what the ROM does
+    pub break_scanline: Option<u16>,
+    pub nmi_scanline: Option<u16>,
+    pub scanline_break: bool, // Raised at the start of break_scanline, the CPU turns it into a breakpoint
+    // Frame skip: when false no pixels are produced, timing and flags still run
+    pub render_enabled: bool,
+    // Recording: gets an RGB copy of every completed frame, and forces pixels on through frame skip
+    pub frame_sink: Option<Sender<Vec<u8>>>,
+    pub unmapped_chr_reads: Cell<u64>, // Pattern table reads the mapper had nothing for
+    provenance: Vec<PixelProvenance>,
+    scanline_sprites: Vec<ScanlineSprite>,
+    secondary_oam: [u8; 32],
+    secondary_oam_sprites: [u8; 8], // Primary OAM index of each secondary OAM slot, for sprite zero and debugging
+    sprite_evaluation: SpriteEvaluation,
+
+    pub system_palette: [(u8, u8, u8); 64], // RGB output of each of the 64 NES colors
+    pub sprite_overflow_bug: bool, // Emulate the buggy overflow evaluation instead of a plain 9th sprite check
+    // Accurate raises sprite zero hit at the exact dot of the overlap, fast checks each line once it's drawn
+    pub accurate_sprite_zero_hit: bool,
+    // No-flicker mode when false: every sprite on the line is drawn, the overflow flag still acts as if limited
+    pub sprite_limit_enabled: bool,
 
-    chr_is_ram: bool,
     oam_addr: u8,
     scroll_x: u8,
     scroll_y: u8,
+
+    // Scroll state used for rendering, copied from the written scroll like t -> v
+    render_scroll_x: u8,
+    render_scroll_y: u8,
+    render_nametable: u8,
+
+    warm_up_cycles: u32,
+    pub warm_up_enabled: bool, // Off skips the warm-up entirely, for ROMs that don't wait for it
+
+    // A12 rising edges as (scanline, cycle), consumed by the mapper
+    a12_low_cycles: u32,
+    a12_edges: Vec<(u16, u16)>,
+
+    // Hblank notifications as (scanline, rendering enabled), raised at dot 260 of every visible
+    // and pre-render line. Only collected while someone polls them.
+    pub scanline_events_enabled: bool,
+    scanline_events: Vec<(u16, bool)>,
 }
 
 impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring, chr_is_ram: bool) -> PPU {
+    pub fn new(mapper: MapperRef, mirroring: Mirroring, region: Region) -> PPU {
         let vram_size = match mirroring {
             Mirroring::FourScreen => 0x1000,
             _ => 0x0800,
         };
 
         PPU {
-            chr_rom,
+            mapper,
             vram: vec![0; vram_size],
             oam: [0xFF; 256],
             palette: [0; 32],
@@ -148,19 +401,59 @@ impl PPU {
 
             data_buffer: 0,
 
+            io_latch: 0,
+            io_latch_decay: 0,
+
             mirroring,
 
             scanline: 0,
             cycle: 0,
+            region,
+            odd_frame: false,
 
             frame_complete: false,
-            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            frame_count: 0,
+            back_buffer: [0, 0, 0, 0xFF].repeat(SCREEN_WIDTH * SCREEN_HEIGHT),
+            front_buffer: [0, 0, 0, 0xFF].repeat(SCREEN_WIDTH * SCREEN_HEIGHT),
+            completed_frame_ready: false,
+            line_registers: [ScanlineRegisters::default(); SCREEN_HEIGHT],
+            completed_line_registers: [ScanlineRegisters::default(); SCREEN_HEIGHT],
             background_index_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            background_tile: Cell::new(None),
+            debug_provenance: false,
+            break_scanline: None,
+            nmi_scanline: None,
+            scanline_break: false,
+            render_enabled: true,
+            frame_sink: None,
+            unmapped_chr_reads: Cell::new(0),
+            provenance: vec![PixelProvenance::default(); SCREEN_WIDTH * SCREEN_HEIGHT],
+            scanline_sprites: Vec::with_capacity(8),
+            secondary_oam: [0xFF; 32],
+            secondary_oam_sprites: [0; 8],
+            sprite_evaluation: SpriteEvaluation::default(),
+
+            system_palette: SYSTEM_PALLETE,
+            sprite_overflow_bug: true,
+            sprite_limit_enabled: true,
+            accurate_sprite_zero_hit: true,
 
-            chr_is_ram,
             oam_addr: 0,
             scroll_x: 0,
             scroll_y: 0,
+
+            render_scroll_x: 0,
+            render_scroll_y: 0,
+            render_nametable: 0,
+
+            warm_up_cycles: if region == Region::Pal { PAL_WARM_UP_CYCLES } else { WARM_UP_CYCLES },
+            warm_up_enabled: true,
+
+            a12_low_cycles: 0,
+            a12_edges: Vec::new(),
+
+            scanline_events_enabled: false,
+            scanline_events: Vec::new(),
         }
     }
 
@@ -171,16 +464,134 @@ impl PPU {
         self.mask_register = 0;
         self.status_register = 0;
         self.data_buffer = 0;
+        self.io_latch = 0;
+        self.io_latch_decay = 0;
         self.oam.fill(0xFF);
         self.scanline = 0;
         self.cycle = 0;
+        self.odd_frame = false;
         self.nmi = false;
         self.frame_complete = false;
         self.oam_addr = 0;
         self.scroll_x = 0;
         self.scroll_y = 0;
-        self.frame_buffer.fill(0);
+        self.render_scroll_x = 0;
+        self.render_scroll_y = 0;
+        self.render_nametable = 0;
+        self.warm_up_cycles = self.warm_up_length();
+        self.a12_low_cycles = 0;
+        self.a12_edges.clear();
+        self.scanline_events.clear();
+        for buffer in [&mut self.back_buffer, &mut self.front_buffer] {
+            for pixel in buffer.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[0, 0, 0, 0xFF]);
+            }
+        }
+        self.completed_frame_ready = false;
         self.background_index_buffer.fill(0);
+        self.scanline_sprites.clear();
+        self.secondary_oam.fill(0xFF);
+        self.sprite_evaluation = SpriteEvaluation::default();
+    }
+
+    // Save States
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.palette);
+
+        writer.write_u16(self.address_register);
+        writer.write_bool(self.address_latch);
+        writer.write_u8(self.control_register);
+        writer.write_bool(self.nmi);
+        writer.write_u8(self.mask_register);
+        writer.write_u8(self.status_register);
+        writer.write_u8(self.data_buffer);
+        writer.write_u8(self.io_latch);
+        writer.write_u32(self.io_latch_decay);
+
+        writer.write_u16(self.scanline);
+        writer.write_u16(self.cycle);
+        writer.write_bool(self.odd_frame);
+        writer.write_u64(self.frame_count);
+        writer.write_bytes(&self.back_buffer);
+        writer.write_bytes(&self.front_buffer);
+
+        writer.write_u8(self.oam_addr);
+        writer.write_u8(self.scroll_x);
+        writer.write_u8(self.scroll_y);
+        writer.write_u8(self.render_scroll_x);
+        writer.write_u8(self.render_scroll_y);
+        writer.write_u8(self.render_nametable);
+        writer.write_u32(self.warm_up_cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.vram)?;
+        reader.read_into(&mut self.oam)?;
+        reader.read_into(&mut self.palette)?;
+
+        self.address_register = reader.read_u16()?;
+        self.address_latch = reader.read_bool()?;
+        self.control_register = reader.read_u8()?;
+        self.nmi = reader.read_bool()?;
+        self.mask_register = reader.read_u8()?;
+        self.status_register = reader.read_u8()?;
+        self.data_buffer = reader.read_u8()?;
+        self.io_latch = reader.read_u8()?;
+        self.io_latch_decay = reader.read_u32()?;
+
+        self.scanline = reader.read_u16()?;
+        self.cycle = reader.read_u16()?;
+        self.odd_frame = reader.read_bool()?;
+        self.frame_count = reader.read_u64()?;
+        reader.read_into(&mut self.back_buffer)?;
+        reader.read_into(&mut self.front_buffer)?;
+
+        self.oam_addr = reader.read_u8()?;
+        self.scroll_x = reader.read_u8()?;
+        self.scroll_y = reader.read_u8()?;
+        self.render_scroll_x = reader.read_u8()?;
+        self.render_scroll_y = reader.read_u8()?;
+        self.render_nametable = reader.read_u8()?;
+        self.warm_up_cycles = reader.read_u32()?;
+
+        self.frame_complete = false;
+        self.completed_frame_ready = true;
+        self.scanline_sprites.clear();
+        self.secondary_oam.fill(0xFF);
+        self.sprite_evaluation = SpriteEvaluation::default();
+        self.a12_edges.clear();
+        self.scanline_events.clear();
+        Ok(())
+    }
+
+    // Timing
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        if self.scanline > self.pre_render_scanline() {
+            self.scanline = 0;
+        }
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self.region {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    // PAL has a 70 scanline vblank right after the post-render line,
+    // Dendy keeps NTSC's 20 scanline vblank and pads the post-render period instead
+    pub fn vblank_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
     }
 
     // Mirroring
@@ -200,11 +611,26 @@ impl PPU {
                 _ => vram_index,
             },
             Mirroring::FourScreen => vram_index,
+            Mirroring::OneScreenLower => vram_index & 0x03FF,
+            Mirroring::OneScreenUpper => 0x0400 | (vram_index & 0x03FF),
+        }
+    }
+
+    // Mappers switch the nametable layout at runtime, VRAM contents stay where they are
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if mirroring == Mirroring::FourScreen && self.vram.len() < 0x1000 {
+            self.vram.resize(0x1000, 0);
         }
+        self.mirroring = mirroring;
     }
 
     // Address Register
     pub fn write_to_address_register(&mut self, data: u8) {
+        self.write_io_latch(data);
+        if self.warming_up() {
+            return;
+        }
+
         if self.address_latch {
             self.address_register = (self.address_register & 0x00FF) | ((data as u16) << 8);
         } else {
@@ -226,9 +652,26 @@ impl PPU {
 
     // Control Register
     pub fn write_to_control_register(&mut self, data: u8) {
+        self.write_io_latch(data);
+        if self.warming_up() {
+            return;
+        }
+
         self.control_register = data;
     }
 
+    pub fn warming_up(&self) -> bool {
+        self.warm_up_cycles > 0
+    }
+
+    fn warm_up_length(&self) -> u32 {
+        match (self.warm_up_enabled, self.region) {
+            (false, _) => 0,
+            (true, Region::Pal) => PAL_WARM_UP_CYCLES,
+            (true, _) => WARM_UP_CYCLES,
+        }
+    }
+
     pub fn get_control_flag(&self, flag: PPUControlFlags) -> bool {
         self.control_register & (flag as u8) != 0
     }
@@ -245,7 +688,9 @@ impl PPU {
 
     // Status register
     pub fn read_status_register(&mut self) -> u8 {
-        let status = (self.status_register & 0xE0) | (self.data_buffer & 0x1F); // Noise
+        // Only the top 3 bits are driven, the rest comes from the open bus
+        let status = (self.status_register & 0xE0) | (self.io_latch & 0x1F);
+        self.write_io_latch(status);
         self.set_status_flag(PPUStatusFlags::VerticalBlank, false);
         self.address_latch = true;
         status
@@ -260,11 +705,30 @@ impl PPU {
     }
 
     // Mask Register
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask_register & 0x18 != 0
+    }
+
+    // True while the PPU is fetching for the visible or pre-render scanlines
+    pub fn is_rendering(&self) -> bool {
+        self.rendering_enabled() && (self.scanline < 240 || self.scanline == self.pre_render_scanline())
+    }
+
     pub fn write_to_mask_register(&mut self, data: u8) {
+        self.write_io_latch(data);
+        if self.warming_up() {
+            return;
+        }
+
         self.mask_register = data;
     }
 
     pub fn write_to_scroll_register(&mut self, data: u8) {
+        self.write_io_latch(data);
+        if self.warming_up() {
+            return;
+        }
+
         if self.address_latch {
             self.scroll_x = data;
         } else {
@@ -274,26 +738,63 @@ impl PPU {
     }
 
     pub fn write_to_oam_address(&mut self, data: u8) {
+        self.write_io_latch(data);
         self.oam_addr = data;
     }
 
+    // Wraps from $FF back to $00. During rendering OAM is busy with sprite evaluation,
+    // the write is dropped and only the sprite index part of the address moves (a glitchy +4).
     pub fn write_to_oam_data(&mut self, data: u8) {
+        self.write_io_latch(data);
+        if self.is_rendering() {
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
+
         self.oam[self.oam_addr as usize] = data;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
-    pub fn read_oam_data(&self) -> u8 {
-        self.oam[self.oam_addr as usize]
+    // Reads never move oam_addr
+    pub fn read_oam_data(&mut self) -> u8 {
+        let data = if self.is_rendering() && self.scanline < 240 && (1..=64).contains(&self.cycle) {
+            // Secondary OAM is being cleared during these cycles, which reads back as 0xFF
+            0xFF
+        } else if self.oam_addr & 0x03 == 0x02 {
+            // Bits 2-4 of the attribute byte don't exist in hardware
+            self.oam[self.oam_addr as usize] & 0xE3
+        } else {
+            self.oam[self.oam_addr as usize]
+        };
+        self.write_io_latch(data);
+        data
+    }
+
+    // Open Bus
+    pub fn write_io_latch(&mut self, data: u8) {
+        self.io_latch = data;
+        self.io_latch_decay = OPEN_BUS_DECAY_CYCLES;
+    }
+
+    pub fn read_io_latch(&self) -> u8 {
+        self.io_latch
     }
 
     // PPU Read & Write
     pub fn read_data(&mut self) -> u8 {
-        let addr = self.address_register;
+        let addr = self.address_register & 0x3FFF;
         let data = match addr {
             0x3F00..=0x3FFF => {
-                let value = self.ppu_read(addr);
+                // Palette entries are 6 bits wide, the top 2 bits come from the open bus.
+                // Greyscale mode masks the value read back the same way it masks the output.
+                let mut value = self.ppu_read(addr) & 0x3F;
+                if self.mask_register & 0x01 != 0 {
+                    value &= 0x30;
+                }
+
+                // The buffer gets the nametable byte "underneath" the palette ($2F00-$2FFF)
                 self.data_buffer = self.ppu_read(addr - 0x1000);
-                value
+                value | (self.io_latch & 0xC0)
             }
             _ => {
                 let value = self.data_buffer;
@@ -302,21 +803,30 @@ impl PPU {
             }
         };
 
+        self.write_io_latch(data);
         self.increment_vram_addr();
         data
     }
 
     pub fn write_data(&mut self, data: u8) {
+        self.write_io_latch(data);
         let addr = self.address_register;
         self.ppu_write(addr, data);
+        self.background_tile.set(None);
         self.increment_vram_addr();
     }
 
     fn ppu_read(&self, addr: u16) -> u8 {
         let addr = addr & 0x3FFF;
         match addr {
-            0x0000..=0x1FFF => self.chr_rom[addr as usize],
+            0x0000..=0x1FFF => {
+                let data = self.mapper.borrow_mut().ppu_read(addr);
+                self.pattern_data(data)
+            }
             0x2000..=0x2FFF => {
+                if let Some(data) = self.mapper.borrow_mut().read_nametable(addr, &self.vram) {
+                    return data;
+                }
                 let index = self.mirror_vram_addr(addr) as usize;
                 self.vram[index]
             }
@@ -336,19 +846,36 @@ impl PPU {
         }
     }
 
+    // Unmapped pattern reads come back as 0 and are counted for the Unmapped Accesses view
+    fn pattern_data(&self, data: Option<u8>) -> u8 {
+        data.unwrap_or_else(|| {
+            self.unmapped_chr_reads.set(self.unmapped_chr_reads.get() + 1);
+            0
+        })
+    }
+
+    // Sprite pattern fetch, which MMC5 serves from a separate set of CHR banks
+    fn sprite_pattern_read(&self, addr: u16) -> u8 {
+        let data = self.mapper.borrow_mut().ppu_read_sprite(addr & 0x1FFF);
+        self.pattern_data(data)
+    }
+
     fn ppu_write(&mut self, addr: u16, data: u8) {
         let addr = addr & 0x3FFF;
         match addr {
             0x0000..=0x1FFF => {
-                if self.chr_is_ram {
-                    self.chr_rom[addr as usize] = data;
-                }
+                self.mapper.borrow_mut().ppu_write(addr, data);
             }
             0x2000..=0x2FFF => {
+                if self.mapper.borrow_mut().write_nametable(addr, data, &mut self.vram) {
+                    return;
+                }
                 let index = self.mirror_vram_addr(addr) as usize;
                 self.vram[index] = data;
             }
             0x3000..=0x3EFF => self.ppu_write(addr - 0x1000, data),
+            // Pixels look their color up as they're drawn, so a write during rendering
+            // changes everything drawn after it in the same frame
             0x3F00..=0x3FFF => {
                 let mut palette_index = (addr - 0x3F00) % 32;
                 palette_index = match palette_index {
@@ -364,208 +891,824 @@ impl PPU {
         }
     }
 
-    fn set_frame_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
-            return;
+    // Frame Output
+    // Last completed frame as RGBA (alpha = 255). This is the preferred way to read the picture.
+    pub fn completed_frame(&self) -> &[u8] {
+        &self.front_buffer
+    }
+
+    // Returns the completed frame only once after it finished, None if no new frame is ready
+    pub fn take_completed_frame(&mut self) -> Option<&[u8]> {
+        if !self.completed_frame_ready {
+            return None;
         }
 
-        let index = (y * SCREEN_WIDTH + x) * 3;
-        self.frame_buffer[index] = rgb.0;
-        self.frame_buffer[index + 1] = rgb.1;
-        self.frame_buffer[index + 2] = rgb.2;
+        self.completed_frame_ready = false;
+        Some(&self.front_buffer)
     }
 
-    fn render_sprites(&mut self) {
-        if self.mask_register & 0x10 == 0 {
-            return;
-        }
+    // RGBA pixels of one line of the last completed frame
+    pub fn scanline_pixels(&self, line: usize) -> &[u8] {
+        let line = line.min(SCREEN_HEIGHT - 1);
+        &self.front_buffer[line * SCREEN_WIDTH * 4..(line + 1) * SCREEN_WIDTH * 4]
+    }
 
-        let show_leftmost_sprites = self.mask_register & 0x04 != 0;
-        let show_background = self.mask_register & 0x08 != 0;
+    // Registers each line of the last completed frame was drawn with
+    pub fn scanline_registers(&self) -> &[ScanlineRegisters] {
+        &self.completed_line_registers
+    }
 
-        let sprite_height = if self.get_control_flag(PPUControlFlags::SpriteSize) {
-            16
-        } else {
-            8
-        };
+    // Frame currently being drawn, useful when stepping through a frame in the debugger
+    pub fn in_progress_frame(&self) -> &[u8] {
+        &self.back_buffer
+    }
 
-        for sprite_index in 0..64 {
-            let base = sprite_index * 4;
-            let y = self.oam[base] as i16 + 1;
-            let tile_index = self.oam[base + 1];
-            let attributes = self.oam[base + 2];
-            let x = self.oam[base + 3] as i16;
+    // Owned copy of the last completed frame, for callers that want RGBA without borrowing the PPU
+    pub fn frame_buffer_rgba(&self) -> Vec<u8> {
+        self.front_buffer.clone()
+    }
 
-            let flip_h = attributes & 0x40 != 0;
-            let flip_v = attributes & 0x80 != 0;
-            let behind_background = attributes & 0x20 != 0;
-            let palette_index = attributes & 0x03;
+    // Packed RGB copy of the last completed frame, kept for compatibility
+    pub fn frame_buffer_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        for pixel in self.front_buffer.chunks_exact(4) {
+            rgb.extend_from_slice(&pixel[..3]);
+        }
+        rgb
+    }
 
-            for row in 0..sprite_height {
-                let row_index = if flip_v {
-                    sprite_height - 1 - row
-                } else {
-                    row
-                };
+    fn send_completed_frame(&mut self) {
+        let Some(sink) = &self.frame_sink else {
+            return;
+        };
 
-                let (pattern_table, tile) = if sprite_height == 16 {
-                    let table = if tile_index & 0x01 == 0 { 0x0000 } else { 0x1000 };
-                    let mut tile = tile_index & 0xFE;
-                    let mut row_in_tile = row_index;
-                    if row_in_tile >= 8 {
-                        tile = tile.wrapping_add(1);
-                        row_in_tile -= 8;
-                    }
-                    (table, (tile, row_in_tile))
-                } else {
-                    let table = if self.get_control_flag(PPUControlFlags::PatternSprite) {
-                        0x1000
-                    } else {
-                        0x0000
-                    };
-                    (table, (tile_index, row_index))
-                };
+        let mut rgb = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        for pixel in self.front_buffer.chunks_exact(4) {
+            rgb.extend_from_slice(&pixel[..3]);
+        }
+        // The recorder went away (write error), stop producing frames for it
+        if sink.send(rgb).is_err() {
+            self.frame_sink = None;
+        }
+    }
 
-                let tile_addr = pattern_table + (tile.0 as u16) * 16 + tile.1 as u16;
-                let plane_low = self.ppu_read(tile_addr);
-                let plane_high = self.ppu_read(tile_addr + 8);
+    // Switches the RGB output of the 64 NES colors. The frames already drawn are recolored too,
+    // so a paused picture shows the new palette right away.
+    pub fn set_system_palette(&mut self, palette: [(u8, u8, u8); 64]) {
+        let previous = self.system_palette;
+        for buffer in [&mut self.back_buffer, &mut self.front_buffer] {
+            for pixel in buffer.chunks_exact_mut(4) {
+                let rgb = (pixel[0], pixel[1], pixel[2]);
+                if let Some(index) = previous.iter().position(|&color| color == rgb) {
+                    let (r, g, b) = palette[index];
+                    pixel[..3].copy_from_slice(&[r, g, b]);
+                }
+            }
+        }
+        self.system_palette = palette;
+        self.completed_frame_ready = true;
+    }
 
-                for col in 0..8 {
-                    let bit = if flip_h { col } else { 7 - col };
-                    let color_low = (plane_low >> bit) & 0x01;
-                    let color_high = (plane_high >> bit) & 0x01;
-                    let color = (color_high << 1) | color_low;
-                    if color == 0 {
-                        continue;
-                    }
+    fn set_frame_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return;
+        }
 
-                    let palette_addr = 0x3F10 + (palette_index as u16) * 4 + color as u16;
-                    let palette_value = self.ppu_read(palette_addr) & 0x3F;
-                    let rgb = SYSTEM_PALLETE[palette_value as usize];
+        let index = (y * SCREEN_WIDTH + x) * 4;
+        self.back_buffer[index] = rgb.0;
+        self.back_buffer[index + 1] = rgb.1;
+        self.back_buffer[index + 2] = rgb.2;
+    }
 
-                    let pixel_x = x + col as i16;
-                    let pixel_y = y + row as i16;
-                    if pixel_x < 0 || pixel_y < 0 {
-                        continue;
-                    }
+    pub fn sprite_height(&self) -> u16 {
+        if self.get_control_flag(PPUControlFlags::SpriteSize) {
+            16
+        } else {
+            8
+        }
+    }
 
-                    let pixel_x = pixel_x as usize;
-                    let pixel_y = pixel_y as usize;
-                    if pixel_x >= SCREEN_WIDTH || pixel_y >= SCREEN_HEIGHT {
-                        continue;
-                    }
+    // Sprite pipeline for the next scanline, run on every rendering dot of the visible lines:
+    // dots 1-64 clear secondary OAM, 65-256 evaluate primary OAM into it, 257-320 fetch pattern rows.
+    fn sprite_pipeline(&mut self) {
+        match self.cycle {
+            1..=64 => {
+                if self.cycle.is_multiple_of(2) {
+                    self.secondary_oam[(self.cycle / 2 - 1) as usize] = 0xFF;
+                }
+                if self.cycle == 64 {
+                    self.sprite_evaluation = SpriteEvaluation::default();
+                }
+            }
+            65..=256 => {
+                if self.cycle % 2 == 1 {
+                    self.read_sprite_evaluation_byte();
+                } else {
+                    self.step_sprite_evaluation();
+                }
+            }
+            257..=320 => {
+                // OAMADDR is held at 0 while the sprite patterns are fetched
+                self.oam_addr = 0;
+                if self.cycle == 257 {
+                    self.scanline_sprites.clear();
+                }
+                if (self.cycle - 257).is_multiple_of(8) {
+                    let slot = ((self.cycle - 257) / 8) as usize;
+                    self.fetch_sprite_slot(slot);
+                }
+                if self.cycle == 320 && !self.sprite_limit_enabled {
+                    self.fetch_extra_sprites();
+                }
+            }
+            _ => {}
+        }
+    }
 
-                    if pixel_x < 8 && !show_leftmost_sprites {
-                        continue;
-                    }
+    fn read_sprite_evaluation_byte(&mut self) {
+        let evaluation = &mut self.sprite_evaluation;
+        if !evaluation.done {
+            evaluation.data = self.oam[evaluation.sprite * 4 + evaluation.byte];
+        }
+    }
 
-                    if behind_background && show_background {
-                        let bg_color =
-                            self.background_index_buffer[pixel_y * SCREEN_WIDTH + pixel_x];
-                        if bg_color != 0 {
-                            continue;
-                        }
-                    }
+    // Even dot of evaluation. Until secondary OAM is full every Y is copied, and an in range sprite
+    // gets its other 3 bytes copied too. After that the scan only looks for a 9th sprite.
+    fn step_sprite_evaluation(&mut self) {
+        let mut evaluation = self.sprite_evaluation;
+        if evaluation.done {
+            return;
+        }
 
-                    self.set_frame_pixel(pixel_x, pixel_y, rgb);
+        let in_range = self.sprite_in_range(evaluation.data as u16, self.sprite_height());
+        if evaluation.secondary_index < self.secondary_oam.len() {
+            self.secondary_oam[evaluation.secondary_index] = evaluation.data;
+            if evaluation.byte == 0 && !in_range {
+                // Y lands in secondary OAM but doesn't claim the slot
+                evaluation.sprite += 1;
+            } else {
+                if evaluation.byte == 0 {
+                    self.secondary_oam_sprites[evaluation.secondary_index / 4] = evaluation.sprite as u8;
                 }
+                evaluation.secondary_index += 1;
+                evaluation.byte = (evaluation.byte + 1) & 0x03;
+                if evaluation.byte == 0 {
+                    evaluation.sprite += 1;
+                }
+            }
+        } else if in_range {
+            self.set_status_flag(PPUStatusFlags::SpriteOverflow, true);
+            evaluation.done = true;
+        } else {
+            evaluation.sprite += 1;
+            // Hardware bug: the byte offset is incremented together with the sprite index,
+            // so tile, attribute and X bytes get compared as if they were Y coordinates
+            if self.sprite_overflow_bug {
+                evaluation.byte = (evaluation.byte + 1) & 0x03;
             }
         }
+
+        if evaluation.sprite == 64 {
+            evaluation.done = true;
+        }
+        self.sprite_evaluation = evaluation;
     }
 
-    fn background_pixel_info(&self, x: u16, y: u16) -> ((u8, u8, u8), u8) {
-        let base_nametable = self.control_register & 0x03;
-        let base_x = if base_nametable & 0x01 != 0 { 256 } else { 0 };
-        let base_y = if base_nametable & 0x02 != 0 { 240 } else { 0 };
+    // Fetches the pattern row of one secondary OAM slot for the next scanline
+    fn fetch_sprite_slot(&mut self, slot: usize) {
+        if slot * 4 >= self.sprite_evaluation.secondary_index.min(self.secondary_oam.len()) || self.scanline >= 240 {
+            return;
+        }
 
-        let world_x = x.wrapping_add(self.scroll_x as u16 + base_x);
-        let world_y = y.wrapping_add(self.scroll_y as u16 + base_y);
+        let base = slot * 4;
+        let sprite = self.fetch_sprite(
+            self.secondary_oam[base],
+            self.secondary_oam[base + 1],
+            self.secondary_oam[base + 2],
+            self.secondary_oam[base + 3],
+            self.secondary_oam_sprites[slot],
+        );
+        self.scanline_sprites.push(sprite);
+    }
 
-        let nametable_x = (world_x / 256) % 2;
-        let nametable_y = (world_y / 240) % 2;
-        let nametable_base = 0x2000 + (nametable_y * 2 + nametable_x) * 0x0400;
+    // No-flicker mode: sprites past the 8th are fetched from primary OAM on top of the real pipeline
+    fn fetch_extra_sprites(&mut self) {
+        if self.scanline >= 240 || self.scanline_sprites.len() < 8 {
+            return;
+        }
 
-        let tile_x = (world_x % 256) / 8;
-        let tile_y = (world_y % 240) / 8;
-        let tile_index = self.ppu_read(nametable_base + tile_y * 32 + tile_x);
+        let sprite_height = self.sprite_height();
+        let last_fetched = self.scanline_sprites[7].oam_index as usize;
+        for sprite_index in last_fetched + 1..64 {
+            let base = sprite_index * 4;
+            if !self.sprite_in_range(self.oam[base] as u16, sprite_height) {
+                continue;
+            }
 
-        let attribute_addr = nametable_base + 0x03C0 + (tile_y / 4) * 8 + (tile_x / 4);
-        let attribute = self.ppu_read(attribute_addr);
-        let quadrant_x = (tile_x % 4) / 2;
-        let quadrant_y = (tile_y % 4) / 2;
-        let quadrant = quadrant_y * 2 + quadrant_x;
+            let sprite = self.fetch_sprite(
+                self.oam[base],
+                self.oam[base + 1],
+                self.oam[base + 2],
+                self.oam[base + 3],
+                sprite_index as u8,
+            );
+            self.scanline_sprites.push(sprite);
+        }
+    }
 
-        let palette_select = match quadrant {
-            0 => attribute & 0x03,
-            1 => (attribute >> 2) & 0x03,
-            2 => (attribute >> 4) & 0x03,
-            _ => (attribute >> 6) & 0x03,
-        };
+    fn fetch_sprite(&self, y: u8, tile_index: u8, attributes: u8, x: u8, oam_index: u8) -> ScanlineSprite {
+        let sprite_height = self.sprite_height();
+        let flip_h = attributes & 0x40 != 0;
+        let flip_v = attributes & 0x80 != 0;
 
-        let pattern_table_base = if self.get_control_flag(PPUControlFlags::PatternBackground) {
-            0x1000
+        let row = self.scanline - y as u16;
+        let row_index = if flip_v {
+            sprite_height - 1 - row
         } else {
-            0x0000
+            row
         };
 
-        let fine_y = world_y % 8;
-        let fine_x = world_x % 8;
-        let tile_addr = pattern_table_base + (tile_index as u16) * 16 + fine_y;
+        let tile_addr = self.sprite_row_addr(tile_index, row_index);
+        let mut plane_low = self.sprite_pattern_read(tile_addr);
+        let mut plane_high = self.sprite_pattern_read(tile_addr + 8);
 
-        let plane_low = self.ppu_read(tile_addr);
-        let plane_high = self.ppu_read(tile_addr + 8);
-        let bit = 7 - fine_x;
+        // Keep the leftmost pixel in bit 7
+        if flip_h {
+            plane_low = plane_low.reverse_bits();
+            plane_high = plane_high.reverse_bits();
+        }
 
-        let color_low = (plane_low >> bit) & 0x01;
-        let color_high = (plane_high >> bit) & 0x01;
-        let color = (color_high << 1) | color_low;
+        ScanlineSprite {
+            x,
+            pattern_addr: tile_addr,
+            plane_low,
+            plane_high,
+            attributes,
+            oam_index,
+        }
+    }
 
-        let palette_addr = if color == 0 {
-            0x3F00
+    // Pattern table of a sprite tile. 8x8 sprites use the PatternSprite flag, 8x16 sprites
+    // ignore it and take the table from bit 0 of the tile index instead.
+    fn sprite_pattern_table(&self, tile_index: u8) -> u16 {
+        if self.sprite_height() == 16 {
+            if tile_index & 0x01 == 0 { 0x0000 } else { 0x1000 }
+        } else if self.get_control_flag(PPUControlFlags::PatternSprite) {
+            0x1000
         } else {
-            0x3F00 + ((palette_select << 2) | color) as u16
-        };
+            0x0000
+        }
+    }
 
-        let palette_value = self.ppu_read(palette_addr) & 0x3F;
-        (SYSTEM_PALLETE[palette_value as usize], color)
+    // Pattern address of a row of a sprite, 8x16 sprites continue into the next tile after row 7
+    fn sprite_row_addr(&self, tile_index: u8, row: u16) -> u16 {
+        let table = self.sprite_pattern_table(tile_index);
+        if self.sprite_height() == 16 {
+            let tile = (tile_index & 0xFE) as u16 + row / 8;
+            table + tile * 16 + row % 8
+        } else {
+            table + (tile_index as u16) * 16 + row
+        }
     }
 
-    fn background_pixel(&self, x: u16, y: u16) -> (u8, u8, u8) {
-        self.background_pixel_info(x, y).0
+    pub fn sprite_list(&self) -> [SpriteInfo; 64] {
+        let mut sprites = [SpriteInfo::default(); 64];
+
+        for (index, sprite) in sprites.iter_mut().enumerate() {
+            let base = index * 4;
+            let y = self.oam[base];
+            let tile_index = self.oam[base + 1];
+            let attributes = self.oam[base + 2];
+
+            *sprite = SpriteInfo {
+                x: self.oam[base + 3],
+                y,
+                tile_index,
+                palette: attributes & 0x03,
+                behind_background: attributes & 0x20 != 0,
+                flip_h: attributes & 0x40 != 0,
+                flip_v: attributes & 0x80 != 0,
+                pattern_addr: self.sprite_row_addr(tile_index, 0),
+                // Sprites are drawn one line below their Y, so $EF and up never show
+                on_screen: (y as u16) + 1 < SCREEN_HEIGHT as u16,
+            };
+        }
+
+        sprites
     }
 
-    pub fn clock(&mut self) {
-        if self.scanline < 240 {
-            if (1..=256).contains(&self.cycle) {
-                let x = (self.cycle - 1) as usize;
-                let y = self.scanline as usize;
-                let show_background = self.mask_register & 0x08 != 0;
-                let show_leftmost_background = self.mask_register & 0x02 != 0;
-                let (rgb, bg_color) = if show_background {
-                    if x < 8 && !show_leftmost_background {
-                        let palette_value = self.ppu_read(0x3F00) & 0x3F;
-                        (SYSTEM_PALLETE[palette_value as usize], 0)
-                    } else {
-                        self.background_pixel_info(x as u16, y as u16)
-                    }
+    // Renders one sprite as an 8 pixel wide RGB image, 8 or 16 pixels tall depending on the sprite size.
+    // Transparent pixels use the backdrop color.
+    pub fn render_sprite(&self, index: usize) -> Vec<u8> {
+        let sprite = self.sprite_list()[index & 0x3F];
+        let sprite_height = self.sprite_height();
+        let mut pixels = vec![0; 8 * sprite_height as usize * 3];
+
+        for row in 0..sprite_height {
+            let row_index = if sprite.flip_v { sprite_height - 1 - row } else { row };
+            let tile_addr = self.sprite_row_addr(sprite.tile_index, row_index);
+            let plane_low = self.sprite_pattern_read(tile_addr);
+            let plane_high = self.sprite_pattern_read(tile_addr + 8);
+
+            for col in 0..8u16 {
+                let bit = if sprite.flip_h { col } else { 7 - col };
+                let color = (((plane_high >> bit) & 0x01) << 1) | ((plane_low >> bit) & 0x01);
+                let palette_addr = if color == 0 {
+                    0x3F00
                 } else {
-                    let palette_value = self.ppu_read(0x3F00) & 0x3F;
-                    (SYSTEM_PALLETE[palette_value as usize], 0)
+                    0x3F10 + ((sprite.palette << 2) | color) as u16
                 };
-                self.background_index_buffer[y * SCREEN_WIDTH + x] = bg_color;
-                self.set_frame_pixel(x, y, rgb);
+                let rgb = self.system_palette[(self.ppu_read(palette_addr) & 0x3F) as usize];
+
+                let pixel_index = (row as usize * 8 + col as usize) * 3;
+                pixels[pixel_index] = rgb.0;
+                pixels[pixel_index + 1] = rgb.1;
+                pixels[pixel_index + 2] = rgb.2;
             }
         }
 
-        if self.scanline == 241 && self.cycle == 1 {
-            self.set_status_flag(PPUStatusFlags::VerticalBlank, true);
+        pixels
+    }
+
+    // OAM Y is one less than the first line a sprite is drawn on. Evaluation only runs on lines
+    // 0-239, so Y >= $EF never reaches a visible line, and rows of 8x16 sprites past line 239
+    // are simply never fetched rather than wrapping to the top.
+    fn sprite_in_range(&self, y: u16, sprite_height: u16) -> bool {
+        self.scanline >= y && self.scanline - y < sprite_height
+    }
+
+    // Returns the (color, palette, behind background, OAM index) of the first opaque sprite pixel at x.
+    // Lower OAM indices win, even when that sprite is behind the background.
+    fn sprite_pixel(&self, x: usize) -> Option<(u8, u8, bool, u8)> {
+        if self.mask_register & 0x10 == 0 {
+            return None;
+        }
+
+        let show_leftmost_sprites = self.mask_register & 0x04 != 0;
+        if x < 8 && !show_leftmost_sprites {
+            return None;
+        }
+
+        for sprite in &self.scanline_sprites {
+            let offset = x as i16 - sprite.x as i16;
+            if !(0..8).contains(&offset) {
+                continue;
+            }
+
+            let bit = 7 - offset;
+            let color_low = (sprite.plane_low >> bit) & 0x01;
+            let color_high = (sprite.plane_high >> bit) & 0x01;
+            let color = (color_high << 1) | color_low;
+            if color == 0 {
+                continue;
+            }
+
+            return Some((color, sprite.attributes & 0x03, sprite.attributes & 0x20 != 0, sprite.oam_index));
+        }
+
+        None
+    }
+
+    // Color shown where neither layer draws, sampled per pixel so mid-frame changes show up as bands.
+    // With rendering off and v pointing into palette RAM, that entry replaces $3F00 (background palette hack).
+    fn backdrop_color(&self) -> (u8, u8, u8) {
+        let palette_addr = if !self.rendering_enabled() && self.address_register & 0x3F00 == 0x3F00 {
+            self.address_register
+        } else {
+            0x3F00
+        };
+
+        let palette_value = self.ppu_read(palette_addr) & 0x3F;
+        self.system_palette[palette_value as usize]
+    }
+
+    // Sprite zero hit needs both layers enabled, never happens at x=255, and not in the
+    // left 8 columns when either layer is clipped there
+    fn sprite_zero_hit_possible(&self, x: usize) -> bool {
+        let show_background = self.mask_register & 0x08 != 0;
+        let show_sprites = self.mask_register & 0x10 != 0;
+        let show_leftmost = self.mask_register & 0x06 == 0x06;
+
+        show_background && show_sprites && x != 255 && (x >= 8 || show_leftmost)
+    }
+
+    // Drops the cached background tile, for mapper writes that may have switched the CHR or nametables under it
+    pub fn invalidate_background_tile(&self) {
+        self.background_tile.set(None);
+    }
+
+    fn record_line_registers(&mut self) {
+        // Banks can switch between lines, tiles are only reused within one
+        self.background_tile.set(None);
+        self.line_registers[self.scanline as usize] = ScanlineRegisters {
+            control: self.control_register,
+            mask: self.mask_register,
+            scroll_x: self.render_scroll_x,
+            scroll_y: self.render_scroll_y,
+            nametable: self.render_nametable,
+        };
+    }
+
+    // Fast sprite zero hit: sprite zero's row is compared against the finished line in one go,
+    // so the flag shows up at the end of the line instead of at the overlapping dot
+    fn line_sprite_zero_hit(&mut self, y: usize) {
+        let Some(sprite) = self.scanline_sprites.iter().find(|sprite| sprite.oam_index == 0).copied() else {
+            return;
+        };
+
+        for offset in 0..8 {
+            let x = sprite.x as usize + offset;
+            if x >= SCREEN_WIDTH {
+                break;
+            }
+
+            let bit = 7 - offset;
+            let opaque = ((sprite.plane_low | sprite.plane_high) >> bit) & 0x01 != 0;
+            let background = self.background_index_buffer[y * SCREEN_WIDTH + x];
+            if opaque && background != 0 && self.sprite_zero_hit_possible(x) {
+                self.set_status_flag(PPUStatusFlags::SpriteZeroHit, true);
+                return;
+            }
+        }
+    }
+
+    // Cheap stand-in for the per-pixel check while frames are skipped: sprite zero's left edge
+    // counts as a hit whenever it's on the line, so games polling for it don't hang
+    fn estimate_sprite_zero_hit(&mut self, x: usize) {
+        let sprite_zero_here = self
+            .scanline_sprites
+            .iter()
+            .any(|sprite| sprite.oam_index == 0 && sprite.x as usize == x);
+
+        if sprite_zero_here && self.sprite_zero_hit_possible(x) {
+            self.set_status_flag(PPUStatusFlags::SpriteZeroHit, true);
+        }
+    }
+
+    fn background_pixel_info(&self, x: u16, y: u16) -> ((u8, u8, u8), u8) {
+        let world_x = x + self.render_scroll_x as u16 + if self.render_nametable & 0x01 != 0 { 256 } else { 0 };
+        let world_y = y + self.render_scroll_y as u16 + if self.render_nametable & 0x02 != 0 { 240 } else { 0 };
+
+        let nametable_x = (world_x / 256) % 2;
+        let nametable_y = (world_y / 240) % 2;
+        let nametable_base = 0x2000 + (nametable_y * 2 + nametable_x) * 0x0400;
+
+        let tile_x = (world_x % 256) / 8;
+        let tile_y = (world_y % 240) / 8;
+        let fine_y = world_y % 8;
+
+        let tile = self.fetch_background_tile(nametable_base, tile_x, tile_y, fine_y);
+        let bit = 7 - (world_x % 8);
+        let color = (((tile.plane_high >> bit) & 0x01) << 1) | ((tile.plane_low >> bit) & 0x01);
+
+        // Background entries never hit the $3F10/$14/$18/$1C mirrors, palette RAM is read directly
+        let palette_index = if color == 0 { 0 } else { (tile.palette_select << 2) | color };
+        let palette_value = self.palette[palette_index as usize] & 0x3F;
+        (self.system_palette[palette_value as usize], color)
+    }
+
+    // Nametable, attribute and pattern fetches happen once per tile instead of once per pixel
+    fn fetch_background_tile(&self, nametable_base: u16, tile_x: u16, tile_y: u16, fine_y: u16) -> BackgroundTile {
+        let nametable_addr = nametable_base + tile_y * 32 + tile_x;
+        let pattern_row = self.background_pattern_table() | fine_y;
+        if let Some(tile) = self.background_tile.get() {
+            if tile.nametable_addr == nametable_addr && tile.pattern_row == pattern_row {
+                return tile;
+            }
+        }
+
+        let tile_index = self.ppu_read(nametable_addr);
+        let pattern_addr = pattern_row + (tile_index as u16) * 16;
+        let tile = BackgroundTile {
+            nametable_addr,
+            pattern_row,
+            plane_low: self.ppu_read(pattern_addr),
+            plane_high: self.ppu_read(pattern_addr + 8),
+            palette_select: self.attribute_palette(nametable_base, tile_x, tile_y),
+        };
+        self.background_tile.set(Some(tile));
+        tile
+    }
+
+    fn background_pattern_table(&self) -> u16 {
+        if self.get_control_flag(PPUControlFlags::PatternBackground) {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    // Background palette of a tile, selected by its quadrant in the attribute byte
+    fn attribute_palette(&self, nametable_base: u16, tile_x: u16, tile_y: u16) -> u8 {
+        let attribute_addr = nametable_base + 0x03C0 + (tile_y / 4) * 8 + (tile_x / 4);
+        let attribute = self.ppu_read(attribute_addr);
+        let quadrant_x = (tile_x % 4) / 2;
+        let quadrant_y = (tile_y % 4) / 2;
+        let quadrant = quadrant_y * 2 + quadrant_x;
+
+        match quadrant {
+            0 => attribute & 0x03,
+            1 => (attribute >> 2) & 0x03,
+            2 => (attribute >> 4) & 0x03,
+            _ => (attribute >> 6) & 0x03,
+        }
+    }
+
+    // Renders all four nametables as a 512x480 RGB image, laid out as they are addressed
+    // ($2000 top left, $2400 top right, $2800 bottom left, $2C00 bottom right)
+    pub fn render_nametables(&self) -> Vec<u8> {
+        let width = SCREEN_WIDTH * 2;
+        let mut pixels = vec![0; width * SCREEN_HEIGHT * 2 * 3];
+        let pattern_table_base = self.background_pattern_table();
+
+        for nametable in 0..4u16 {
+            let nametable_base = 0x2000 + nametable * 0x0400;
+            let origin_x = (nametable % 2) as usize * SCREEN_WIDTH;
+            let origin_y = (nametable / 2) as usize * SCREEN_HEIGHT;
+
+            for tile_y in 0..30u16 {
+                for tile_x in 0..32u16 {
+                    let tile_index = self.ppu_read(nametable_base + tile_y * 32 + tile_x);
+                    let palette_select = self.attribute_palette(nametable_base, tile_x, tile_y);
+
+                    for row in 0..8u16 {
+                        let tile_addr = pattern_table_base + (tile_index as u16) * 16 + row;
+                        let plane_low = self.ppu_read(tile_addr);
+                        let plane_high = self.ppu_read(tile_addr + 8);
+
+                        for col in 0..8u16 {
+                            let bit = 7 - col;
+                            let color = (((plane_high >> bit) & 0x01) << 1) | ((plane_low >> bit) & 0x01);
+                            let palette_addr = if color == 0 {
+                                0x3F00
+                            } else {
+                                0x3F00 + ((palette_select << 2) | color) as u16
+                            };
+                            let rgb = self.system_palette[(self.ppu_read(palette_addr) & 0x3F) as usize];
+
+                            let x = origin_x + (tile_x * 8 + col) as usize;
+                            let y = origin_y + (tile_y * 8 + row) as usize;
+                            let index = (y * width + x) * 3;
+                            pixels[index] = rgb.0;
+                            pixels[index + 1] = rgb.1;
+                            pixels[index + 2] = rgb.2;
+                        }
+                    }
+                }
+            }
+        }
+
+        pixels
+    }
+
+    // Renders one 4KB pattern table as a 128x128 RGB image of 16x16 tiles.
+    // Palettes 0-3 are the background sub-palettes and 4-7 the sprite ones.
+    pub fn render_pattern_table(&self, table: usize, palette: u8) -> [u8; 128 * 128 * 3] {
+        let mut pixels = [0; 128 * 128 * 3];
+        let table_base = (table as u16 & 0x01) * 0x1000;
+        let palette_base = 0x3F00 + ((palette & 0x07) as u16) * 4;
+
+        for tile in 0..256u16 {
+            let tile_x = (tile % 16) as usize * 8;
+            let tile_y = (tile / 16) as usize * 8;
+
+            for row in 0..8u16 {
+                let tile_addr = table_base + tile * 16 + row;
+                let plane_low = self.ppu_read(tile_addr);
+                let plane_high = self.ppu_read(tile_addr + 8);
+
+                for col in 0..8u16 {
+                    let bit = 7 - col;
+                    let color = (((plane_high >> bit) & 0x01) << 1) | ((plane_low >> bit) & 0x01);
+                    let palette_addr = if color == 0 { 0x3F00 } else { palette_base + color as u16 };
+                    let rgb = self.system_palette[(self.ppu_read(palette_addr) & 0x3F) as usize];
+
+                    let index = ((tile_y + row as usize) * 128 + tile_x + col as usize) * 3;
+                    pixels[index] = rgb.0;
+                    pixels[index + 1] = rgb.1;
+                    pixels[index + 2] = rgb.2;
+                }
+            }
+        }
+
+        pixels
+    }
+
+    // Top left corner of the visible area in the 512x480 nametable image.
+    // The 256x240 viewport wraps around the edges of that image.
+    pub fn scroll_position(&self) -> (u16, u16) {
+        let x = self.render_scroll_x as u16 + if self.render_nametable & 0x01 != 0 { 256 } else { 0 };
+        let y = self.render_scroll_y as u16 + if self.render_nametable & 0x02 != 0 { 240 } else { 0 };
+        (x % 512, y % 480)
+    }
+
+    // Horizontal scroll and nametable X bit, copied at dot 257 of every rendered line
+    fn copy_horizontal_scroll(&mut self) {
+        self.render_scroll_x = self.scroll_x;
+        self.render_nametable = (self.render_nametable & 0x02) | (self.control_register & 0x01);
+    }
+
+    // Vertical scroll and nametable Y bit, copied during dots 280-304 of the pre-render line
+    fn copy_vertical_scroll(&mut self) {
+        self.render_scroll_y = self.scroll_y;
+        self.render_nametable = (self.render_nametable & 0x01) | (self.control_register & 0x02);
+    }
+
+    // Level of PPU address line A12 during this cycle.
+    // While rendering only the pattern fetches (last 4 cycles of each 8 cycle fetch group) touch
+    // the pattern tables, otherwise A12 follows the VRAM address set through $2006/$2007.
+    fn a12_level(&self) -> bool {
+        if !self.is_rendering() {
+            return self.address_register & 0x1000 != 0;
+        }
+
+        let pattern_fetch = (self.cycle.wrapping_sub(1)) % 8 >= 4;
+        match self.cycle {
+            1..=256 | 321..=336 => {
+                pattern_fetch && self.background_pattern_table() != 0
+            }
+            257..=320 => {
+                let slot = ((self.cycle - 257) / 8) as usize;
+                let pattern_addr = match self.scanline_sprites.get(slot) {
+                    Some(sprite) => sprite.pattern_addr,
+                    // Empty slots fetch tile $FF
+                    None => self.sprite_pattern_table(0xFF),
+                };
+                pattern_fetch && pattern_addr & 0x1000 != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn track_a12(&mut self) {
+        if self.a12_level() {
+            if self.a12_low_cycles >= A12_FILTER_CYCLES {
+                self.a12_edges.push((self.scanline, self.cycle));
+            }
+            self.a12_low_cycles = 0;
+        } else {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    // Per pixel provenance in row major order, None unless debug_provenance is enabled
+    pub fn pixel_provenance(&self) -> Option<&[PixelProvenance]> {
+        if self.debug_provenance {
+            Some(&self.provenance)
+        } else {
+            None
+        }
+    }
+
+    // Returns whether a frame was completed since the last call
+    pub fn poll_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    // (scanline, dot) the PPU is about to process
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.cycle)
+    }
+
+    pub fn is_odd_frame(&self) -> bool {
+        self.odd_frame
+    }
+
+    pub fn take_a12_edges(&mut self) -> Vec<(u16, u16)> {
+        std::mem::take(&mut self.a12_edges)
+    }
+
+    pub fn take_scanline_events(&mut self) -> Vec<(u16, bool)> {
+        std::mem::take(&mut self.scanline_events)
+    }
+
+    pub fn clock(&mut self) {
+        if self.warm_up_cycles > 0 {
+            self.warm_up_cycles -= 1;
+        }
+
+        if self.io_latch_decay > 0 {
+            self.io_latch_decay -= 1;
+            if self.io_latch_decay == 0 {
+                self.io_latch = 0;
+            }
+        }
+
+        // Scanline counting mappers that watch the PPU bus rather than A12
+        if self.cycle == 1 {
+            let rendering = self.scanline < 240 && self.rendering_enabled();
+            self.mapper.borrow_mut().notify_scanline(rendering);
+        }
+
+        if self.scanline < 240 && !self.render_enabled && self.frame_sink.is_none() {
+            if self.cycle == 1 {
+                self.record_line_registers();
+            }
+            if (1..=256).contains(&self.cycle) {
+                self.estimate_sprite_zero_hit((self.cycle - 1) as usize);
+            }
+        } else if self.scanline < 240 {
+            if self.cycle == 1 {
+                self.record_line_registers();
+            }
+            if (1..=256).contains(&self.cycle) {
+                let x = (self.cycle - 1) as usize;
+                let y = self.scanline as usize;
+                let show_background = self.mask_register & 0x08 != 0;
+                let show_leftmost_background = self.mask_register & 0x02 != 0;
+                let (rgb, bg_color) = if show_background && (x >= 8 || show_leftmost_background) {
+                    self.background_pixel_info(x as u16, y as u16)
+                } else {
+                    (self.backdrop_color(), 0)
+                };
+                self.background_index_buffer[y * SCREEN_WIDTH + x] = bg_color;
+
+                let sprite = self.sprite_pixel(x);
+                let mut sprite_zero_hit = false;
+                if let Some((_, _, _, 0)) = sprite {
+                    // Opaque sprite zero pixel over an opaque background pixel, regardless of priority
+                    if self.accurate_sprite_zero_hit && bg_color != 0 && self.sprite_zero_hit_possible(x) {
+                        self.set_status_flag(PPUStatusFlags::SpriteZeroHit, true);
+                        sprite_zero_hit = true;
+                    }
+                }
+
+                // Priority mux: an opaque sprite pixel wins unless it's behind an opaque background pixel
+                let sprite_in_front = match sprite {
+                    Some((_, _, behind_background, _)) => bg_color == 0 || !behind_background,
+                    None => false,
+                };
+                let rgb = match sprite {
+                    Some((color, palette_index, _, _)) if sprite_in_front => {
+                        let palette_addr = 0x3F10 + (palette_index as u16) * 4 + color as u16;
+                        let palette_value = self.ppu_read(palette_addr) & 0x3F;
+                        self.system_palette[palette_value as usize]
+                    }
+                    _ => rgb,
+                };
+                self.set_frame_pixel(x, y, rgb);
+
+                if !self.accurate_sprite_zero_hit && x == SCREEN_WIDTH - 1 {
+                    self.line_sprite_zero_hit(y);
+                }
+
+                if self.debug_provenance {
+                    self.provenance[y * SCREEN_WIDTH + x] = PixelProvenance {
+                        background_color: bg_color,
+                        sprite: sprite.map(|(_, _, _, oam_index)| oam_index),
+                        sprite_in_front,
+                        sprite_zero_hit,
+                    };
+                }
+            }
+        }
+
+        if self.is_rendering() {
+            if self.cycle == 257 {
+                self.copy_horizontal_scroll();
+            }
+            if self.scanline < 240 {
+                self.sprite_pipeline();
+            } else if (257..=320).contains(&self.cycle) {
+                // The pre-render line fetches nothing useful, line 0 never has sprites
+                self.oam_addr = 0;
+                self.scanline_sprites.clear();
+            }
+
+            // The pre-render line does the same work as a visible line so that
+            // the first visible line starts with the scroll for the new frame
+            if self.scanline == self.pre_render_scanline() && (280..=304).contains(&self.cycle) {
+                self.copy_vertical_scroll();
+            }
+        }
+
+        self.track_a12();
+
+        if self.scanline_events_enabled
+            && self.cycle == 260
+            && (self.scanline < 240 || self.scanline == self.pre_render_scanline())
+        {
+            self.scanline_events.push((self.scanline, self.rendering_enabled()));
+        }
+
+        if self.scanline == self.vblank_scanline() && self.cycle == 1 {
+            self.set_status_flag(PPUStatusFlags::VerticalBlank, true);
 
             if self.get_control_flag(PPUControlFlags::EnableNMI) {
                 self.nmi = true;
             }
         }
 
-        if self.scanline == 261 && self.cycle == 1 {
+        if self.cycle == 0 {
+            if self.break_scanline == Some(self.scanline) {
+                self.scanline_break = true;
+            }
+            if self.nmi_scanline == Some(self.scanline) {
+                self.nmi = true;
+            }
+        }
+
+        if self.scanline == self.pre_render_scanline() && self.cycle == 1 {
             self.set_status_flag(PPUStatusFlags::VerticalBlank, false);
             self.set_status_flag(PPUStatusFlags::SpriteZeroHit, false);
             self.set_status_flag(PPUStatusFlags::SpriteOverflow, false);
@@ -573,15 +1716,920 @@ impl PPU {
 
         self.cycle += 1;
 
+        // NTSC skips the last dot of the pre-render line on odd frames while rendering
+        if self.region == Region::Ntsc
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.scanline == self.pre_render_scanline()
+            && self.cycle == 340
+        {
+            self.cycle = 341;
+        }
+
         if self.cycle >= 341 {
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline > 261 {
+            if self.scanline > self.pre_render_scanline() {
                 self.scanline = 0;
-                self.render_sprites();
+                self.odd_frame = !self.odd_frame;
+                std::mem::swap(&mut self.back_buffer, &mut self.front_buffer);
+                self.completed_line_registers = self.line_registers;
+                self.completed_frame_ready = true;
                 self.frame_complete = true;
+                self.frame_count += 1;
+                self.send_completed_frame();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::mapper::{BankWindow, Mapper, MapperDebugInfo};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const BLACK: u8 = 0x0F;
+    const WHITE: u8 = 0x30;
+    const RED: u8 = 0x16;
+    const GREEN: u8 = 0x1A;
+
+    // NROM board with 8KB of CHR RAM, warm-up skipped so register writes take effect right away
+    fn test_ppu() -> PPU {
+        test_ppu_with_chr(Vec::new())
+    }
+
+    fn test_ppu_with_chr(chr: Vec<u8>) -> PPU {
+        let cartridge = Cartridge::from_parts(vec![0; 0x8000], chr, 0, Mirroring::Horizontal);
+        let mut ppu = PPU::new(cartridge.create_mapper(), Mirroring::Horizontal, Region::Ntsc);
+        ppu.warm_up_enabled = false;
+        ppu.reset();
+        ppu
+    }
+
+    fn write_vram(ppu: &mut PPU, addr: u16, data: &[u8]) {
+        ppu.write_to_address_register((addr >> 8) as u8);
+        ppu.write_to_address_register(addr as u8);
+        for &byte in data {
+            ppu.write_data(byte);
+        }
+    }
+
+    fn run_frame(ppu: &mut PPU) {
+        while !ppu.poll_frame_complete() {
+            ppu.clock();
+        }
+    }
+
+    fn run_to(ppu: &mut PPU, scanline: u16, cycle: u16) {
+        while ppu.position() != (scanline, cycle) {
+            ppu.clock();
+        }
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> (u8, u8, u8) {
+        let index = (y * SCREEN_WIDTH + x) * 4;
+        let frame = ppu.completed_frame();
+        (frame[index], frame[index + 1], frame[index + 2])
+    }
+
+    // Pixel of a packed RGB image from the debug views
+    fn rgb_pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> (u8, u8, u8) {
+        let index = (y * width + x) * 3;
+        (pixels[index], pixels[index + 1], pixels[index + 2])
+    }
+
+    fn color(value: u8) -> (u8, u8, u8) {
+        SYSTEM_PALLETE[value as usize]
+    }
+
+    // Every sprite tile is solid color 1, drawn white on a black backdrop
+    fn sprite_ppu(sprite_height: u16) -> PPU {
+        let mut ppu = test_ppu();
+        let solid_tile = [[0xFF; 8], [0x00; 8]].concat();
+        write_vram(&mut ppu, 0x0000, &solid_tile.repeat(256));
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x3F11, &[WHITE]);
+        ppu.write_to_control_register(if sprite_height == 16 { 0x20 } else { 0x00 });
+        ppu.write_to_mask_register(0x14);
+        ppu
+    }
+
+    // Lines sprite 0 at x=100 shows up on after one frame with the given OAM Y
+    fn sprite_lines(sprite_height: u16, y: u8) -> Vec<usize> {
+        let mut ppu = sprite_ppu(sprite_height);
+        ppu.oam[0..4].copy_from_slice(&[y, 0x02, 0x00, 100]);
+        run_frame(&mut ppu);
+        (0..SCREEN_HEIGHT).filter(|&line| pixel(&ppu, 100, line) == color(WHITE)).collect()
+    }
+
+    #[test]
+    fn ppu_sprite_y_boundaries_8x8() {
+        assert_eq!(sprite_lines(8, 0xE7), (232..240).collect::<Vec<_>>());
+        assert_eq!(sprite_lines(8, 0xEA), (235..240).collect::<Vec<_>>());
+        assert_eq!(sprite_lines(8, 0xEE), vec![239]);
+        for y in 0xEF..=0xFF {
+            assert!(sprite_lines(8, y).is_empty(), "Y={:02X}", y);
+        }
+    }
+
+    #[test]
+    fn ppu_sprite_y_boundaries_8x16() {
+        assert_eq!(sprite_lines(16, 0xDF), (224..240).collect::<Vec<_>>());
+        // Clipped at the bottom edge rather than wrapping to the top of the screen
+        assert_eq!(sprite_lines(16, 0xE8), (233..240).collect::<Vec<_>>());
+        assert_eq!(sprite_lines(16, 0xEE), vec![239]);
+        for y in 0xEF..=0xFF {
+            assert!(sprite_lines(16, y).is_empty(), "Y={:02X}", y);
+        }
+    }
+
+    #[test]
+    fn ppu_mid_frame_backdrop_change_splits_frame() {
+        // Background on with only transparent tiles, so every pixel is the backdrop
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_to_mask_register(0x0A);
+
+        run_to(&mut ppu, 120, 300);
+        write_vram(&mut ppu, 0x3F00, &[WHITE]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        run_frame(&mut ppu);
+
+        for line in [0, 60, 120] {
+            assert_eq!(pixel(&ppu, 128, line), color(BLACK), "line {}", line);
+        }
+        for line in [121, 180, 239] {
+            assert_eq!(pixel(&ppu, 128, line), color(WHITE), "line {}", line);
+        }
+    }
+
+    #[test]
+    fn ppu_mid_frame_sprite_disable_hides_bottom_half() {
+        // Solid sprites on lines 40-47 and 180-187 over a transparent background
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x0010, &[[0xFF; 8], [0x00; 8]].concat());
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x3F11, &[WHITE]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.oam[0..8].copy_from_slice(&[39, 0x01, 0x00, 100, 179, 0x01, 0x00, 100]);
+        ppu.write_to_mask_register(0x1E);
+
+        run_to(&mut ppu, 120, 300);
+        ppu.write_to_mask_register(0x0A);
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 104, 44), color(WHITE));
+        assert_eq!(pixel(&ppu, 104, 184), color(BLACK));
+    }
+
+    // Deterministic noise for filling CHR, nametables and palettes
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        (0..len as u32).map(|i| (i.wrapping_add(seed).wrapping_mul(2654435761) >> 13) as u8).collect()
+    }
+
+    #[test]
+    fn ppu_small_chr_rom_repeats_past_its_end() {
+        // 4KB of CHR ROM where the board expects 8KB
+        let chr = noise(0x1000, 5);
+        let mut ppu = test_ppu_with_chr(chr.clone());
+        for addr in [0x1000, 0x1234, 0x1FFF] {
+            assert_eq!(ppu.ppu_read(addr), chr[addr as usize & 0x0FFF], "${:04X}", addr);
+        }
+
+        // Rendering and the pattern table view both read the upper table without panicking
+        ppu.write_to_control_register(0x10);
+        ppu.write_to_mask_register(0x0A);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.render_pattern_table(1, 0), ppu.render_pattern_table(0, 0));
+    }
+
+    #[test]
+    fn ppu_palette_write_during_rendering_shows_on_later_lines() {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_to_mask_register(0x0A);
+
+        // v is left pointing at $3F01, which only picks the backdrop while rendering is off
+        run_to(&mut ppu, 100, 300);
+        write_vram(&mut ppu, 0x3F00, &[WHITE]);
+        run_frame(&mut ppu);
+
+        for line in [0, 50, 100] {
+            assert_eq!(pixel(&ppu, 128, line), color(BLACK), "line {}", line);
+        }
+        for line in [101, 170, 239] {
+            assert_eq!(pixel(&ppu, 128, line), color(WHITE), "line {}", line);
+        }
+    }
+
+    // Background pixel with every fetch done for the pixel itself, the way it was before tiles were cached
+    fn uncached_background_pixel(ppu: &PPU, x: u16, y: u16) -> (u8, u8, u8) {
+        let world_x = x + ppu.render_scroll_x as u16 + if ppu.render_nametable & 0x01 != 0 { 256 } else { 0 };
+        let world_y = y + ppu.render_scroll_y as u16 + if ppu.render_nametable & 0x02 != 0 { 240 } else { 0 };
+        let nametable_base = 0x2000 + (((world_y / 240) % 2) * 2 + (world_x / 256) % 2) * 0x0400;
+        let tile_x = (world_x % 256) / 8;
+        let tile_y = (world_y % 240) / 8;
+
+        let tile_index = ppu.ppu_read(nametable_base + tile_y * 32 + tile_x);
+        let palette_select = ppu.attribute_palette(nametable_base, tile_x, tile_y);
+        let tile_addr = ppu.background_pattern_table() + tile_index as u16 * 16 + world_y % 8;
+        let bit = 7 - world_x % 8;
+        let value = (((ppu.ppu_read(tile_addr + 8) >> bit) & 0x01) << 1) | ((ppu.ppu_read(tile_addr) >> bit) & 0x01);
+
+        let palette_addr = if value == 0 { 0x3F00 } else { 0x3F00 + ((palette_select << 2) | value) as u16 };
+        color(ppu.ppu_read(palette_addr))
+    }
+
+    #[test]
+    fn ppu_cached_background_matches_uncached_fetches() {
+        let mut ppu = test_ppu_with_chr(noise(0x2000, 1));
+        write_vram(&mut ppu, 0x2000, &noise(0x800, 2));
+        write_vram(&mut ppu, 0x3F00, &noise(16, 3).iter().map(|value| value & 0x3F).collect::<Vec<_>>());
+        // Scrolled into the second nametable so tiles cross nametable edges
+        ppu.write_to_control_register(0x11);
+        ppu.write_to_scroll_register(37);
+        ppu.write_to_scroll_register(91);
+        ppu.write_to_address_register(0x00);
+        ppu.write_to_address_register(0x00);
+        ppu.write_to_mask_register(0x0A);
+
+        // The vertical scroll is copied on the pre-render line, so the second frame is fully scrolled
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                assert_eq!(pixel(&ppu, x, y), uncached_background_pixel(&ppu, x as u16, y as u16), "pixel {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn ppu_oam_data_read_masks_attribute_bits() {
+        let mut ppu = test_ppu();
+        ppu.oam[0..4].copy_from_slice(&[0xFF; 4]);
+        for (addr, expected) in [(0, 0xFF), (1, 0xFF), (2, 0xE3), (3, 0xFF)] {
+            ppu.write_to_oam_address(addr);
+            assert_eq!(ppu.read_oam_data(), expected, "OAM ${:02X}", addr);
+        }
+    }
+
+    #[test]
+    fn ppu_oam_data_read_during_secondary_oam_clear() {
+        let mut ppu = test_ppu();
+        ppu.oam[0x10] = 0x42;
+        ppu.write_to_mask_register(0x18);
+        ppu.write_to_oam_address(0x10);
+
+        run_to(&mut ppu, 20, 30);
+        assert_eq!(ppu.read_oam_data(), 0xFF);
+        // Rendering is off during vblank, the raw byte comes back. OAMADDR was cleared by the sprite fetches.
+        run_to(&mut ppu, 245, 30);
+        ppu.write_to_oam_address(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x42);
+    }
+
+    #[test]
+    fn ppu_oam_data_write_wraps_and_read_keeps_address() {
+        let mut ppu = test_ppu();
+        ppu.write_to_oam_address(0xFE);
+        for value in [0x11, 0x22, 0x33] {
+            ppu.write_to_oam_data(value);
+        }
+        assert_eq!(ppu.oam[0xFE], 0x11);
+        assert_eq!(ppu.oam[0xFF], 0x22);
+        assert_eq!(ppu.oam[0x00], 0x33);
+
+        // Reads always see the same byte until the address is written again
+        ppu.write_to_oam_address(0xFF);
+        assert_eq!(ppu.read_oam_data(), 0x22);
+        assert_eq!(ppu.read_oam_data(), 0x22);
+        ppu.write_to_oam_data(0x44);
+        assert_eq!(ppu.oam[0xFF], 0x44);
+    }
+
+    // Tile 0 is transparent, tile 1 solid color 1. The background is empty apart from the left half of
+    // tile row 10 (lines 80-87), drawn green. Sprite palette 0 is white and palette 1 red.
+    fn layered_ppu() -> PPU {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x0010, &[[0xFF; 8], [0x00; 8]].concat());
+        write_vram(&mut ppu, 0x2000 + 10 * 32, &[0x01; 16]);
+        write_vram(&mut ppu, 0x3F00, &[BLACK, GREEN]);
+        write_vram(&mut ppu, 0x3F11, &[WHITE]);
+        write_vram(&mut ppu, 0x3F15, &[RED]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_to_mask_register(0x1E);
+        ppu
+    }
+
+    // Sprite on lines 80-87 using tile 1
+    fn place_sprite(ppu: &mut PPU, index: usize, x: u8, attributes: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[79, 0x01, attributes, x]);
+    }
+
+    #[test]
+    fn ppu_lower_oam_index_wins_overlap() {
+        let mut ppu = layered_ppu();
+        place_sprite(&mut ppu, 0, 160, 0x00);
+        place_sprite(&mut ppu, 1, 164, 0x01);
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 163, 84), color(WHITE));
+        assert_eq!(pixel(&ppu, 164, 84), color(WHITE));
+        assert_eq!(pixel(&ppu, 167, 84), color(WHITE));
+        assert_eq!(pixel(&ppu, 168, 84), color(RED));
+    }
+
+    #[test]
+    fn ppu_sprite_priority_against_background() {
+        let mut ppu = layered_ppu();
+        place_sprite(&mut ppu, 0, 80, 0x20);
+        place_sprite(&mut ppu, 1, 200, 0x20);
+        place_sprite(&mut ppu, 2, 100, 0x01);
+        run_frame(&mut ppu);
+
+        // Behind the background: hidden by opaque pixels, visible through transparent ones
+        assert_eq!(pixel(&ppu, 84, 84), color(GREEN));
+        assert_eq!(pixel(&ppu, 204, 84), color(WHITE));
+        // In front: covers the background
+        assert_eq!(pixel(&ppu, 104, 84), color(RED));
+    }
+
+    #[test]
+    fn ppu_behind_background_sprite_hides_later_front_sprite() {
+        // Sprite 0 wins the sprite mux and then loses to the background, so sprite 1 never shows
+        // even though it's in front. Over a transparent background sprite 0 shows instead.
+        let mut ppu = layered_ppu();
+        place_sprite(&mut ppu, 0, 40, 0x20);
+        place_sprite(&mut ppu, 1, 40, 0x01);
+        place_sprite(&mut ppu, 2, 160, 0x20);
+        place_sprite(&mut ppu, 3, 160, 0x01);
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 44, 84), color(GREEN));
+        assert_eq!(pixel(&ppu, 164, 84), color(WHITE));
+    }
+
+    #[test]
+    fn ppu_a12_rises_once_per_line_with_sprites_at_1000() {
+        let mut ppu = test_ppu();
+        ppu.write_to_control_register(0x08);
+        ppu.write_to_mask_register(0x18);
+        run_frame(&mut ppu);
+        ppu.take_a12_edges();
+        run_frame(&mut ppu);
+
+        // One edge per rendered line plus the pre-render line, at the first sprite pattern fetch
+        let edges = ppu.take_a12_edges();
+        let scanlines: Vec<u16> = edges.iter().map(|&(scanline, _)| scanline).collect();
+        assert_eq!(scanlines, [(0..240).collect(), vec![261]].concat());
+        for (scanline, cycle) in edges {
+            assert_eq!(cycle, 261, "scanline {}", scanline);
+        }
+    }
+
+    #[test]
+    fn ppu_rgba_frame_buffer_matches_rgb() {
+        let mut ppu = layered_ppu();
+        place_sprite(&mut ppu, 0, 160, 0x01);
+        run_frame(&mut ppu);
+        // Halfway through the next frame, which mustn't leak into the copies
+        write_vram(&mut ppu, 0x3F00, &[WHITE]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        run_to(&mut ppu, 120, 0);
+
+        let rgba = ppu.frame_buffer_rgba();
+        let rgb = ppu.frame_buffer_rgb();
+        assert_eq!(rgba.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        assert_eq!(rgb.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        assert!(rgba.chunks_exact(4).all(|pixel| pixel[3] == 255));
+        assert!(rgba.chunks_exact(4).zip(rgb.chunks_exact(3)).all(|(rgba, rgb)| rgba[..3] == *rgb));
+        assert_eq!(rgba, ppu.completed_frame());
+        assert_eq!(pixel(&ppu, 0, 0), color(BLACK));
+    }
+
+    // Eight sprites on line 100 followed by the given OAM bytes for sprites 8 and 9. Everything
+    // else, including the tile, attribute and X bytes of the first eight, is out of range.
+    fn overflow_ppu(sprite_8: [u8; 4], sprite_9: [u8; 4]) -> PPU {
+        let mut ppu = test_ppu();
+        ppu.oam = [0xF0; 256];
+        for sprite in 0..8 {
+            ppu.oam[sprite * 4] = 100;
+        }
+        ppu.oam[32..36].copy_from_slice(&sprite_8);
+        ppu.oam[36..40].copy_from_slice(&sprite_9);
+        ppu
+    }
+
+    // Evaluates line 100 the way dots 65-256 do, returns the sprite overflow flag
+    fn evaluate_overflow(ppu: &mut PPU) -> bool {
+        ppu.scanline = 100;
+        ppu.set_status_flag(PPUStatusFlags::SpriteOverflow, false);
+        ppu.sprite_evaluation = SpriteEvaluation::default();
+        for _ in (65..=256).step_by(2) {
+            ppu.read_sprite_evaluation_byte();
+            ppu.step_sprite_evaluation();
+        }
+        ppu.status_register & PPUStatusFlags::SpriteOverflow as u8 != 0
+    }
+
+    #[test]
+    fn ppu_sprite_overflow_bug_is_the_default() {
+        // A plain 9th sprite is found either way
+        let mut ppu = overflow_ppu([100, 0xF0, 0xF0, 0xF0], [0xF0; 4]);
+        assert!(ppu.sprite_overflow_bug);
+        assert!(evaluate_overflow(&mut ppu));
+
+        // Sprite 8 misses, so sprite 9 is checked starting at its tile byte: a tile of 100 is
+        // taken as an in-range Y (false positive)
+        let mut ppu = overflow_ppu([0xF0; 4], [0xF0, 100, 0xF0, 0xF0]);
+        assert!(evaluate_overflow(&mut ppu));
+
+        // while sprite 9's real in-range Y is never looked at (false negative)
+        let mut ppu = overflow_ppu([0xF0; 4], [100, 0xF0, 0xF0, 0xF0]);
+        assert!(!evaluate_overflow(&mut ppu));
+    }
+
+    #[test]
+    fn ppu_render_nametables_spot_check() {
+        let mut ppu = layered_ppu();
+        // Tile (3, 2) of $2000 with palette 1 from the attribute table, tile (5, 4) of $2800 with palette 0
+        write_vram(&mut ppu, 0x2000 + 10 * 32, &[0x00; 16]);
+        write_vram(&mut ppu, 0x2000 + 2 * 32 + 3, &[0x01]);
+        write_vram(&mut ppu, 0x23C0, &[0x40]);
+        write_vram(&mut ppu, 0x2800 + 4 * 32 + 5, &[0x01]);
+        write_vram(&mut ppu, 0x3F05, &[RED]);
+
+        let pixels = ppu.render_nametables();
+        assert_eq!(pixels.len(), 512 * 480 * 3);
+        let pixel = |x, y| rgb_pixel(&pixels, 512, x, y);
+        assert_eq!(pixel(28, 20), color(RED));
+        assert_eq!(pixel(23, 20), color(BLACK));
+        // Horizontal mirroring repeats each nametable on the right
+        assert_eq!(pixel(256 + 28, 20), color(RED));
+        assert_eq!(pixel(44, 240 + 36), color(GREEN));
+        assert_eq!(pixel(256 + 44, 240 + 36), color(GREEN));
+        assert_eq!(pixel(44, 36), color(BLACK));
+    }
+
+    #[test]
+    fn ppu_render_pattern_table_decodes_nestest_tile() {
+        let cartridge = Cartridge::from_bytes(include_bytes!("nestest.nes").to_vec(), false, Default::default()).unwrap();
+        let mut ppu = PPU::new(cartridge.create_mapper(), Mirroring::Horizontal, Region::Ntsc);
+        ppu.warm_up_enabled = false;
+        ppu.reset();
+        write_vram(&mut ppu, 0x3F00, &[BLACK, RED, GREEN, WHITE]);
+        write_vram(&mut ppu, 0x3F17, &[RED]);
+
+        // "A" is tile $41, found at column 1, row 4 of the 16x16 grid
+        let glyph = [
+            "...###..", "..##.##.", ".##...##", ".#######", ".##...##", ".##...##", ".##...##", "........",
+        ];
+        let pixels = ppu.render_pattern_table(0, 0);
+        for (row, line) in glyph.iter().enumerate() {
+            for (col, dot) in line.chars().enumerate() {
+                let expected = if dot == '#' { color(WHITE) } else { color(BLACK) };
+                assert_eq!(rgb_pixel(&pixels, 128, 8 + col, 32 + row), expected, "row {} col {}", row, col);
+            }
+        }
+
+        // Sprite palette 1 puts color 3 at $3F17
+        let pixels = ppu.render_pattern_table(0, 5);
+        assert_eq!(rgb_pixel(&pixels, 128, 11, 32), color(RED));
+    }
+
+    #[test]
+    fn ppu_nametable_write_lands_per_mirroring_mode() {
+        // $2C05 is byte 5 of the fourth nametable
+        for (mirroring, vram_index) in [
+            (Mirroring::Horizontal, 0x0405),
+            (Mirroring::Vertical, 0x0405),
+            (Mirroring::FourScreen, 0x0C05),
+            (Mirroring::OneScreenLower, 0x0005),
+            (Mirroring::OneScreenUpper, 0x0405),
+        ] {
+            let mut ppu = test_ppu();
+            ppu.set_mirroring(mirroring);
+            write_vram(&mut ppu, 0x2C05, &[0x42]);
+            assert_eq!(ppu.mirror_vram_addr(0x2C05), vram_index, "{}", mirroring);
+            assert_eq!(ppu.vram[vram_index as usize], 0x42, "{}", mirroring);
+            assert_eq!(ppu.vram.iter().filter(|&&byte| byte == 0x42).count(), 1, "{}", mirroring);
+        }
+    }
+
+    #[test]
+    fn ppu_set_mirroring_keeps_vram() {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x2000, &[0x11]);
+        write_vram(&mut ppu, 0x2800, &[0x22]);
+        ppu.set_mirroring(Mirroring::OneScreenUpper);
+        assert_eq!(ppu.vram[0x0000], 0x11);
+        assert_eq!(ppu.vram[0x0400], 0x22);
+
+        ppu.set_mirroring(Mirroring::Horizontal);
+        assert_eq!(ppu.ppu_read(0x2400), 0x11);
+        assert_eq!(ppu.ppu_read(0x2C00), 0x22);
+    }
+
+    // Color of a sprite drawn with the given $2000 value and tile, where every tile in the $0000
+    // table is solid color 1 (white) and every tile in the $1000 table solid color 2 (red)
+    fn sprite_table_color(control: u8, tile_index: u8) -> (u8, u8, u8) {
+        let mut ppu = test_ppu();
+        let table_0 = [[0xFF; 8], [0x00; 8]].concat().repeat(256);
+        let table_1 = [[0x00; 8], [0xFF; 8]].concat().repeat(256);
+        write_vram(&mut ppu, 0x0000, &[table_0, table_1].concat());
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x3F11, &[WHITE, RED]);
+        ppu.write_to_control_register(control);
+        ppu.write_to_mask_register(0x14);
+        ppu.oam[0..4].copy_from_slice(&[79, tile_index, 0x00, 100]);
+        run_frame(&mut ppu);
+        pixel(&ppu, 104, 84)
+    }
+
+    #[test]
+    fn ppu_sprite_pattern_table_selection() {
+        // 8x8 sprites follow the sprite table bit
+        assert_eq!(sprite_table_color(0x00, 0x03), color(WHITE));
+        assert_eq!(sprite_table_color(0x08, 0x02), color(RED));
+        // 8x16 sprites take the table from bit 0 of the tile index and ignore it
+        assert_eq!(sprite_table_color(0x20, 0x03), color(RED));
+        assert_eq!(sprite_table_color(0x28, 0x02), color(WHITE));
+    }
+
+    // Board with two switchable 8KB CHR banks and nothing else
+    struct TwoBankChr {
+        bank: usize,
+        chr: Vec<u8>,
+    }
+
+    impl Mapper for TwoBankChr {
+        fn cpu_read(&mut self, _addr: u16) -> Option<u8> {
+            None
+        }
+        fn cpu_write(&mut self, _addr: u16, _data: u8) -> bool {
+            false
+        }
+        fn ppu_read(&mut self, addr: u16) -> Option<u8> {
+            Some(self.chr[self.bank * 0x2000 + (addr & 0x1FFF) as usize])
+        }
+        fn ppu_write(&mut self, _addr: u16, _data: u8) -> bool {
+            false
+        }
+        fn mirroring(&self) -> Option<Mirroring> {
+            None
+        }
+        fn irq_pending(&mut self) -> bool {
+            false
+        }
+        fn save_state(&self, _writer: &mut StateWriter) {}
+        fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), String> {
+            Ok(())
+        }
+        fn debug_banks(&self) -> MapperDebugInfo {
+            MapperDebugInfo {
+                cpu_windows: [BankWindow::OPEN_BUS; 4],
+                ppu_windows: [BankWindow::OPEN_BUS; 8],
+                registers: Vec::new(),
             }
         }
     }
+
+    #[test]
+    fn ppu_pattern_fetches_follow_the_mapper_bank() {
+        // Bank 0 is all transparent tiles, bank 1 all solid color 1
+        let chr = [vec![0x00; 0x2000], [[0xFF; 8], [0x00; 8]].concat().repeat(512)].concat();
+        let mapper = Rc::new(RefCell::new(TwoBankChr { bank: 0, chr }));
+        let mut ppu = PPU::new(mapper.clone(), Mirroring::Horizontal, Region::Ntsc);
+        ppu.warm_up_enabled = false;
+        ppu.reset();
+        write_vram(&mut ppu, 0x3F00, &[BLACK, WHITE]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.write_to_mask_register(0x0A);
+
+        assert_eq!(ppu.ppu_read(0x0000), 0x00);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 128, 120), color(BLACK));
+
+        mapper.borrow_mut().bank = 1;
+        assert_eq!(ppu.ppu_read(0x0000), 0xFF);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 128, 120), color(WHITE));
+    }
+
+    #[test]
+    fn ppu_palette_presets_change_output_colors() {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x3F00, &[RED]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALLETE[RED as usize]);
+
+        // Switching recolors the frame already drawn, and the next one uses the new colors too
+        ppu.set_system_palette(PalettePreset::Ppu2C02.colors());
+        assert_eq!(pixel(&ppu, 0, 0), PALETTE_2C02[RED as usize]);
+        assert_ne!(PALETTE_2C02[RED as usize], SYSTEM_PALLETE[RED as usize]);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), PALETTE_2C02[RED as usize]);
+
+        ppu.set_system_palette(PalettePreset::Grayscale.colors());
+        let (r, g, b) = pixel(&ppu, 0, 0);
+        assert!(r == g && g == b, "{:?}", (r, g, b));
+    }
+
+    #[test]
+    fn ppu_pal_file_round_trips() {
+        let mut palette = SYSTEM_PALLETE;
+        palette[0x05] = (0x12, 0x34, 0x56);
+        let path = std::env::temp_dir().join(format!("runes-palette-{}.pal", std::process::id()));
+        let path = path.to_string_lossy();
+
+        save_pal_file(&path, &palette).unwrap();
+        assert_eq!(std::fs::metadata(path.as_ref()).unwrap().len(), 192);
+        let loaded = load_pal_file(&path);
+        std::fs::remove_file(path.as_ref()).unwrap();
+        assert_eq!(loaded.unwrap(), palette);
+    }
+
+    // Whether sprite zero at x on lines 80-87 raises the hit flag over a fully opaque background
+    fn sprite_zero_hits(x: u8, mask: u8) -> bool {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x0000, &[[0xFF; 8], [0x00; 8]].concat());
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.oam[0..4].copy_from_slice(&[79, 0x00, 0x00, x]);
+        ppu.write_to_mask_register(mask);
+        run_to(&mut ppu, 100, 0);
+        ppu.status_register & PPUStatusFlags::SpriteZeroHit as u8 != 0
+    }
+
+    #[test]
+    fn ppu_sprite_zero_hit_right_edge() {
+        for x in 248..255 {
+            assert!(sprite_zero_hits(x, 0x1E), "x={}", x);
+        }
+        // Only the pixel at x=255 overlaps, which never hits
+        assert!(!sprite_zero_hits(255, 0x1E));
+    }
+
+    #[test]
+    fn ppu_sprite_zero_hit_left_clipping() {
+        assert!(sprite_zero_hits(0, 0x1E));
+        assert!(!sprite_zero_hits(0, 0x1C));
+        assert!(!sprite_zero_hits(0, 0x1A));
+        assert!(!sprite_zero_hits(0, 0x18));
+        // Past the clipped columns it hits again
+        assert!(sprite_zero_hits(1, 0x18));
+    }
+
+    #[test]
+    fn ppu_sprite_zero_hit_needs_both_layers() {
+        assert!(!sprite_zero_hits(100, 0x00));
+        assert!(!sprite_zero_hits(100, 0x0A));
+        assert!(!sprite_zero_hits(100, 0x14));
+        assert!(sprite_zero_hits(100, 0x1E));
+    }
+
+    #[test]
+    fn ppu_palette_hack_draws_backdrop_bands() {
+        // Rendering off, so the backdrop comes from whichever palette entry v points at
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x3F00, &[BLACK, 0x00, 0x00, 0x00, 0x00, RED, 0x00, 0x00, 0x00, GREEN]);
+        run_frame(&mut ppu);
+
+        write_vram(&mut ppu, 0x3F05, &[]);
+        run_to(&mut ppu, 80, 0);
+        write_vram(&mut ppu, 0x3F09, &[]);
+        run_to(&mut ppu, 160, 0);
+        write_vram(&mut ppu, 0x0000, &[]);
+        run_frame(&mut ppu);
+
+        for (line, expected) in [(0, RED), (79, RED), (80, GREEN), (159, GREEN), (160, BLACK), (239, BLACK)] {
+            assert_eq!(pixel(&ppu, 0, line), color(expected), "line {}", line);
+            assert_eq!(pixel(&ppu, 255, line), color(expected), "line {}", line);
+        }
+    }
+
+    fn read_vram(ppu: &mut PPU, addr: u16) -> u8 {
+        ppu.write_to_address_register((addr >> 8) as u8);
+        ppu.write_to_address_register(addr as u8);
+        ppu.read_data()
+    }
+
+    #[test]
+    fn ppu_palette_reads_through_mirrors_and_greyscale() {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x2F00, &[0xA0]);
+        write_vram(&mut ppu, 0x2F10, &[0xA1]);
+        write_vram(&mut ppu, 0x2F20, &[0xA2]);
+        // $3F10 is a mirror of $3F00
+        write_vram(&mut ppu, 0x3F10, &[0x2D]);
+
+        // Palette reads come back right away, the buffer gets the nametable byte under them
+        for (addr, buffered) in [(0x3F00, 0xA0), (0x3F10, 0xA1), (0x3F20, 0xA2)] {
+            assert_eq!(read_vram(&mut ppu, addr), 0x2D, "${:04X}", addr);
+            assert_eq!(ppu.data_buffer, buffered, "${:04X}", addr);
+        }
+
+        // Greyscale keeps only the luma bits
+        ppu.write_to_mask_register(0x01);
+        assert_eq!(read_vram(&mut ppu, 0x3F00), 0x20);
+        assert_eq!(ppu.data_buffer, 0xA0);
+    }
+
+    #[test]
+    fn ppu_pixel_provenance_for_hand_built_frame() {
+        let mut ppu = layered_ppu();
+        place_sprite(&mut ppu, 0, 40, 0x00);
+        place_sprite(&mut ppu, 1, 200, 0x20);
+        place_sprite(&mut ppu, 2, 80, 0x20);
+        run_frame(&mut ppu);
+        assert!(ppu.pixel_provenance().is_none());
+
+        ppu.debug_provenance = true;
+        run_frame(&mut ppu);
+        let provenance = ppu.pixel_provenance().unwrap();
+        let at = |x: usize, y: usize| {
+            let pixel = provenance[y * SCREEN_WIDTH + x];
+            (pixel.background_color, pixel.sprite, pixel.sprite_in_front, pixel.sprite_zero_hit)
+        };
+        // Sprite 0 in front of the opaque background
+        assert_eq!(at(44, 84), (1, Some(0), true, true));
+        // Behind-background sprites over a transparent and an opaque background
+        assert_eq!(at(204, 84), (0, Some(1), true, false));
+        assert_eq!(at(84, 84), (1, Some(2), false, false));
+        // Nothing but the backdrop
+        assert_eq!(at(150, 10), (0, None, false, false));
+    }
+
+    #[test]
+    fn ppu_sprite_overflow_without_the_bug() {
+        // (sprite 8, sprite 9, overflow without the bug, overflow with it)
+        let cases = [
+            ([100, 0xF0, 0xF0, 0xF0], [0xF0; 4], true, true),
+            ([0xF0; 4], [0xF0, 100, 0xF0, 0xF0], false, true),
+            ([0xF0; 4], [100, 0xF0, 0xF0, 0xF0], true, false),
+        ];
+        for (sprite_8, sprite_9, accurate, buggy) in cases {
+            let mut ppu = overflow_ppu(sprite_8, sprite_9);
+            ppu.sprite_overflow_bug = false;
+            assert_eq!(evaluate_overflow(&mut ppu), accurate, "{:?} {:?}", sprite_8, sprite_9);
+            ppu.sprite_overflow_bug = true;
+            assert_eq!(evaluate_overflow(&mut ppu), buggy, "{:?} {:?}", sprite_8, sprite_9);
+        }
+    }
+
+    #[test]
+    fn ppu_warm_up_ignores_control_writes() {
+        let cartridge = Cartridge::from_parts(vec![0; 0x8000], Vec::new(), 0, Mirroring::Horizontal);
+        let mut ppu = PPU::new(cartridge.create_mapper(), Mirroring::Horizontal, Region::Ntsc);
+        ppu.write_to_control_register(0x80);
+        assert_eq!(ppu.control_register, 0x00);
+
+        while ppu.warming_up() {
+            ppu.clock();
+        }
+        ppu.write_to_control_register(0x80);
+        assert_eq!(ppu.control_register, 0x80);
+    }
+
+    #[test]
+    fn ppu_scanline_events_once_per_rendered_line() {
+        let mut ppu = test_ppu();
+        ppu.scanline_events_enabled = true;
+        ppu.write_to_mask_register(0x18);
+        run_frame(&mut ppu);
+        ppu.take_scanline_events();
+        run_frame(&mut ppu);
+
+        // Every visible line plus the pre-render line, all with rendering on
+        let events = ppu.take_scanline_events();
+        assert_eq!(events.len(), 241);
+        let mut scanlines: Vec<u16> = events.iter().map(|&(scanline, _)| scanline).collect();
+        scanlines.sort_unstable();
+        assert_eq!(scanlines, [(0..240).collect(), vec![261]].concat());
+        assert!(events.iter().all(|&(_, rendering)| rendering));
+    }
+
+    // Position right after the clock that raised sprite zero hit, for a sprite on line 80 at x=100
+    fn sprite_zero_hit_position(accurate: bool) -> (u16, u16) {
+        let mut ppu = test_ppu();
+        ppu.accurate_sprite_zero_hit = accurate;
+        write_vram(&mut ppu, 0x0000, &[[0xFF; 8], [0x00; 8]].concat());
+        write_vram(&mut ppu, 0x0000, &[]);
+        ppu.oam[0..4].copy_from_slice(&[79, 0x00, 0x00, 100]);
+        ppu.write_to_mask_register(0x1E);
+        run_frame(&mut ppu);
+        while ppu.status_register & PPUStatusFlags::SpriteZeroHit as u8 == 0 {
+            ppu.clock();
+        }
+        ppu.position()
+    }
+
+    #[test]
+    fn ppu_sprite_zero_hit_dot() {
+        // Accurate mode raises the flag on dot 101, where pixel 100 is drawn
+        assert_eq!(sprite_zero_hit_position(true), (80, 102));
+        // Fast mode only checks the line once its last pixel is out
+        assert_eq!(sprite_zero_hit_position(false), (80, 257));
+    }
+
+    #[test]
+    fn ppu_frame_sink_gets_one_frame_per_frame() {
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x3F00, &[RED]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        ppu.frame_sink = Some(sender);
+
+        for _ in 0..5 {
+            run_frame(&mut ppu);
+        }
+        ppu.frame_sink = None;
+
+        let frames: Vec<Vec<u8>> = receiver.iter().collect();
+        assert_eq!(frames.len(), 5);
+        // Packed RGB of the finished picture
+        assert_eq!(frames[4].len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        let (r, g, b) = color(RED);
+        assert_eq!(frames[4][..3], [r, g, b]);
+    }
+
+    #[test]
+    fn ppu_eight_sprite_cutoff_and_fetch_addresses() {
+        // Ten solid sprites on lines 80-87 using tiles $10-$19 of the $1000 table, 16 pixels apart.
+        // Sprite 2 is flipped vertically.
+        let mut ppu = test_ppu();
+        write_vram(&mut ppu, 0x1100, &[[0xFF; 8], [0x00; 8]].concat().repeat(10));
+        write_vram(&mut ppu, 0x3F00, &[BLACK]);
+        write_vram(&mut ppu, 0x3F11, &[WHITE]);
+        write_vram(&mut ppu, 0x0000, &[]);
+        for index in 0..10 {
+            let attributes = if index == 2 { 0x80 } else { 0x00 };
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[79, 0x10 + index as u8, attributes, index as u8 * 16]);
+        }
+        ppu.write_to_control_register(0x08);
+        ppu.write_to_mask_register(0x1E);
+        run_frame(&mut ppu);
+
+        // The fetches at the end of line 79 load the first row of each sprite for line 80
+        run_to(&mut ppu, 79, 321);
+        let fetched: Vec<(u8, u16)> =
+            ppu.scanline_sprites.iter().map(|sprite| (sprite.oam_index, sprite.pattern_addr)).collect();
+        let expected: Vec<(u8, u16)> = (0..8u8)
+            .map(|index| (index, 0x1000 + (0x10 + index as u16) * 16 + if index == 2 { 7 } else { 0 }))
+            .collect();
+        assert_eq!(fetched, expected);
+
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 7 * 16, 80), color(WHITE));
+        assert_eq!(pixel(&ppu, 8 * 16, 80), color(BLACK));
+        assert_eq!(pixel(&ppu, 9 * 16, 80), color(BLACK));
+    }
+
+    #[test]
+    fn ppu_status_low_bits_come_from_the_latch() {
+        let mut ppu = test_ppu();
+        ppu.write_to_control_register(0xFF);
+        assert_eq!(ppu.read_status_register(), 0x1F);
+
+        // The $2002 read refreshed the latch, it's only gone after a full decay period
+        for _ in 0..OPEN_BUS_DECAY_CYCLES - 1 {
+            ppu.clock();
+        }
+        assert_eq!(ppu.read_io_latch(), 0x1F);
+        ppu.clock();
+        assert_eq!(ppu.read_status_register(), 0x00);
+    }
+
+    #[test]
+    fn ppu_status_read_clears_vblank_and_keeps_latch_bits() {
+        let mut ppu = test_ppu();
+        run_to(&mut ppu, 241, 2);
+        ppu.write_to_control_register(0xFF);
+        assert_eq!(ppu.read_status_register(), 0x9F);
+        assert_eq!(ppu.read_status_register(), 0x1F);
+    }
+
+    #[test]
+    fn ppu_mid_frame_scroll_write_shows_on_later_lines() {
+        let mut ppu = test_ppu();
+        ppu.write_to_mask_register(0x0A);
+
+        // Written mid-line, the horizontal copy at dot 257 picks it up for the next line
+        run_to(&mut ppu, 100, 100);
+        ppu.write_to_scroll_register(0x40);
+        ppu.write_to_scroll_register(0x00);
+        run_frame(&mut ppu);
+
+        for (line, registers) in ppu.scanline_registers()[..240].iter().enumerate() {
+            let expected = if line <= 100 { 0x00 } else { 0x40 };
+            assert_eq!(registers.scroll_x, expected, "line {}", line);
+        }
+    }
 }
@@ -0,0 +1,98 @@
+// Cheat finder over the 2KB of work RAM. Every search keeps the candidate addresses that pass
+// the filter, comparing against the RAM as it was at the previous search.
+const RAM_SIZE: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchFilter {
+    Equal, // Equal to the searched value
+    Changed,
+    Unchanged,
+    Greater, // Greater than at the previous search
+    Less,
+}
+
+impl SearchFilter {
+    pub const ALL: [SearchFilter; 5] = [
+        SearchFilter::Equal,
+        SearchFilter::Changed,
+        SearchFilter::Unchanged,
+        SearchFilter::Greater,
+        SearchFilter::Less,
+    ];
+
+    fn matches(self, previous: u8, current: u8, value: u8) -> bool {
+        match self {
+            SearchFilter::Equal => current == value,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Greater => current > previous,
+            SearchFilter::Less => current < previous,
+        }
+    }
+}
+
+impl std::fmt::Display for SearchFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchFilter::Equal => write!(f, "Equal to"),
+            SearchFilter::Changed => write!(f, "Changed"),
+            SearchFilter::Unchanged => write!(f, "Unchanged"),
+            SearchFilter::Greater => write!(f, "Greater than previous"),
+            SearchFilter::Less => write!(f, "Less than previous"),
+        }
+    }
+}
+
+pub struct RamSearch {
+    pub candidates: Vec<u16>,
+    snapshot: [u8; RAM_SIZE],
+    pub searches: u32,
+}
+
+impl RamSearch {
+    // Starts over with every address as a candidate and the current RAM as the previous values
+    pub fn new(ram: &[u8; RAM_SIZE]) -> RamSearch {
+        RamSearch {
+            candidates: (0..RAM_SIZE as u16).collect(),
+            snapshot: *ram,
+            searches: 0,
+        }
+    }
+
+    // value is only used by SearchFilter::Equal
+    pub fn search(&mut self, ram: &[u8; RAM_SIZE], filter: SearchFilter, value: u8) {
+        let snapshot = &self.snapshot;
+        self.candidates
+            .retain(|&addr| filter.matches(snapshot[addr as usize], ram[addr as usize], value));
+        self.snapshot = *ram;
+        self.searches += 1;
+    }
+
+    // Value of the address at the last search
+    pub fn previous(&self, addr: u16) -> u8 {
+        self.snapshot[addr as usize % RAM_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_search_narrows_to_the_changed_address() {
+        // The lives counter at $0123 goes from 3 to 5. $0010 stays at 3 and $0200 is 5 all along.
+        let mut ram = [0u8; RAM_SIZE];
+        ram[0x0010] = 3;
+        ram[0x0123] = 3;
+        ram[0x0200] = 5;
+        let mut search = RamSearch::new(&ram);
+        search.search(&ram, SearchFilter::Equal, 3);
+        assert_eq!(search.candidates, [0x0010, 0x0123]);
+
+        ram[0x0123] = 5;
+        search.search(&ram, SearchFilter::Equal, 5);
+        assert_eq!(search.candidates, [0x0123]);
+        assert_eq!(search.previous(0x0123), 5);
+        assert_eq!(search.searches, 2);
+    }
+}
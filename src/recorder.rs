@@ -0,0 +1,82 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+const FRAME_WIDTH: usize = 256;
+const FRAME_HEIGHT: usize = 240;
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mkv", "webm", "avi"];
+
+// Writes the PPU's native RGB frames on a worker thread so disk or encoder speed never stalls emulation.
+// Frames come from `PPU::frame_sink`, one per emulated frame; recording ends when that sender is dropped.
+pub struct Recorder {
+    pub path: String,
+    worker: JoinHandle<Result<u64, String>>,
+}
+
+impl Recorder {
+    // A video file extension pipes the frames through ffmpeg, anything else is a directory of numbered PPM frames
+    pub fn start(path: &str, frame_rate: f64) -> Result<(Recorder, Sender<Vec<u8>>), String> {
+        let (sender, receiver) = mpsc::channel();
+        let is_video = Path::new(path)
+            .extension()
+            .is_some_and(|extension| VIDEO_EXTENSIONS.iter().any(|video| extension.eq_ignore_ascii_case(video)));
+
+        let worker = if is_video {
+            let mut child = Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+                .args(["-s", &format!("{}x{}", FRAME_WIDTH, FRAME_HEIGHT)])
+                .args(["-r", &frame_rate.to_string(), "-i", "-", "-pix_fmt", "yuv420p", path])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("Couldn't start ffmpeg: {}", e))?;
+            let stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+
+            thread::spawn(move || {
+                let frames = write_frames(receiver, BufWriter::new(stdin));
+                let status = child.wait().map_err(|e| e.to_string())?;
+                if !status.success() {
+                    return Err(format!("ffmpeg exited with {}", status));
+                }
+                frames
+            })
+        } else {
+            fs::create_dir_all(path).map_err(|e| e.to_string())?;
+            let directory = path.to_string();
+            thread::spawn(move || write_ppm_frames(receiver, &directory))
+        };
+
+        Ok((Recorder { path: path.to_string(), worker }, sender))
+    }
+
+    // Waits for every queued frame to be written, the frame sender must be dropped first
+    pub fn finish(self) -> Result<u64, String> {
+        self.worker.join().map_err(|_| "Recording thread panicked".to_string())?
+    }
+}
+
+fn write_frames(receiver: Receiver<Vec<u8>>, mut writer: impl Write) -> Result<u64, String> {
+    let mut frames = 0;
+    for frame in receiver {
+        writer.write_all(&frame).map_err(|e| e.to_string())?;
+        frames += 1;
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(frames)
+}
+
+fn write_ppm_frames(receiver: Receiver<Vec<u8>>, directory: &str) -> Result<u64, String> {
+    let mut frames = 0;
+    for frame in receiver {
+        let path = Path::new(directory).join(format!("frame_{:06}.ppm", frames));
+        let mut writer = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        write!(writer, "P6\n{} {}\n255\n", FRAME_WIDTH, FRAME_HEIGHT).map_err(|e| e.to_string())?;
+        writer.write_all(&frame).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        frames += 1;
+    }
+    Ok(frames)
+}
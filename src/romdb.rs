@@ -0,0 +1,207 @@
+use crate::cartridge::{Cartridge, Mirroring, Region};
+use std::collections::HashMap;
+
+// What the NES 2.0 database knows about one dump
+#[derive(Debug, Clone)]
+pub struct RomDbEntry {
+    pub title: String,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub region: Option<Region>, // None for multi-region games
+    pub mirroring: Option<Mirroring>, // None when the mapper controls it
+    // Volatile plus battery backed RAM in bytes, 0 when the board has none
+    pub prg_ram_size: usize,
+    pub chr_ram_size: usize,
+}
+
+// Lookup table built from nes20db.xml, keyed by the PRG+CHR hashes of each <rom> element
+pub struct RomDb {
+    entries: Vec<RomDbEntry>,
+    by_sha1: HashMap<String, usize>,
+    by_crc32: HashMap<u32, usize>,
+}
+
+impl RomDb {
+    pub fn load(path: &str) -> Result<RomDb, String> {
+        let xml = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let db = RomDb::parse(&xml);
+        if db.entries.is_empty() {
+            return Err(format!("No games found in {}", path));
+        }
+        Ok(db)
+    }
+
+    // Only the handful of elements needed for identification are read, the rest of the schema is skipped
+    pub fn parse(xml: &str) -> RomDb {
+        let mut db = RomDb {
+            entries: Vec::new(),
+            by_sha1: HashMap::new(),
+            by_crc32: HashMap::new(),
+        };
+
+        for game in xml.split("<game>").skip(1) {
+            let game = game.split("</game>").next().unwrap_or(game);
+            let Some(rom) = element(game, "rom") else {
+                continue;
+            };
+            let pcb = element(game, "pcb").unwrap_or("");
+            let console = element(game, "console").unwrap_or("");
+            let size = |name: &str| {
+                element(game, name)
+                    .and_then(|ram| attribute(ram, "size"))
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .unwrap_or(0)
+            };
+
+            // The title only appears in the comment holding the original file path
+            let title = game
+                .split("<!--")
+                .nth(1)
+                .and_then(|comment| comment.split("-->").next())
+                .map(|path| path.trim().rsplit(['/', '\\']).next().unwrap_or(path))
+                .map(|file| file.trim_end_matches(".nes").to_string())
+                .unwrap_or_default();
+
+            let entry = RomDbEntry {
+                title,
+                mapper: attribute(pcb, "mapper").and_then(|value| value.parse().ok()).unwrap_or(0),
+                submapper: attribute(pcb, "submapper").and_then(|value| value.parse().ok()).unwrap_or(0),
+                region: match attribute(console, "region") {
+                    Some("1") => Some(Region::Pal),
+                    Some("2") => None,
+                    Some("3") => Some(Region::Dendy),
+                    _ => Some(Region::Ntsc),
+                },
+                mirroring: match attribute(pcb, "mirroring") {
+                    Some("H") => Some(Mirroring::Horizontal),
+                    Some("V") => Some(Mirroring::Vertical),
+                    Some("4") => Some(Mirroring::FourScreen),
+                    _ => None,
+                },
+                prg_ram_size: size("prgram") + size("prgnvram"),
+                chr_ram_size: size("chrram") + size("chrnvram"),
+            };
+
+            let index = db.entries.len();
+            if let Some(sha1) = attribute(rom, "sha1") {
+                db.by_sha1.insert(sha1.to_ascii_uppercase(), index);
+            }
+            if let Some(crc32) = attribute(rom, "crc32").and_then(|value| u32::from_str_radix(value, 16).ok()) {
+                db.by_crc32.insert(crc32, index);
+            }
+            db.entries.push(entry);
+        }
+
+        db
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // SHA-1 is authoritative, CRC32 is only a fallback for entries without one
+    pub fn lookup(&self, cartridge: &Cartridge) -> Option<&RomDbEntry> {
+        self.by_sha1
+            .get(&cartridge.sha1_hex())
+            .or_else(|| self.by_crc32.get(&cartridge.crc32))
+            .map(|&index| &self.entries[index])
+    }
+}
+
+// Attributes of the first <name .../> element in the text
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{} ", name))?;
+    let end = xml[start..].find('>')?;
+    Some(&xml[start..start + end])
+}
+
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!(" {}=\"", name);
+    let start = element.find(&key)? + key.len();
+    let end = element[start..].find('"')?;
+    Some(&element[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NROM with horizontal mirroring, PRG and CHR filled with the seed so each image hashes differently
+    fn nrom_cartridge(seed: u8) -> Cartridge {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![seed; 0x4000 + 0x2000]);
+        Cartridge::from_bytes(data, false, Default::default()).unwrap()
+    }
+
+    // Trimmed nes20db.xml: the first game is matched by SHA-1, the second only has a CRC32
+    fn test_db(by_sha1: &Cartridge, by_crc32: &Cartridge) -> RomDb {
+        RomDb::parse(&format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+  <game>
+    <!-- NES\Licensed\Bad Header (USA).nes -->
+    <prgrom size="16384" crc32="00000000" sha1="0000000000000000000000000000000000000000"/>
+    <rom size="24576" crc32="{:08X}" sha1="{}"/>
+    <prgram size="2048"/>
+    <prgnvram size="2048"/>
+    <pcb mapper="4" submapper="1" mirroring="V" battery="1"/>
+    <console type="0" region="1"/>
+  </game>
+  <game>
+    <!-- NES/Unlicensed/No Hash.nes -->
+    <rom size="24576" crc32="{:08X}"/>
+    <pcb mapper="0" submapper="0" mirroring="H" battery="0"/>
+    <console type="0" region="0"/>
+  </game>
+</nes20db>"#,
+            by_sha1.crc32,
+            by_sha1.sha1_hex().to_ascii_lowercase(),
+            by_crc32.crc32
+        ))
+    }
+
+    #[test]
+    fn romdb_parses_games() {
+        let db = test_db(&nrom_cartridge(1), &nrom_cartridge(2));
+        assert_eq!(db.len(), 2);
+
+        let entry = db.lookup(&nrom_cartridge(1)).unwrap();
+        assert_eq!(entry.title, "Bad Header (USA)");
+        assert_eq!((entry.mapper, entry.submapper), (4, 1));
+        assert_eq!(entry.region, Some(Region::Pal));
+        assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+        assert_eq!((entry.prg_ram_size, entry.chr_ram_size), (0x1000, 0));
+    }
+
+    #[test]
+    fn romdb_lookup_hits_and_misses() {
+        let db = test_db(&nrom_cartridge(1), &nrom_cartridge(2));
+        assert_eq!(db.lookup(&nrom_cartridge(1)).unwrap().title, "Bad Header (USA)");
+        assert_eq!(db.lookup(&nrom_cartridge(2)).unwrap().title, "No Hash");
+        assert!(db.lookup(&nrom_cartridge(3)).is_none());
+
+        // A matching CRC32 alone doesn't win over a SHA-1 that's in the database for another game
+        let mut collision = nrom_cartridge(2);
+        collision.sha1 = nrom_cartridge(1).sha1;
+        assert_eq!(db.lookup(&collision).unwrap().title, "Bad Header (USA)");
+    }
+
+    #[test]
+    fn romdb_entry_rewrites_header() {
+        let mut cartridge = nrom_cartridge(1);
+        assert_eq!(cartridge.prg_ram_size(), 0x2000);
+
+        let db = test_db(&nrom_cartridge(1), &nrom_cartridge(2));
+        cartridge.apply_db_entry(db.lookup(&nrom_cartridge(1)).unwrap()).unwrap();
+        assert_eq!(cartridge.mapper, 4);
+        assert_eq!(cartridge.submapper(), Some(1));
+        assert_eq!(cartridge.region, Region::Pal);
+        assert_eq!(cartridge.mirror, Mirroring::Vertical);
+        assert_eq!(cartridge.prg_ram_size(), 0x1000);
+        assert_eq!(cartridge.chr_ram_size(), 0);
+    }
+}
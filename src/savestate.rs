@@ -0,0 +1,131 @@
+const MAGIC: [u8; 4] = *b"RNST";
+const VERSION: u8 = 5;
+// Magic, version and the CRC32 of the ROM the state was made with
+const HEADER_SIZE: usize = 9;
+
+// A snapshot of the whole machine, serialized into a flat little endian byte stream
+#[derive(Debug, Clone)]
+pub struct SaveState {
+    pub data: Vec<u8>,
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(rom_crc32: u32) -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&rom_crc32.to_le_bytes());
+        StateWriter { data }
+    }
+
+    pub fn finish(self) -> SaveState {
+        SaveState { data: self.data }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length prefixed byte block
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    pub rom_crc32: u32,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(state: &'a SaveState) -> Result<Self, String> {
+        if state.data.len() < 5 || state.data[0..4] != MAGIC {
+            return Err("Not a runes save state".to_string());
+        }
+        if state.data[4] != VERSION {
+            return Err(format!("Unsupported save state version: {}", state.data[4]));
+        }
+        if state.data.len() < HEADER_SIZE {
+            return Err("Save state is truncated".to_string());
+        }
+
+        Ok(StateReader {
+            rom_crc32: u32::from_le_bytes([state.data[5], state.data[6], state.data[7], state.data[8]]),
+            data: &state.data,
+            position: HEADER_SIZE,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position + len;
+        if end > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    // Reads a byte block into a buffer that must already have the right size
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err("Save state doesn't match the loaded cartridge".to_string());
+        }
+
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
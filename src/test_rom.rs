@@ -0,0 +1,110 @@
+use crate::cpu::CPU;
+
+// Status protocol of blargg style test ROMs: $6000 holds the status, $6001-$6003 a signature
+// that marks the status as valid, and $6004 a zero terminated result message
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const MESSAGE_ADDR: u16 = 0x6004;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+// The ROM asks for a reset no sooner than 100ms after requesting it
+const RESET_DELAY_FRAMES: u32 = 8;
+const MAX_MESSAGE_LEN: u16 = 0x1000;
+
+pub enum TestRomResult {
+    Passed(String),
+    Failed(u8, String),
+    TimedOut,
+}
+
+// Runs a test ROM from reset until it reports a result, or gives up after max_frames
+pub fn run_test_rom(cpu: &mut CPU, max_frames: u32) -> TestRomResult {
+    cpu.reset();
+
+    let mut reset_countdown = None;
+    for _ in 0..max_frames {
+        cpu.run_to_vblank();
+
+        let signature = (0..SIGNATURE.len() as u16).map(|offset| cpu.bus.peek(SIGNATURE_ADDR + offset));
+        if !signature.eq(SIGNATURE) {
+            continue;
+        }
+
+        match cpu.bus.peek(STATUS_ADDR) {
+            STATUS_RUNNING => {}
+            STATUS_RESET_REQUESTED => match reset_countdown {
+                Some(0) => {
+                    cpu.reset();
+                    cpu.bus.ppu.reset();
+                    reset_countdown = None;
+                }
+                Some(frames) => reset_countdown = Some(frames - 1),
+                None => reset_countdown = Some(RESET_DELAY_FRAMES),
+            },
+            0x00 => return TestRomResult::Passed(read_message(cpu)),
+            status => return TestRomResult::Failed(status, read_message(cpu)),
+        }
+    }
+
+    TestRomResult::TimedOut
+}
+
+fn read_message(cpu: &CPU) -> String {
+    let mut message = String::new();
+    for offset in 0..MAX_MESSAGE_LEN {
+        let byte = cpu.bus.peek(MESSAGE_ADDR + offset);
+        if byte == 0 {
+            break;
+        }
+        message.push(byte as char);
+    }
+    message.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    // NROM with 8KB of PRG RAM running the source from reset, then spinning on a JMP to itself
+    fn test_rom_cpu(source: &str) -> CPU {
+        let mut program = assemble(source, 0x8000).unwrap();
+        let spin = 0x8000 + program.len() as u16;
+        program.extend(assemble(&format!("jmp ${:04X}", spin), spin).unwrap());
+
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+        CPU::new(Cartridge::from_parts(prg, Vec::new(), 0, Mirroring::Horizontal))
+    }
+
+    // Signature, a two letter message, then the final status
+    fn reporting_source(status: u8, message: [u8; 2]) -> String {
+        format!(
+            "lda #$DE / sta $6001 / lda #$B0 / sta $6002 / lda #$61 / sta $6003 / lda #$80 / sta $6000
+             lda #${:02X} / sta $6004 / lda #${:02X} / sta $6005 / lda #0 / sta $6006
+             lda #${:02X} / sta $6000",
+            message[0], message[1], status
+        )
+    }
+
+    #[test]
+    fn test_rom_reports_pass() {
+        let mut cpu = test_rom_cpu(&reporting_source(0x00, *b"ok"));
+        assert!(matches!(run_test_rom(&mut cpu, 10), TestRomResult::Passed(message) if message == "ok"));
+    }
+
+    #[test]
+    fn test_rom_reports_failure_code() {
+        let mut cpu = test_rom_cpu(&reporting_source(0x03, *b"no"));
+        assert!(matches!(run_test_rom(&mut cpu, 10), TestRomResult::Failed(0x03, message) if message == "no"));
+    }
+
+    #[test]
+    fn test_rom_without_signature_times_out() {
+        let mut cpu = test_rom_cpu("lda #0 / sta $6000");
+        assert!(matches!(run_test_rom(&mut cpu, 10), TestRomResult::TimedOut));
+    }
+}
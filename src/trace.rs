@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Streams one line per executed instruction to a file
+pub struct TraceLog {
+    writer: BufWriter<File>,
+    pub path: String,
+    pub lines: u64,
+    pub line_cap: Option<u64>, // Stops logging after this many lines to bound disk usage
+}
+
+impl TraceLog {
+    pub fn create(path: &str, line_cap: Option<u64>) -> Result<TraceLog, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        Ok(TraceLog {
+            writer: BufWriter::with_capacity(1 << 16, file),
+            path: path.to_string(),
+            lines: 0,
+            line_cap,
+        })
+    }
+
+    // Returns false once the log is full or can't be written anymore
+    pub fn write_line(&mut self, line: &str) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        if writeln!(self.writer, "{}", line).is_err() {
+            return false;
+        }
+        self.lines += 1;
+        !self.is_full()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.line_cap.is_some_and(|cap| self.lines >= cap)
+    }
+
+    pub fn finish(mut self) -> Result<u64, String> {
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.lines)
+    }
+}
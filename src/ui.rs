@@ -1,20 +1,103 @@
 use eframe::egui;
-use std::time::{Duration, Instant};
-use crate::cpu::CPU;
-use crate::ppu::SYSTEM_PALLETE;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use crate::battery::{BatterySave, DEFAULT_FLUSH_INTERVAL_SECONDS};
+use crate::bus::UNMAPPED_LOG_CAPACITY;
+use crate::cartridge::{Cartridge, Region};
+use crate::controller::ButtonLatch;
+use crate::cpu::{CPU, MAX_STEP_CLOCKS};
+use crate::ppu::{load_pal_file, save_pal_file, PalettePreset};
 use egui_dock::{DockArea, NodeIndex, Style, Tree};
 
 use crate::opcodes::references;
-use crate::renderer;
+use crate::ram_search::{RamSearch, SearchFilter};
+use crate::recorder::Recorder;
+use crate::romdb::RomDb;
+use crate::savestate::SaveState;
+use crate::trace::TraceLog;
 
-const CPU_CLOCK_HZ: f64 = 1_789_773.0;
-const PPU_CLOCK_HZ: f64 = CPU_CLOCK_HZ * 3.0;
-const TARGET_FPS: f64 = 60.0988;
 const MAX_TIMESTEP: Duration = Duration::from_millis(100);
 const DEFAULT_UI_SCALE: f32 = 1.0;
 const UI_SCALE_ENV: &str = "RUNES_UI_SCALE";
+const MAX_FRAME_SKIP: u32 = 9;
+const WINDOW_SETTINGS_PATH: &str = "runes-window.cfg";
+const MAX_LISTED_CANDIDATES: usize = 100;
+// Keyboard bindings for controller 1, with the button's bit in standard pad order
+const PAD_KEYS: [(egui::Key, u8); 12] = [
+    (egui::Key::Z, 0),
+    (egui::Key::X, 1),
+    (egui::Key::Tab, 2),
+    (egui::Key::Enter, 3),
+    (egui::Key::ArrowUp, 4),
+    (egui::Key::W, 4),
+    (egui::Key::ArrowDown, 5),
+    (egui::Key::S, 5),
+    (egui::Key::ArrowLeft, 6),
+    (egui::Key::A, 6),
+    (egui::Key::ArrowRight, 7),
+    (egui::Key::D, 7),
+];
+const TURBO_KEY_CHOICES: [egui::Key; 8] = [
+    egui::Key::K,
+    egui::Key::J,
+    egui::Key::C,
+    egui::Key::B,
+    egui::Key::Q,
+    egui::Key::E,
+    egui::Key::I,
+    egui::Key::U,
+];
+// Safety cap so a runaway ROM can't lock up the UI thread, single steps are capped by the CPU
+const MAX_BUDGET_CYCLES: u64 = 2 * 341 * 312 * 6;
+// How often the FPS in the window title is recomputed
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+const ROM_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Window options for streaming and overlays, kept between runs in WINDOW_SETTINGS_PATH
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct WindowSettings {
+    always_on_top: bool,
+    borderless: bool,
+    fullscreen: bool,
+}
 
-pub fn ui(cpu: CPU) -> Result<(), eframe::Error> {
+impl WindowSettings {
+    // One key=true/false per line, a missing file leaves everything off
+    fn load() -> WindowSettings {
+        let mut settings = WindowSettings::default();
+        let Ok(text) = std::fs::read_to_string(WINDOW_SETTINGS_PATH) else {
+            return settings;
+        };
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            let enabled = value.trim() == "true";
+            match key.trim() {
+                "always_on_top" => settings.always_on_top = enabled,
+                "borderless" => settings.borderless = enabled,
+                "fullscreen" => settings.fullscreen = enabled,
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let text = format!(
+            "always_on_top={}\nborderless={}\nfullscreen={}\n",
+            self.always_on_top, self.borderless, self.fullscreen
+        );
+        std::fs::write(WINDOW_SETTINGS_PATH, text).map_err(|e| e.to_string())
+    }
+
+    // Decorations are set even while fullscreen so leaving fullscreen returns to the chosen border
+    fn apply(&self, frame: &mut eframe::Frame) {
+        frame.set_fullscreen(self.fullscreen);
+        frame.set_decorations(!self.borderless);
+        frame.set_always_on_top(self.always_on_top);
+    }
+}
+
+// load_error is shown in place of the game picture when the ROM given on the command line couldn't be loaded
+pub fn ui(cpu: CPU, rom_name: String, load_error: Option<String>) -> Result<(), eframe::Error> {
     env_logger::init();
     let ui_scale = std::env::var(UI_SCALE_ENV)
         .ok()
@@ -22,8 +105,12 @@ pub fn ui(cpu: CPU) -> Result<(), eframe::Error> {
         .filter(|value| *value > 0.0)
         .unwrap_or(DEFAULT_UI_SCALE);
 
+    let window = WindowSettings::load();
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::Vec2::new(1920.0, 1080.0)),
+        always_on_top: window.always_on_top,
+        decorated: !window.borderless,
+        fullscreen: window.fullscreen,
         ..Default::default()
     };
 
@@ -33,7 +120,7 @@ pub fn ui(cpu: CPU) -> Result<(), eframe::Error> {
         Box::new(move |cc| {
             let pixels_per_point = cc.egui_ctx.pixels_per_point();
             cc.egui_ctx.set_pixels_per_point(pixels_per_point * ui_scale);
-            Box::<RunesApp>::new(RunesApp::new(cpu))
+            Box::<RunesApp>::new(RunesApp::new(cpu, rom_name, load_error, window))
         }))
 }
 
@@ -41,14 +128,92 @@ struct RunesContext {
     cpu: CPU,
     page_cpu: u16,
     page_rom: u16,
-
-    chr_rom_texture: Option<egui::TextureHandle>,
+    // Linear mode browses the whole PRG ROM by 16KB bank and page, ignoring the mapper
+    rom_linear: bool,
+    rom_bank: usize,
+    rom_bank_page: usize,
+    follow_pc: bool,
+    jump_address_input: String,
+
+    pattern_table_textures: [Option<egui::TextureHandle>; 2],
+    pattern_table_palette: u8,
+    nametable_texture: Option<egui::TextureHandle>,
+    sprite_texture: Option<egui::TextureHandle>,
+    selected_sprite: usize,
     frame_texture: Option<egui::TextureHandle>,
     running: bool,
     chr_rom_dirty: bool,
+    nametable_dirty: bool,
     palette_snapshot: [u8; 32],
+    palette_path: String,
+    palette_preset: Option<PalettePreset>, // None once a .pal file has been imported
     last_tick: Instant,
     ppu_cycle_accumulator: f64,
+
+    // Frame skip
+    frame_skip: u32,
+    auto_frame_skip: bool,
+    frame_over_budget: bool,
+    skipped_frames: u32,
+
+    opcode_breakpoint_input: String,
+    freeze_address_input: String,
+    trace_path: String,
+    trace_line_cap: u64,
+    recording_path: String,
+    recorder: Option<Recorder>,
+    clipboard: Option<arboard::Clipboard>, // Opened on first use, kept so the image outlives the copy on X11
+    freeze_value_input: String,
+    ram_search: RamSearch,
+    ram_search_filter: SearchFilter,
+    ram_search_value_input: String,
+    run_to_scanline: u16,
+    run_to_dot: u16,
+    break_scanline: u16,
+    nmi_scanline: u16,
+    step_count: u32,
+    step_frame_count: u32,
+
+    quick_save: Option<SaveState>,
+    battery_save: Option<BatterySave>,
+    battery_flush_interval: f64,
+    status_message: Option<String>,
+    load_error: Option<String>, // No ROM is loaded, the game view shows this until one is
+    raster_overlay: bool,
+    split_overlay: bool,
+    highlight_transparent_background: bool,
+    highlight_sprite_zero: bool,
+    provenance_texture: Option<egui::TextureHandle>,
+
+    turbo_a_key: egui::Key,
+    turbo_b_key: egui::Key,
+    mic_button_held: bool,
+    pad_buttons: ButtonLatch,
+
+    // Emulation stops while the window is in the background, without touching `running`
+    pause_on_focus_loss: bool,
+    focus_paused: bool,
+
+    // Window title: loaded ROM plus emulated frames per second of real time
+    rom_name: String,
+    window_title: String,
+    fps: f64,
+    fps_window_start: Instant,
+    fps_window_frame: u64,
+
+    // Requested window options and the ones the window currently has
+    window: WindowSettings,
+    applied_window: WindowSettings,
+
+    // NES 2.0 database for identifying dumps and correcting their headers
+    rom_db: Option<RomDb>,
+    rom_db_path: String,
+    fix_headers_on_load: bool,
+
+    // Hot reload: the ROM file is polled for a new modification time while watching
+    watch_rom: bool,
+    rom_modified: Option<SystemTime>,
+    rom_checked: Instant,
 }
 
 impl egui_dock::TabViewer for RunesContext {
@@ -61,10 +226,15 @@ impl egui_dock::TabViewer for RunesContext {
             "CPU Register Inspector" => self.cpu_register_inspector(ui),
             "CPU Debug Inspector" => self.cpu_debug_inspector(ui),
             "Controller Inspector" => self.controller_inspector(ui),
+            "Cheats" => self.cheats(ui),
             "ROM Memory Inspector" => self.rom_memory_inspector(ui),
             "ROM Header Inspector" => self.rom_header_inspector(ui),
             "CHR ROM Inspector" => self.chr_rom_inspector(ui),
             "Color Palette" => self.color_palette_inspector(ui),
+            "Nametable Viewer" => self.nametable_viewer(ui),
+            "Unmapped Accesses" => self.unmapped_access_log(ui),
+            "OAM Viewer" => self.oam_viewer(ui),
+            "Mapper Inspector" => self.mapper_inspector(ui),
             _ => {}
         }
     }
@@ -72,35 +242,45 @@ impl egui_dock::TabViewer for RunesContext {
     fn title(&mut self, tab: &mut Self::Tab) -> egui::WidgetText {
         tab.as_str().into()
     }
-
-    
 }
 
 impl RunesContext {
     fn run_frame(&mut self) -> bool {
-        const FRAME_CYCLES: u32 = 341 * 262;
-        for _ in 0..FRAME_CYCLES {
+        let frame_cycles = 341 * self.cpu.bus.ppu.scanlines_per_frame() as u32;
+        for _ in 0..frame_cycles {
             self.cpu.clock();
-            if self.cpu.bus.ppu.frame_complete {
-                self.cpu.bus.ppu.frame_complete = false;
+            if self.cpu.bus.ppu.poll_frame_complete() {
+                self.latch_controller_state();
                 return true;
             }
+            if self.cpu.breakpoint_hit.is_some() {
+                break;
+            }
         }
         false
     }
 
     fn run_for_budget(&mut self, budget: Duration) -> bool {
-        self.ppu_cycle_accumulator += budget.as_secs_f64() * PPU_CLOCK_HZ;
+        self.ppu_cycle_accumulator += budget.as_secs_f64() * self.cpu.bus.region.ppu_clock_hz();
         let cycles_to_run = self.ppu_cycle_accumulator.floor() as u64;
         self.ppu_cycle_accumulator -= cycles_to_run as f64;
 
+        if cycles_to_run > MAX_BUDGET_CYCLES {
+            self.ppu_cycle_accumulator = 0.0;
+            self.status_message = Some(format!("Skipped a run of {} cycles, timing reset", cycles_to_run));
+            return false;
+        }
+
         let mut frame_complete = false;
         for _ in 0..cycles_to_run {
             self.cpu.clock();
-            if self.cpu.bus.ppu.frame_complete {
-                self.cpu.bus.ppu.frame_complete = false;
+            if self.cpu.bus.ppu.poll_frame_complete() {
+                self.latch_controller_state();
                 frame_complete = true;
             }
+            if self.cpu.breakpoint_hit.is_some() {
+                break;
+            }
         }
 
         frame_complete
@@ -121,11 +301,67 @@ impl RunesContext {
         self.ppu_cycle_accumulator = 0.0;
     }
 
+    // Decides whether a completed frame gets uploaded to the texture.
+    // With a frame skip of N only every (N+1)th frame is presented; auto frame skip
+    // drops frames (up to MAX_FRAME_SKIP in a row) while the UI runs over budget.
+    fn should_present_frame(&mut self) -> bool {
+        let skip = if self.auto_frame_skip && self.frame_over_budget {
+            MAX_FRAME_SKIP
+        } else {
+            self.frame_skip
+        };
+
+        let present = if self.skipped_frames < skip {
+            self.skipped_frames += 1;
+            false
+        } else {
+            self.skipped_frames = 0;
+            true
+        };
+
+        // Frames that won't be shown skip the pixel work in the PPU
+        self.cpu.bus.ppu.render_enabled = self.skipped_frames >= skip;
+        present
+    }
+
+    fn step_out(&mut self) {
+        if !self.cpu.step_out() && self.cpu.breakpoint_hit.is_none() {
+            self.status_message = Some("Step out: no return within a frame".to_string());
+        }
+    }
+
     fn step_instruction(&mut self) {
-        loop {
-            self.cpu.clock();
-            if self.cpu.complete() {
-                break;
+        if !self.cpu.step_instruction() {
+            self.status_message = Some(format!(
+                "Instruction at {:04X} didn't complete within {} clocks",
+                self.cpu.program_counter, MAX_STEP_CLOCKS
+            ));
+        }
+    }
+
+    // Runs up to count instructions, stopping after the one that hits a breakpoint
+    fn step_instructions(&mut self, count: u32) {
+        self.cpu.breakpoint_hit = None;
+        for step in 1..=count {
+            self.step_instruction();
+            if self.cpu.breakpoint_hit.is_some() {
+                self.status_message = Some(format!("Breakpoint after {} of {} instructions", step, count));
+                return;
+            }
+            if self.cpu.halted {
+                self.status_message = Some(format!("CPU halted after {} of {} instructions", step, count));
+                return;
+            }
+        }
+    }
+
+    fn step_frames(&mut self, count: u32) {
+        self.cpu.breakpoint_hit = None;
+        for frame in 1..=count {
+            self.run_frame();
+            if self.cpu.breakpoint_hit.is_some() {
+                self.status_message = Some(format!("Breakpoint during frame {} of {}", frame, count));
+                return;
             }
         }
     }
@@ -133,47 +369,299 @@ impl RunesContext {
     fn reset(&mut self) {
         self.cpu.reset();
         self.cpu.bus.ppu.reset();
+        self.cpu.bus.apu.reset();
     }
 
-    fn update_controller_state(&mut self, ctx: &egui::Context) {
-        let state = ctx.input(|i| {
-            let mut state = 0u8;
-            if i.key_down(egui::Key::Z) {
-                state |= 1 << 0;
+    // Swaps in a new cartridge, keeping the debugger settings
+    fn load_rom(&mut self, path: &str) -> Result<(), String> {
+        let mut cartridge = Cartridge::new(path).map_err(|e| e.to_string())?;
+        if self.fix_headers_on_load {
+            if let Some(entry) = self.rom_db.as_ref().and_then(|db| db.lookup(&cartridge)) {
+                cartridge.apply_db_entry(entry).map_err(|e| e.to_string())?;
+            }
+        }
+        self.load_cartridge(cartridge);
+        Ok(())
+    }
+
+    // Swaps in a new cartridge, keeping the debugger and video settings of the current session
+    fn load_cartridge(&mut self, mut cartridge: Cartridge) {
+        self.flush_battery_save();
+        cartridge.save_directory = self.cpu.bus.cartridge.save_directory.clone();
+
+        let mut cpu = CPU::new(cartridge);
+        cpu.opcode_breakpoints = std::mem::take(&mut self.cpu.opcode_breakpoints);
+        cpu.break_on_illegal = self.cpu.break_on_illegal;
+        cpu.ignore_state_rom_mismatch = self.cpu.ignore_state_rom_mismatch;
+        cpu.bus.ppu.system_palette = self.cpu.bus.ppu.system_palette;
+        cpu.bus.ppu.sprite_overflow_bug = self.cpu.bus.ppu.sprite_overflow_bug;
+        cpu.bus.ppu.sprite_limit_enabled = self.cpu.bus.ppu.sprite_limit_enabled;
+        cpu.bus.ppu.warm_up_enabled = self.cpu.bus.ppu.warm_up_enabled;
+        cpu.bus.ppu.accurate_sprite_zero_hit = self.cpu.bus.ppu.accurate_sprite_zero_hit;
+        cpu.bus.ppu.break_scanline = self.cpu.bus.ppu.break_scanline;
+        cpu.bus.ppu.nmi_scanline = self.cpu.bus.ppu.nmi_scanline;
+        cpu.bus.famicom_mode = self.cpu.bus.famicom_mode;
+        cpu.bus.swap_controllers = self.cpu.bus.swap_controllers;
+        cpu.bus.ppu.frame_sink = self.cpu.bus.ppu.frame_sink.take();
+
+        self.cpu = cpu;
+        self.load_error = None;
+        self.reset();
+        self.reset_timing();
+        // Saved states belong to the previous cartridge
+        self.quick_save = None;
+        self.chr_rom_dirty = true;
+        self.palette_snapshot = self.cpu.bus.ppu.palette;
+        self.ram_search = RamSearch::new(&self.cpu.bus.cpu_vram);
+        self.rom_modified = self.rom_file_modified();
+        self.open_battery_save();
+    }
+
+    fn rom_file_modified(&self) -> Option<SystemTime> {
+        let path = self.cpu.bus.cartridge.rom_path.as_ref()?;
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // Reads the ROM file again. With the same mapper and sizes the machine state carries over
+    // so code changes show up live, anything else gets a power cycle.
+    fn reload_rom(&mut self) {
+        let Some(path) = self.cpu.bus.cartridge.rom_path.clone() else {
+            self.status_message = Some("No ROM file to reload".to_string());
+            return;
+        };
+        let cartridge = match Cartridge::new_with_overrides(&path.to_string_lossy(), self.cpu.bus.cartridge.overrides) {
+            Ok(cartridge) => cartridge,
+            Err(error) => {
+                // Keep the last good build running, a half written file is often the cause
+                self.rom_modified = self.rom_file_modified();
+                self.status_message = Some(format!("Reload failed: {}", error));
+                return;
+            }
+        };
+
+        if !cartridge.same_layout(&self.cpu.bus.cartridge) {
+            self.load_cartridge(cartridge);
+            self.status_message = Some(format!("Reloaded {} with a power cycle, the mapper or sizes changed", path.display()));
+            return;
+        }
+
+        // The new mapper picks up the old bank registers and RAM through the save state
+        let state = self.cpu.save_state();
+        let quick_save = self.quick_save.take();
+        self.load_cartridge(cartridge);
+        match self.cpu.load_state_from_previous_build(&state) {
+            Ok(()) => {
+                self.quick_save = quick_save;
+                self.status_message = Some(format!("Reloaded {}", path.display()));
             }
-            if i.key_down(egui::Key::X) {
-                state |= 1 << 1;
+            Err(error) => {
+                self.reset();
+                self.status_message = Some(format!("Reloaded {} with a reset: {}", path.display(), error));
             }
-            if i.key_down(egui::Key::Tab) {
-                state |= 1 << 2;
+        }
+        self.nametable_dirty = true;
+    }
+
+    fn poll_rom_file(&mut self) {
+        if !self.watch_rom || self.rom_checked.elapsed() < ROM_WATCH_INTERVAL {
+            return;
+        }
+        self.rom_checked = Instant::now();
+
+        let modified = self.rom_file_modified();
+        if modified.is_some() && modified != self.rom_modified {
+            self.reload_rom();
+        }
+    }
+
+    fn open_battery_save(&mut self) {
+        match BatterySave::open(&mut self.cpu, self.battery_flush_interval) {
+            Ok(save) => self.battery_save = save,
+            Err(error) => {
+                self.battery_save = None;
+                self.status_message = Some(format!("Battery save not loaded: {}", error));
             }
-            if i.key_down(egui::Key::Enter) {
-                state |= 1 << 3;
+        }
+    }
+
+    fn flush_battery_save(&mut self) {
+        let Some(save) = &mut self.battery_save else {
+            return;
+        };
+        if let Err(error) = save.flush(&self.cpu) {
+            self.status_message = Some(format!("Failed to write {}: {}", save.path.display(), error));
+        }
+    }
+
+    // Writes the battery RAM every flush interval of emulated time while it keeps changing
+    fn update_battery_save(&mut self) {
+        let Some(save) = &mut self.battery_save else {
+            return;
+        };
+        save.flush_interval = self.battery_flush_interval;
+        if let Err(error) = save.update(&self.cpu) {
+            self.status_message = Some(format!("Failed to write {}: {}", save.path.display(), error));
+        }
+    }
+
+    fn load_rom_db(&mut self) {
+        match RomDb::load(&self.rom_db_path) {
+            Ok(db) => {
+                self.status_message = Some(format!("Loaded {} games from {}", db.len(), self.rom_db_path));
+                self.rom_db = Some(db);
             }
-            if i.key_down(egui::Key::ArrowUp) || i.key_down(egui::Key::W) {
-                state |= 1 << 4;
+            Err(error) => self.status_message = Some(format!("Couldn't load ROM database: {}", error)),
+        }
+    }
+
+    // Restarts the current game with its header replaced by the database entry
+    fn apply_rom_db_header(&mut self) {
+        let mut cartridge = self.cpu.bus.cartridge.clone();
+        let Some(entry) = self.rom_db.as_ref().and_then(|db| db.lookup(&cartridge)) else {
+            return;
+        };
+        match cartridge.apply_db_entry(entry) {
+            Ok(()) => {
+                self.load_cartridge(cartridge);
+                self.status_message = Some("Restarted with the database header".to_string());
             }
-            if i.key_down(egui::Key::ArrowDown) || i.key_down(egui::Key::S) {
-                state |= 1 << 5;
+            Err(error) => self.status_message = Some(error.to_string()),
+        }
+    }
+
+    fn load_dropped_file(&mut self, path: &Path) {
+        let is_pal_file = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pal"));
+        if is_pal_file {
+            self.palette_path = path.to_string_lossy().into_owned();
+            self.import_palette();
+            return;
+        }
+
+        let is_nes_file = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("nes"));
+        if !is_nes_file {
+            self.status_message = Some(format!("Not a .nes file: {}", path.display()));
+            return;
+        }
+
+        match self.load_rom(&path.to_string_lossy()) {
+            Ok(()) => {
+                self.status_message = Some(format!("Loaded {}", path.display()));
+                self.rom_name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
             }
-            if i.key_down(egui::Key::ArrowLeft) || i.key_down(egui::Key::A) {
-                state |= 1 << 6;
+            Err(e) => self.status_message = Some(format!("Failed to load {}: {}", path.display(), e)),
+        }
+    }
+
+    fn import_palette(&mut self) {
+        match load_pal_file(&self.palette_path) {
+            Ok(palette) => {
+                self.cpu.bus.ppu.set_system_palette(palette);
+                self.palette_preset = None;
+                self.chr_rom_dirty = true;
+                self.nametable_dirty = true;
+                self.status_message = Some(format!("Loaded palette {}", self.palette_path));
             }
-            if i.key_down(egui::Key::ArrowRight) || i.key_down(egui::Key::D) {
-                state |= 1 << 7;
+            Err(e) => self.status_message = Some(format!("Failed to load palette: {}", e)),
+        }
+    }
+
+    fn select_palette_preset(&mut self, preset: PalettePreset) {
+        self.cpu.bus.ppu.set_system_palette(preset.colors());
+        self.palette_preset = Some(preset);
+        self.chr_rom_dirty = true;
+        self.nametable_dirty = true;
+    }
+
+    fn export_palette(&mut self) {
+        match save_pal_file(&self.palette_path, &self.cpu.bus.ppu.system_palette) {
+            Ok(()) => self.status_message = Some(format!("Exported palette to {}", self.palette_path)),
+            Err(e) => self.status_message = Some(format!("Failed to export palette: {}", e)),
+        }
+    }
+
+    fn quick_save(&mut self) {
+        self.quick_save = Some(self.cpu.save_state());
+        self.status_message = Some("State saved".to_string());
+    }
+
+    fn quick_load(&mut self) {
+        let Some(state) = &self.quick_save else {
+            self.status_message = Some("No saved state".to_string());
+            return;
+        };
+
+        match self.cpu.load_state(state) {
+            Ok(()) => {
+                // CHR RAM and the palette came back from the state, refresh the inspectors
+                self.chr_rom_dirty = true;
+                self.palette_snapshot = self.cpu.bus.ppu.palette;
+                self.status_message = Some("State loaded".to_string());
+            }
+            Err(e) => self.status_message = Some(format!("Failed to load state: {}", e)),
+        }
+    }
+
+    fn update_controller_state(&mut self, ctx: &egui::Context) {
+        let (held, pressed) = ctx.input(|i| {
+            let mut held = 0u8;
+            let mut pressed = 0u8;
+            for (key, bit) in PAD_KEYS {
+                if i.key_down(key) {
+                    held |= 1 << bit;
+                }
+                if i.key_pressed(key) {
+                    pressed |= 1 << bit;
+                }
             }
-            state
+            (held, pressed)
         });
+        self.pad_buttons.update(held, pressed);
 
-        self.cpu.bus.set_controller_state(0, state);
+        let turbo = ctx.input(|i| {
+            let mut turbo = 0u8;
+            if i.key_down(self.turbo_a_key) {
+                turbo |= 1 << 0;
+            }
+            if i.key_down(self.turbo_b_key) {
+                turbo |= 1 << 1;
+            }
+            turbo
+        });
+
+        let mic_key = ctx.input(|i| i.key_down(egui::Key::M));
+        self.cpu.bus.microphone = mic_key || self.mic_button_held;
+
+        self.cpu.bus.set_turbo_state(0, turbo);
         self.cpu.bus.set_controller_state(1, 0);
+        // Nothing is emulated while paused, so there are no frame boundaries to wait for
+        if !self.running || self.focus_paused {
+            self.latch_controller_state();
+        }
     }
 
-    fn update_frame_texture(&mut self, ctx: &egui::Context) {
-        let image = egui::ColorImage::from_rgb(
-            [256, 240],
-            &self.cpu.bus.ppu.frame_buffer,
-        );
+    // Buttons only reach the console at emulated frame boundaries, so the repaint rate doesn't change
+    // how long a press lasts
+    fn latch_controller_state(&mut self) {
+        let buttons = self.pad_buttons.latch();
+        self.cpu.bus.set_controller_state(0, buttons);
+    }
+
+    // While stepping the frame is shown as far as the PPU got, otherwise the last completed one
+    fn update_frame_texture(&mut self, ctx: &egui::Context, in_progress: bool) {
+        let ppu = &mut self.cpu.bus.ppu;
+        let frame = if in_progress {
+            ppu.in_progress_frame()
+        } else {
+            match ppu.take_completed_frame() {
+                Some(frame) => frame,
+                None => return,
+            }
+        };
+        let image = egui::ColorImage::from_rgba_unmultiplied([256, 240], frame);
 
         if let Some(texture) = &mut self.frame_texture {
             texture.set(image, Default::default());
@@ -208,7 +696,7 @@ impl RunesContext {
         let mut best_index = 0;
         let mut best_distance = u32::MAX;
 
-        for (index, (pr, pg, pb)) in SYSTEM_PALLETE.iter().enumerate() {
+        for (index, (pr, pg, pb)) in self.cpu.bus.ppu.system_palette.iter().enumerate() {
             let dr = r - *pr as i32;
             let dg = g - *pg as i32;
             let db = b - *pb as i32;
@@ -222,29 +710,48 @@ impl RunesContext {
         best_index as u8
     }
 
-    fn palette_rgb(&self, palette_index: u8) -> (u8, u8, u8) {
-        let value = self.palette_value(palette_index);
-        SYSTEM_PALLETE[value as usize]
-    }
-
     fn cpu_memory_inspector(&mut self, ui: &mut egui::Ui) {
         // change style to monospace
         ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
 
+        let pc = self.cpu.program_counter;
+        if self.follow_pc {
+            self.page_cpu = pc >> 8;
+        }
+
         // page selector
         ui.horizontal(|ui| {
             ui.label("Page: ");
-            ui.add(egui::DragValue::new(&mut self.page_cpu).speed(1.0).clamp_range(0..=0x07));
+            ui.add(egui::DragValue::new(&mut self.page_cpu).speed(1.0).clamp_range(0..=0xFF));
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
         });
 
+        ui.horizontal(|ui| {
+            ui.label("Jump to: ");
+            ui.add(egui::TextEdit::singleline(&mut self.jump_address_input).desired_width(40.0));
+            if ui.button("Go").clicked() {
+                let input = self.jump_address_input.trim().trim_start_matches('$');
+                if let Ok(addr) = u16::from_str_radix(input, 16) {
+                    self.page_cpu = addr >> 8;
+                    self.follow_pc = false;
+                }
+            }
+        });
+
+        let highlight = ui.visuals().selection.bg_fill;
         for addr in 0..=15 {
             ui.horizontal(|ui| {
                 ui.label(format!("{:02X}{:2X}0", self.page_cpu, addr));
                 ui.separator();
                 for i in 0..=15 {
-                    // format as hex
-                    // only print when read from page 8000 ~ 8010
-                    ui.label(format!("{:02X}", self.cpu.bus.cpu_vram[(self.page_cpu << 8 | addr << 4 | i) as usize]));
+                    let cpu_addr = self.page_cpu << 8 | addr << 4 | i;
+                    let text = egui::RichText::new(format!("{:02X}", self.cpu.bus.peek(cpu_addr)));
+                    // highlight the byte at the program counter
+                    if cpu_addr == pc {
+                        ui.label(text.background_color(highlight));
+                    } else {
+                        ui.label(text);
+                    }
                 }
             });
         }
@@ -254,6 +761,12 @@ impl RunesContext {
         // change style to monospace
         ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
 
+        ui.checkbox(&mut self.rom_linear, "Linear PRG ROM offsets");
+        if self.rom_linear {
+            self.rom_linear_view(ui);
+            return;
+        }
+
         // page selector
         ui.horizontal(|ui| {
             ui.label("Page: ");
@@ -273,6 +786,34 @@ impl RunesContext {
         }
     }
 
+    fn rom_linear_view(&mut self, ui: &mut egui::Ui) {
+        let prg_rom = &self.cpu.bus.cartridge.prg_rom;
+        let last_bank = (prg_rom.len() / 0x4000).max(1) - 1;
+
+        ui.horizontal(|ui| {
+            ui.label("Bank: ");
+            ui.add(egui::DragValue::new(&mut self.rom_bank).speed(1.0).clamp_range(0..=last_bank));
+            ui.label("Page: ");
+            ui.add(egui::DragValue::new(&mut self.rom_bank_page).speed(1.0).clamp_range(0..=0x3F));
+            ui.label(format!("of {} x 16KB", last_bank + 1));
+        });
+
+        let base = self.rom_bank.min(last_bank) * 0x4000 + self.rom_bank_page * 0x100;
+        for row in 0..16 {
+            ui.horizontal(|ui| {
+                let offset = base + row * 16;
+                ui.label(format!("{:05X}", offset));
+                ui.separator();
+                for i in 0..16 {
+                    match prg_rom.get(offset + i) {
+                        Some(byte) => ui.label(format!("{:02X}", byte)),
+                        None => ui.label("--"),
+                    };
+                }
+            });
+        }
+    }
+
     fn cpu_register_inspector(&mut self, ui: &mut egui::Ui) {
         // change style to monospace
         ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
@@ -304,14 +845,328 @@ impl RunesContext {
 
         ui.horizontal(|ui| {
             ui.label("Status: ");
-            ui.label(format!("{:08b}", self.cpu.status));
+            ui.label(format!("{:02X} {:08b}", self.cpu.status, self.cpu.status));
         });
 
+        // One letter per status bit from bit 7 down, set flags are highlighted
+        let highlight = ui.visuals().selection.bg_fill;
+        ui.horizontal(|ui| {
+            for (bit, name) in ["N", "V", "-", "B", "D", "I", "Z", "C"].iter().enumerate() {
+                let text = egui::RichText::new(*name);
+                if self.cpu.status & (0x80 >> bit) != 0 {
+                    ui.label(text.strong().background_color(highlight));
+                } else {
+                    ui.label(text.weak());
+                }
+            }
+        });
     }
 
     fn cpu_debug_inspector(&mut self, ui: &mut egui::Ui) {
         ui.label(format!("Opcode {}", references::INSTRUCTION_LOOKUP[self.cpu.opcode as usize]));       
         ui.label(format!("Cycles: {:?}", self.cpu.cycles));
+
+        let (scanline, dot) = self.cpu.bus.ppu.position();
+        let parity = if self.cpu.bus.ppu.is_odd_frame() { "odd" } else { "even" };
+        ui.label(format!(
+            "Frame {} ({}), scanline {}, dot {}",
+            self.cpu.bus.ppu.frame_count, parity, scanline, dot
+        ));
+        ui.horizontal(|ui| {
+            ui.label("Run to: ");
+            let last_scanline = self.cpu.bus.ppu.pre_render_scanline();
+            ui.add(egui::DragValue::new(&mut self.run_to_scanline).speed(1.0).clamp_range(0..=last_scanline));
+            ui.add(egui::DragValue::new(&mut self.run_to_dot).speed(1.0).clamp_range(0..=340));
+            if ui.button("Go").clicked() {
+                self.running = false;
+                self.cpu.run_to_ppu_position(self.run_to_scanline, self.run_to_dot);
+                self.update_frame_texture(ui.ctx(), true);
+            }
+        });
+        ui.horizontal(|ui| {
+            let last_scanline = self.cpu.bus.ppu.pre_render_scanline();
+            let mut break_enabled = self.cpu.bus.ppu.break_scanline.is_some();
+            ui.checkbox(&mut break_enabled, "Break at scanline");
+            ui.add(egui::DragValue::new(&mut self.break_scanline).speed(1.0).clamp_range(0..=last_scanline));
+            self.cpu.bus.ppu.break_scanline = break_enabled.then_some(self.break_scanline);
+
+            let mut nmi_enabled = self.cpu.bus.ppu.nmi_scanline.is_some();
+            ui.checkbox(&mut nmi_enabled, "NMI at scanline")
+                .on_hover_text("Raise an NMI when the line starts, even with NMIs disabled in $2000");
+            ui.add(egui::DragValue::new(&mut self.nmi_scanline).speed(1.0).clamp_range(0..=last_scanline));
+            self.cpu.bus.ppu.nmi_scanline = nmi_enabled.then_some(self.nmi_scanline);
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.step_count).speed(10.0).clamp_range(1..=u32::MAX));
+            if ui.button("Step instructions").clicked() {
+                self.running = false;
+                self.step_instructions(self.step_count);
+                self.update_frame_texture(ui.ctx(), true);
+            }
+            ui.add(egui::DragValue::new(&mut self.step_frame_count).speed(1.0).clamp_range(1..=u32::MAX));
+            if ui.button("Step frames").clicked() {
+                self.running = false;
+                self.step_frames(self.step_frame_count);
+                self.update_frame_texture(ui.ctx(), self.cpu.breakpoint_hit.is_some());
+            }
+        });
+
+        if self.cpu.halted {
+            ui.label(format!("Halted: JAM at {:04X}", self.cpu.program_counter));
+        }
+
+        if let Some((pc, opcode)) = self.cpu.breakpoint_hit {
+            if self.cpu.bus.ppu.break_scanline == Some(scanline) && dot == 1 {
+                ui.label(format!("Break at scanline {}, PC {:04X}", scanline, pc));
+            } else {
+                ui.label(format!("Breakpoint: {:02X} at {:04X}", opcode, pc));
+            }
+        }
+
+        ui.separator();
+        ui.label("Trace log");
+        ui.horizontal(|ui| {
+            ui.add_enabled(
+                self.cpu.trace_log.is_none(),
+                egui::TextEdit::singleline(&mut self.trace_path).desired_width(120.0),
+            );
+            ui.label("Line cap (0 = none): ");
+            ui.add(egui::DragValue::new(&mut self.trace_line_cap).speed(1000.0));
+        });
+        ui.horizontal(|ui| match &self.cpu.trace_log {
+            Some(log) => {
+                let state = if log.is_full() { "full" } else { "tracing" };
+                ui.label(format!("{} lines, {}", log.lines, state));
+                if ui.button("Stop").clicked() {
+                    self.stop_trace();
+                }
+            }
+            None => {
+                if ui.button("Start").clicked() {
+                    self.start_trace();
+                }
+            }
+        });
+
+        ui.label("Opcode breakpoints");
+        ui.horizontal(|ui| {
+            ui.label("Opcode: ");
+            ui.add(egui::TextEdit::singleline(&mut self.opcode_breakpoint_input).desired_width(30.0));
+            if ui.button("Add").clicked() {
+                if let Ok(opcode) = u8::from_str_radix(self.opcode_breakpoint_input.trim(), 16) {
+                    self.cpu.opcode_breakpoints.insert(opcode);
+                    self.opcode_breakpoint_input.clear();
+                }
+            }
+        });
+        ui.checkbox(&mut self.cpu.break_on_illegal, "Break on illegal opcodes");
+
+        let mut opcodes: Vec<u8> = self.cpu.opcode_breakpoints.iter().copied().collect();
+        opcodes.sort_unstable();
+        for opcode in opcodes {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}", references::INSTRUCTION_LOOKUP[opcode as usize]));
+                if ui.small_button("Remove").clicked() {
+                    self.cpu.opcode_breakpoints.remove(&opcode);
+                }
+            });
+        }
+    }
+
+    fn cheats(&mut self, ui: &mut egui::Ui) {
+        ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+
+        ui.label("Frozen RAM addresses");
+        ui.horizontal(|ui| {
+            ui.label("Address: ");
+            ui.add(egui::TextEdit::singleline(&mut self.freeze_address_input).desired_width(40.0));
+            ui.label("Value: ");
+            ui.add(egui::TextEdit::singleline(&mut self.freeze_value_input).desired_width(30.0));
+            if ui.button("Freeze").clicked() {
+                let address = u16::from_str_radix(self.freeze_address_input.trim(), 16);
+                let value = u8::from_str_radix(self.freeze_value_input.trim(), 16);
+                if let (Ok(address), Ok(value)) = (address, value) {
+                    if self.cpu.bus.freeze(address, value) {
+                        self.freeze_address_input.clear();
+                        self.freeze_value_input.clear();
+                    } else {
+                        self.status_message = Some(format!("Can't freeze {:04X}, only RAM can be frozen", address));
+                    }
+                }
+            }
+        });
+
+        let mut frozen: Vec<(u16, u8)> = self.cpu.bus.frozen.iter().map(|(&a, &v)| (a, v)).collect();
+        frozen.sort_unstable();
+        for (address, value) in frozen {
+            ui.horizontal(|ui| {
+                ui.label(format!("{:04X} = {:02X}", address, value));
+                if ui.small_button("Thaw").clicked() {
+                    self.cpu.bus.thaw(address);
+                }
+            });
+        }
+
+        ui.separator();
+        self.ram_search(ui);
+    }
+
+    // Narrows down the RAM address holding a value, to find what to freeze
+    fn ram_search(&mut self, ui: &mut egui::Ui) {
+        ui.label("RAM search");
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("ram-search-filter")
+                .selected_text(self.ram_search_filter.to_string())
+                .show_ui(ui, |ui| {
+                    for filter in SearchFilter::ALL {
+                        ui.selectable_value(&mut self.ram_search_filter, filter, filter.to_string());
+                    }
+                });
+            ui.add_enabled(
+                self.ram_search_filter == SearchFilter::Equal,
+                egui::TextEdit::singleline(&mut self.ram_search_value_input).desired_width(30.0),
+            );
+            if ui.button("Search").clicked() {
+                match u8::from_str_radix(self.ram_search_value_input.trim(), 16) {
+                    Err(_) if self.ram_search_filter == SearchFilter::Equal => {
+                        self.status_message = Some("Enter a hex value to search for".to_string());
+                    }
+                    value => {
+                        let value = value.unwrap_or(0);
+                        self.ram_search.search(&self.cpu.bus.cpu_vram, self.ram_search_filter, value);
+                    }
+                }
+            }
+            if ui.button("Reset").clicked() {
+                self.ram_search = RamSearch::new(&self.cpu.bus.cpu_vram);
+            }
+        });
+
+        ui.label(format!(
+            "{} candidates after {} searches",
+            self.ram_search.candidates.len(),
+            self.ram_search.searches
+        ));
+        let mut freeze = None;
+        egui::ScrollArea::vertical().id_source("ram-search-candidates").show(ui, |ui| {
+            for &address in self.ram_search.candidates.iter().take(MAX_LISTED_CANDIDATES) {
+                ui.horizontal(|ui| {
+                    let current = self.cpu.bus.cpu_vram[address as usize];
+                    ui.label(format!(
+                        "{:04X}: {:02X} (was {:02X})",
+                        address,
+                        current,
+                        self.ram_search.previous(address)
+                    ));
+                    if ui.small_button("Freeze").clicked() {
+                        freeze = Some((address, current));
+                    }
+                });
+            }
+        });
+        if let Some((address, value)) = freeze {
+            self.cpu.bus.freeze(address, value);
+        }
+    }
+
+    fn start_trace(&mut self) {
+        let line_cap = if self.trace_line_cap == 0 { None } else { Some(self.trace_line_cap) };
+        match TraceLog::create(&self.trace_path, line_cap) {
+            Ok(log) => self.cpu.trace_log = Some(log),
+            Err(e) => self.status_message = Some(format!("Failed to start trace: {}", e)),
+        }
+    }
+
+    fn stop_trace(&mut self) {
+        let Some(log) = self.cpu.trace_log.take() else {
+            return;
+        };
+
+        let path = log.path.clone();
+        match log.finish() {
+            Ok(lines) => self.status_message = Some(format!("Wrote {} trace lines to {}", lines, path)),
+            Err(e) => self.status_message = Some(format!("Failed to write trace: {}", e)),
+        }
+    }
+
+    // Emulated frames per real second, measured over FPS_WINDOW
+    fn update_window_title(&mut self) -> Option<&str> {
+        let elapsed = self.fps_window_start.elapsed();
+        if elapsed >= FPS_WINDOW {
+            let frame_count = self.cpu.bus.ppu.frame_count;
+            // A freshly loaded ROM starts counting from 0 again
+            let frames = frame_count.saturating_sub(self.fps_window_frame);
+            self.fps = frames as f64 / elapsed.as_secs_f64();
+            self.fps_window_start = Instant::now();
+            self.fps_window_frame = frame_count;
+        }
+
+        let title = if self.running && !self.focus_paused {
+            format!("runes \u{2014} {} \u{2014} {:.0} fps", self.rom_name, self.fps)
+        } else {
+            format!("runes \u{2014} {} \u{2014} paused", self.rom_name)
+        };
+
+        if title == self.window_title {
+            return None;
+        }
+        self.window_title = title;
+        Some(&self.window_title)
+    }
+
+    // Copies the last completed frame as an image, or saves it as a PNG where there's no image clipboard
+    fn copy_frame_to_clipboard(&mut self) {
+        let frame = self.cpu.bus.ppu.completed_frame().to_vec();
+        let clipboard = match self.clipboard.take() {
+            Some(clipboard) => Ok(clipboard),
+            None => arboard::Clipboard::new(),
+        };
+        let copied = clipboard.and_then(|mut clipboard| {
+            let image = arboard::ImageData {
+                width: 256,
+                height: 240,
+                bytes: std::borrow::Cow::Borrowed(&frame),
+            };
+            let result = clipboard.set_image(image);
+            self.clipboard = Some(clipboard);
+            result
+        });
+
+        let error = match copied {
+            Ok(()) => {
+                self.status_message = Some("Copied frame to clipboard".to_string());
+                return;
+            }
+            Err(e) => e,
+        };
+
+        let path = format!("runes-frame-{}.png", self.cpu.bus.ppu.frame_count);
+        match std::fs::write(&path, crate::png::encode_rgba(256, 240, &frame)) {
+            Ok(()) => self.status_message = Some(format!("No image clipboard ({}), saved frame to {}", error, path)),
+            Err(e) => self.status_message = Some(format!("Failed to copy frame: {}, saving failed too: {}", error, e)),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            // Dropping the sender ends the frame stream, the worker then drains what's queued
+            self.cpu.bus.ppu.frame_sink = None;
+            let path = recorder.path.clone();
+            match recorder.finish() {
+                Ok(frames) => self.status_message = Some(format!("Recorded {} frames to {}", frames, path)),
+                Err(e) => self.status_message = Some(format!("Recording failed: {}", e)),
+            }
+            return;
+        }
+
+        match Recorder::start(&self.recording_path, self.cpu.bus.region.frame_rate()) {
+            Ok((recorder, sink)) => {
+                self.cpu.bus.ppu.frame_sink = Some(sink);
+                self.recorder = Some(recorder);
+                self.status_message = Some(format!("Recording to {}", self.recording_path));
+            }
+            Err(e) => self.status_message = Some(format!("Failed to start recording: {}", e)),
+        }
     }
 
     fn controller_inspector(&mut self, ui: &mut egui::Ui) {
@@ -338,71 +1193,290 @@ impl RunesContext {
                 ui.label(if pressed { "ON" } else { "off" });
             });
         }
+
+        ui.separator();
+        ui.checkbox(&mut self.cpu.bus.swap_controllers, "Swap ports")
+            .on_hover_text("Controller 1 is read from $4017 and controller 2 from $4016");
+        ui.checkbox(&mut self.cpu.bus.famicom_mode, "Famicom mode");
+        ui.add_enabled_ui(self.cpu.bus.famicom_mode, |ui| {
+            ui.horizontal(|ui| {
+                // Held like the real mic, only active while the mouse button is down on it
+                let mic = ui.button("Mic (hold)");
+                self.mic_button_held = mic.is_pointer_button_down_on();
+                ui.label(if self.cpu.bus.microphone { "ON" } else { "off" });
+            });
+        });
+    }
+
+    fn unmapped_access_log(&mut self, ui: &mut egui::Ui) {
+        ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Last {} unmapped accesses", UNMAPPED_LOG_CAPACITY));
+            ui.label(format!("Unmapped CHR reads: {}", self.cpu.bus.ppu.unmapped_chr_reads.get()));
+            if ui.button("Clear").clicked() {
+                self.cpu.bus.unmapped_accesses.clear();
+            }
+        });
+
+        egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+            for access in &self.cpu.bus.unmapped_accesses {
+                let kind = if access.write { "W" } else { "R" };
+                ui.label(format!(
+                    "{:>10}  {}  ${:04X}  {:02X}",
+                    access.cycle, kind, access.address, access.value
+                ));
+            }
+        });
     }
 
     fn rom_header_inspector(&mut self, ui: &mut egui::Ui) {
         ui.label(format!("PRG ROM Size: {}", self.cpu.bus.cartridge.header.prg_rom_size));
         ui.label(format!("CHR ROM Size: {}", self.cpu.bus.cartridge.header.chr_rom_size));
-        ui.label(format!("Mapper: {}", (self.cpu.bus.cartridge.header.mapper_2 & 0xF0) | (self.cpu.bus.cartridge.header.mapper_1 >> 4)));
+        let cartridge = &self.cpu.bus.cartridge;
+        let marker = |overridden: bool| if overridden { " (overridden)" } else { "" };
+        ui.label(format!("Mapper: {}{}", cartridge.mapper, marker(cartridge.overrides.mapper.is_some())));
+        ui.label(format!("Mirroring: {}{}", cartridge.mirror, marker(cartridge.overrides.mirroring.is_some())));
+        let prg_ram_size = cartridge.prg_ram_size();
+        ui.label(format!(
+            "PRG RAM: {}{}",
+            if prg_ram_size % 1024 == 0 { format!("{} KB", prg_ram_size / 1024) } else { format!("{} bytes", prg_ram_size) },
+            marker(cartridge.overrides.prg_ram_kb.is_some())
+        ));
+        let chr_ram_size = cartridge.chr_ram_size();
+        if chr_ram_size > 0 {
+            ui.label(format!(
+                "CHR RAM: {}{}",
+                if chr_ram_size % 1024 == 0 { format!("{} KB", chr_ram_size / 1024) } else { format!("{} bytes", chr_ram_size) },
+                if cartridge.has_chr_rom_and_ram() { " (with CHR ROM)" } else { "" }
+            ));
+        }
+        ui.label(format!("Region: {}", self.cpu.bus.cartridge.region));
+        if let Some(save) = &self.battery_save {
+            ui.label(format!("Battery save: {}", save.path.display()));
+            ui.horizontal(|ui| {
+                ui.label("Write every");
+                ui.add(egui::DragValue::new(&mut self.battery_flush_interval).speed(1.0).clamp_range(1.0..=600.0));
+                ui.label("s of play");
+            });
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Reload ROM").clicked() {
+                self.reload_rom();
+            }
+            ui.checkbox(&mut self.watch_rom, "Reload on change");
+        });
+
+        ui.separator();
+        ui.label(format!("CRC32: {:08X}", self.cpu.bus.cartridge.crc32));
+        ui.label(format!("SHA-1: {}", self.cpu.bus.cartridge.sha1_hex()));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.rom_db_path);
+            if ui.button("Load database").clicked() {
+                self.load_rom_db();
+            }
+        });
+        ui.checkbox(&mut self.fix_headers_on_load, "Fix headers on load");
+
+        let mut use_db_header = false;
+        if let Some(db) = &self.rom_db {
+            match db.lookup(&self.cpu.bus.cartridge) {
+                Some(entry) => {
+                    ui.label(format!("Database: {}", entry.title));
+                    ui.label(format!("Mapper: {}, submapper {}", entry.mapper, entry.submapper));
+                    ui.label(format!(
+                        "Region: {}",
+                        entry.region.map_or_else(|| "Multi-region".to_string(), |region| region.to_string())
+                    ));
+                    use_db_header = ui.button("Use database header").clicked();
+                }
+                None => {
+                    ui.label("Not in the database");
+                }
+            }
+        }
+        if use_db_header {
+            self.apply_rom_db_header();
+        }
+
+        ui.separator();
+        ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+        let vector = |addr: u16| (self.cpu.bus.peek(addr + 1) as u16) << 8 | self.cpu.bus.peek(addr) as u16;
+        let reset = vector(0xFFFC);
+        ui.label(format!("NMI:     ${:04X}", vector(0xFFFA)));
+        ui.label(format!("RESET:   ${:04X}", reset));
+        ui.label(format!("IRQ/BRK: ${:04X}", vector(0xFFFE)));
+
+        ui.separator();
+        ui.label("At RESET:");
+        let banks = self.cpu.bus.mapper.borrow().debug_banks();
+        let mut addr = reset;
+        for _ in 0..8 {
+            let (instruction, length) = self.cpu.disassemble(addr);
+            let offset = banks.prg_offset(addr).map_or_else(|| "      ".to_string(), |offset| format!("{:06X}", offset));
+            ui.label(format!("{:04X}  {}  {}", addr, offset, instruction.trim_end()));
+            addr = addr.wrapping_add(length);
+        }
+    }
+
+    fn mapper_inspector(&mut self, ui: &mut egui::Ui) {
+        let banks = self.cpu.bus.mapper.borrow().debug_banks();
+        ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+        ui.label(format!("Mapper {}", self.cpu.bus.cartridge.mapper));
+
+        ui.separator();
+        for (window, bank) in banks.cpu_windows.iter().enumerate() {
+            ui.label(format!("CPU ${:04X}  {}", 0x8000 + window * 0x2000, bank));
+        }
+        ui.separator();
+        for (window, bank) in banks.ppu_windows.iter().enumerate() {
+            ui.label(format!("PPU ${:04X}  {}", window * 0x0400, bank));
+        }
+
+        if !banks.registers.is_empty() {
+            ui.separator();
+            egui::Grid::new("mapper-registers").show(ui, |ui| {
+                for (name, value) in &banks.registers {
+                    ui.label(*name);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+        }
     }
 
     fn chr_rom_inspector(&mut self, ui: &mut egui::Ui) {
-        if self.chr_rom_texture.is_none() || self.chr_rom_dirty {
-            let width = 256;
-            let height = 240;
-            let mut renderer = renderer::PPURenderer::new_custom_size(width, height);
-            let palette_colors = [
-                self.palette_rgb(0),
-                self.palette_rgb(1),
-                self.palette_rgb(2),
-                self.palette_rgb(3),
-            ];
-
-            let mut tile_y = 0;
-            let mut tile_x = 0;
-
-            for tile_n in 0..255 {
-                if tile_n != 0 && tile_n % 20 == 0 {
-                    tile_y += 10;
-                    tile_x = 0;
+        let previous_palette = self.pattern_table_palette;
+        egui::ComboBox::from_label("Palette")
+            .selected_text(Self::pattern_table_palette_name(self.pattern_table_palette))
+            .show_ui(ui, |ui| {
+                for palette in 0..8 {
+                    let name = Self::pattern_table_palette_name(palette);
+                    ui.selectable_value(&mut self.pattern_table_palette, palette, name);
                 }
-                // load tiles into texture
-                let tile = &self.cpu.bus.ppu.chr_rom[tile_n * 16..=tile_n * 16 + 15];
+            });
+        if self.pattern_table_palette != previous_palette {
+            self.chr_rom_dirty = true;
+        }
 
-                for tile_index_y in 0..=7 {
-                    let mut upper = tile[tile_index_y];
-                    let mut lower = tile[tile_index_y + 8];
+        if self.chr_rom_dirty || self.pattern_table_textures.iter().any(|t| t.is_none()) {
+            for table in 0..2 {
+                let pixels = self.cpu.bus.ppu.render_pattern_table(table, self.pattern_table_palette);
+                let image = egui::ColorImage::from_rgb([128, 128], &pixels);
+                if let Some(texture) = &mut self.pattern_table_textures[table] {
+                    texture.set(image, Default::default());
+                } else {
+                    self.pattern_table_textures[table] = Some(ui.ctx().load_texture(
+                        format!("pattern-table-{}", table),
+                        image,
+                        Default::default(),
+                    ));
+                }
+            }
 
-                    for tile_index_x in (0..=7).rev() {
-                        let color = (1 & upper) << 1 | (1 & lower);
-                        upper >>= 1;
-                        lower >>= 1;
-                        let rgb = palette_colors[color as usize];
+            self.chr_rom_dirty = false;
+        }
 
-                        renderer.set_pixel(tile_x + tile_index_x, tile_y + tile_index_y, rgb);
-                    }
-                }
+        let size = (ui.available_width() / 2.0 - ui.spacing().item_spacing.x)
+            .min(ui.available_height())
+            .max(0.0);
+        ui.horizontal(|ui| {
+            for texture in self.pattern_table_textures.iter().flatten() {
+                ui.image(texture, [size, size]);
+            }
+        });
+    }
 
-                tile_x += 10;
+    fn pattern_table_palette_name(palette: u8) -> String {
+        if palette < 4 {
+            format!("Background {}", palette)
+        } else {
+            format!("Sprite {}", palette - 4)
+        }
+    }
+
+    fn oam_viewer(&mut self, ui: &mut egui::Ui) {
+        let sprite_height = self.cpu.bus.ppu.sprite_height() as usize;
+        let image = egui::ColorImage::from_rgb(
+            [8, sprite_height],
+            &self.cpu.bus.ppu.render_sprite(self.selected_sprite),
+        );
+        if let Some(texture) = &mut self.sprite_texture {
+            texture.set(image, egui::TextureOptions::NEAREST);
+        } else {
+            self.sprite_texture = Some(ui.ctx().load_texture(
+                "sprite-texture",
+                image,
+                egui::TextureOptions::NEAREST,
+            ));
+        }
+
+        if let Some(texture) = &self.sprite_texture {
+            ui.image(texture, [64.0, 8.0 * sprite_height as f32]);
+        }
+
+        ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+        ui.label(" #   X   Y  Tile Pal Pri Flip Addr");
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (index, sprite) in self.cpu.bus.ppu.sprite_list().iter().enumerate() {
+                let flip = match (sprite.flip_h, sprite.flip_v) {
+                    (false, false) => "--",
+                    (true, false) => "H-",
+                    (false, true) => "-V",
+                    (true, true) => "HV",
+                };
+                let text = format!(
+                    "{:02} {:3} {:3}  {:02X}   {}  {}   {}  {:04X}{}",
+                    index,
+                    sprite.x,
+                    sprite.y,
+                    sprite.tile_index,
+                    sprite.palette,
+                    if sprite.behind_background { "B" } else { "F" },
+                    flip,
+                    sprite.pattern_addr,
+                    if sprite.on_screen { "" } else { " off" },
+                );
+                ui.selectable_value(&mut self.selected_sprite, index, text);
             }
+        });
+    }
 
-            let image = renderer.get_color_image();
-            if let Some(texture) = &mut self.chr_rom_texture {
+    fn nametable_viewer(&mut self, ui: &mut egui::Ui) {
+        if self.nametable_texture.is_none() || self.nametable_dirty {
+            let image = egui::ColorImage::from_rgb([512, 480], &self.cpu.bus.ppu.render_nametables());
+            if let Some(texture) = &mut self.nametable_texture {
                 texture.set(image, Default::default());
             } else {
-                self.chr_rom_texture = Some(ui.ctx().load_texture(
-                    "chr-rom-texture",
+                self.nametable_texture = Some(ui.ctx().load_texture(
+                    "nametable-texture",
                     image,
                     Default::default(),
                 ));
             }
 
-            self.chr_rom_dirty = false;
+            self.nametable_dirty = false;
         }
 
-        if let Some(texture) = &self.chr_rom_texture {
-            let size = ui.available_size().min_elem();
-            ui.image(texture, [size, size]);
+        let (scroll_x, scroll_y) = self.cpu.bus.ppu.scroll_position();
+        ui.label(format!("Scroll: {}, {}", scroll_x, scroll_y));
+
+        if let Some(texture) = &self.nametable_texture {
+            let available = ui.available_size();
+            let scale = (available.x / 512.0).min(available.y / 480.0);
+            let response = ui.image(texture, egui::Vec2::new(512.0 * scale, 480.0 * scale));
+
+            // Outline the visible area, drawing the wrapped copies as well
+            let painter = ui.painter_at(response.rect);
+            let stroke = egui::Stroke::new(1.0, egui::Color32::RED);
+            for (offset_x, offset_y) in [(0.0, 0.0), (-512.0, 0.0), (0.0, -480.0), (-512.0, -480.0)] {
+                let min = response.rect.min
+                    + egui::Vec2::new(scroll_x as f32 + offset_x, scroll_y as f32 + offset_y) * scale;
+                let viewport = egui::Rect::from_min_size(min, egui::Vec2::new(256.0, 240.0) * scale);
+                painter.rect_stroke(viewport, 0.0, stroke);
+            }
         }
     }
 
@@ -419,7 +1493,7 @@ impl RunesContext {
                 for color_index in 0..4 {
                     let palette_index = (base + color_index) as u8;
                     let mut palette_value = self.palette_value(palette_index);
-                    let rgb = SYSTEM_PALLETE[palette_value as usize];
+                    let rgb = self.cpu.bus.ppu.system_palette[palette_value as usize];
                     let mut srgb = [rgb.0, rgb.1, rgb.2];
 
                     ui.push_id(palette_index, |ui| {
@@ -439,6 +1513,36 @@ impl RunesContext {
             });
         }
 
+        let mut selected_preset = None;
+        egui::ComboBox::from_label("Preset")
+            .selected_text(self.palette_preset.map_or_else(|| "Custom".to_string(), |preset| preset.to_string()))
+            .show_ui(ui, |ui| {
+                for preset in PalettePreset::ALL {
+                    if ui.selectable_label(self.palette_preset == Some(preset), preset.to_string()).clicked() {
+                        selected_preset = Some(preset);
+                    }
+                }
+            });
+
+        let mut imported = false;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.palette_path);
+            if ui.button("Import").clicked() {
+                self.import_palette();
+                imported = true;
+            }
+            if ui.button("Export palette").clicked() {
+                self.export_palette();
+            }
+        });
+
+        if let Some(preset) = selected_preset {
+            self.select_palette_preset(preset);
+        }
+        if selected_preset.is_some() || imported {
+            self.update_frame_texture(ui.ctx(), false);
+        }
+
         if palette_changed {
             self.chr_rom_dirty = true;
             self.palette_snapshot = self.cpu.bus.ppu.palette;
@@ -448,13 +1552,77 @@ impl RunesContext {
 
     fn game(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
-            ui.label(if self.running { "Running" } else { "Paused" });
+            ui.label(match (self.running, self.focus_paused) {
+                (true, true) => "Paused (unfocused)",
+                (true, false) => "Running",
+                (false, _) => "Paused",
+            });
             ui.separator();
             ui.label("Space: Run/Pause");
             ui.label("N: Step");
             ui.label("F: Frame");
+            ui.label("Shift+F: Scanline");
+            ui.label("V: VBlank");
+            ui.label("O: Step out");
             ui.label("R: Reset");
+            ui.label("Ctrl+R: Reload ROM");
+            ui.label("F5: Save");
+            ui.label("F9: Load");
+            ui.label("F8: Record");
+            ui.label("F12: Copy frame");
+            ui.label("F10: On top");
+            ui.label("F11: Fullscreen");
         });
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Frame skip:");
+            ui.add(egui::DragValue::new(&mut self.frame_skip).speed(1.0).clamp_range(0..=MAX_FRAME_SKIP));
+            ui.checkbox(&mut self.auto_frame_skip, "Auto");
+            ui.separator();
+
+            let mut region = self.cpu.bus.region;
+            egui::ComboBox::from_label("Region")
+                .selected_text(region.to_string())
+                .show_ui(ui, |ui| {
+                    for option in [Region::Ntsc, Region::Pal, Region::Dendy] {
+                        ui.selectable_value(&mut region, option, option.to_string());
+                    }
+                });
+            if region != self.cpu.bus.region {
+                self.cpu.bus.set_region(region);
+            }
+            ui.separator();
+            ui.checkbox(&mut self.cpu.bus.ppu.sprite_overflow_bug, "Sprite overflow bug")
+                .on_hover_text("Off uses a plain 9th sprite check instead of the hardware's diagonal OAM scan");
+            ui.checkbox(&mut self.cpu.bus.ppu.accurate_sprite_zero_hit, "Accurate sprite 0 hit")
+                .on_hover_text("Off checks each line once it's drawn, the flag is raised at the end of the line");
+            ui.checkbox(&mut self.cpu.bus.ppu.warm_up_enabled, "PPU warm-up")
+                .on_hover_text("Ignore $2000/$2001/$2005/$2006 writes for the first ~29658 CPU cycles after reset");
+            ui.checkbox(&mut self.cpu.bus.ppu.sprite_limit_enabled, "8 sprites per line")
+                .on_hover_text("Off draws every sprite on a line to remove flicker");
+            ui.separator();
+            ui.checkbox(&mut self.raster_overlay, "Raster overlay");
+            ui.checkbox(&mut self.split_overlay, "Split lines");
+            ui.checkbox(&mut self.pause_on_focus_loss, "Pause in background");
+            ui.checkbox(&mut self.cpu.ignore_state_rom_mismatch, "States from other ROMs")
+                .on_hover_text("Load save states even if they were made with a different ROM");
+            ui.checkbox(&mut self.highlight_transparent_background, "Transparent BG");
+            ui.checkbox(&mut self.highlight_sprite_zero, "Sprite 0 overlap");
+            ui.separator();
+            ui.checkbox(&mut self.window.always_on_top, "Always on top");
+            ui.checkbox(&mut self.window.borderless, "Borderless");
+            ui.checkbox(&mut self.window.fullscreen, "Fullscreen");
+            ui.separator();
+            ui.add_enabled(
+                self.recorder.is_none(),
+                egui::TextEdit::singleline(&mut self.recording_path).desired_width(120.0),
+            )
+            .on_hover_text("Video extensions (.mp4, .mkv, .webm, .avi) go through ffmpeg, anything else is a folder of PPM frames");
+            let label = if self.recorder.is_some() { "Stop recording" } else { "Record" };
+            if ui.button(label).clicked() {
+                self.toggle_recording();
+            }
+        });
+        self.cpu.bus.ppu.debug_provenance = self.highlight_transparent_background || self.highlight_sprite_zero;
         ui.horizontal(|ui| {
             ui.label("Pad:");
             ui.label("Z=A");
@@ -462,17 +1630,157 @@ impl RunesContext {
             ui.label("Tab=Select");
             ui.label("Enter=Start");
             ui.label("Arrows/WASD=D-pad");
+            if self.cpu.bus.famicom_mode {
+                ui.label("M=Mic");
+            }
         });
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Turbo A")
+                .selected_text(format!("{:?}", self.turbo_a_key))
+                .show_ui(ui, |ui| {
+                    for key in TURBO_KEY_CHOICES {
+                        ui.selectable_value(&mut self.turbo_a_key, key, format!("{:?}", key));
+                    }
+                });
+            egui::ComboBox::from_label("Turbo B")
+                .selected_text(format!("{:?}", self.turbo_b_key))
+                .show_ui(ui, |ui| {
+                    for key in TURBO_KEY_CHOICES {
+                        ui.selectable_value(&mut self.turbo_b_key, key, format!("{:?}", key));
+                    }
+                });
+            ui.label("Rate (Hz):");
+            ui.add(egui::DragValue::new(&mut self.cpu.bus.turbo_rate).speed(0.5).clamp_range(1.0..=30.0));
+        });
+
+        if let Some(message) = &self.status_message {
+            ui.label(message);
+        }
+
+        if let Some(error) = &self.load_error {
+            let text = format!("{}\n\nDrop a .nes file here to load another ROM", error);
+            ui.centered_and_justified(|ui| {
+                ui.label(egui::RichText::new(text).heading().color(egui::Color32::RED));
+            });
+            return;
+        }
 
         if let Some(texture) = &self.frame_texture {
             let available = ui.available_size();
             let scale = (available.x / 256.0).min(available.y / 240.0);
             let size = egui::Vec2::new(256.0 * scale, 240.0 * scale);
-            ui.image(texture, size);
+            let response = ui.image(texture, size);
+            if self.cpu.bus.ppu.debug_provenance {
+                self.draw_provenance_overlay(ui, response.rect);
+            }
+            if self.raster_overlay {
+                self.draw_raster_overlay(ui, response.rect, scale);
+            }
+            if self.split_overlay {
+                self.draw_split_overlay(ui, response.rect, scale);
+            }
         } else {
             ui.label("Framebuffer not ready yet.");
         }
     }
+
+    // Tints transparent background pixels magenta and sprite 0 pixels over opaque background
+    // green (yellow where a hit was registered)
+    fn draw_provenance_overlay(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
+        let Some(provenance) = self.cpu.bus.ppu.pixel_provenance() else {
+            return;
+        };
+
+        let mut pixels = vec![0u8; 256 * 240 * 4];
+        for (pixel, info) in pixels.chunks_exact_mut(4).zip(provenance) {
+            let color = if self.highlight_sprite_zero && info.sprite_zero_hit {
+                [255, 255, 0, 160]
+            } else if self.highlight_sprite_zero && info.sprite == Some(0) && info.background_color != 0 {
+                [0, 255, 0, 120]
+            } else if self.highlight_transparent_background && info.background_color == 0 {
+                [255, 0, 255, 90]
+            } else {
+                continue;
+            };
+            pixel.copy_from_slice(&color);
+        }
+
+        let image = egui::ColorImage::from_rgba_unmultiplied([256, 240], &pixels);
+        let texture = match &mut self.provenance_texture {
+            Some(texture) => {
+                texture.set(image, egui::TextureOptions::NEAREST);
+                texture
+            }
+            None => self.provenance_texture.insert(ui.ctx().load_texture(
+                "provenance-overlay",
+                image,
+                egui::TextureOptions::NEAREST,
+            )),
+        };
+
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+        ui.painter().image(texture.id(), rect, uv, egui::Color32::WHITE);
+    }
+
+    // Draws a line at the current scanline and a marker at the current dot over the game image
+    fn draw_raster_overlay(&self, ui: &mut egui::Ui, rect: egui::Rect, scale: f32) {
+        let scanline = self.cpu.bus.ppu.scanline;
+        let cycle = self.cpu.bus.ppu.cycle;
+        if scanline >= 240 {
+            ui.label(format!("Raster: scanline {} (vblank), dot {}", scanline, cycle));
+            return;
+        }
+
+        let color = egui::Color32::RED;
+        let y = rect.top() + (scanline as f32 + 0.5) * scale;
+        let painter = ui.painter();
+        painter.line_segment(
+            [egui::pos2(rect.left(), y), egui::pos2(rect.right(), y)],
+            egui::Stroke::new(1.0, color),
+        );
+
+        // Dots outside the visible 1..=256 range sit in hblank, clamp them to the edges
+        let x = cycle.clamp(1, 256) - 1;
+        let center = egui::pos2(rect.left() + (x as f32 + 0.5) * scale, y);
+        painter.circle_filled(center, 3.0_f32.max(scale), color);
+    }
+
+    // Marks lines of the last frame where scroll, mask or control differ from the line above
+    fn draw_split_overlay(&self, ui: &mut egui::Ui, rect: egui::Rect, scale: f32) {
+        let color = egui::Color32::YELLOW;
+        let painter = ui.painter();
+        let registers = self.cpu.bus.ppu.scanline_registers();
+
+        for (line, pair) in registers.windows(2).enumerate() {
+            let (above, current) = (pair[0], pair[1]);
+            let mut changes = Vec::new();
+            if (above.scroll_x, above.scroll_y, above.nametable) != (current.scroll_x, current.scroll_y, current.nametable) {
+                changes.push("scroll");
+            }
+            if above.mask != current.mask {
+                changes.push("mask");
+            }
+            if above.control != current.control {
+                changes.push("ctrl");
+            }
+            if changes.is_empty() {
+                continue;
+            }
+
+            let y = rect.top() + (line + 1) as f32 * scale;
+            painter.line_segment(
+                [egui::pos2(rect.left(), y), egui::pos2(rect.right(), y)],
+                egui::Stroke::new(1.0, color),
+            );
+            painter.text(
+                egui::pos2(rect.left() + 2.0, y),
+                egui::Align2::LEFT_BOTTOM,
+                format!("{} {}", line + 1, changes.join("+")),
+                egui::FontId::monospace(10.0),
+                color,
+            );
+        }
+    }
 }
 
 struct RunesApp {
@@ -482,10 +1790,11 @@ struct RunesApp {
 
 
 impl RunesApp {
-    fn new(mut cpu: CPU) -> Self {
+    fn new(mut cpu: CPU, rom_name: String, load_error: Option<String>, window: WindowSettings) -> Self {
         cpu.reset();
         cpu.bus.ppu.reset();
         let palette_snapshot = cpu.bus.ppu.palette;
+        let ram_search = RamSearch::new(&cpu.bus.cpu_vram);
         let mut tree = Tree::new(vec!["Game".to_owned()]);
         let left_column_fraction = 0.17;
         let game_column_fraction = 0.82;
@@ -493,7 +1802,11 @@ impl RunesApp {
         let [game_node_index, chr_rom_node_index] = tree.split_left(
             NodeIndex::root(),
             left_column_fraction,
-            vec!["CHR ROM Inspector".to_owned()],
+            vec![
+                "CHR ROM Inspector".to_owned(),
+                "Nametable Viewer".to_owned(),
+                "OAM Viewer".to_owned(),
+            ],
         );
         tree.split_below(chr_rom_node_index, 0.6, vec!["Color Palette".to_owned()]);
 
@@ -526,63 +1839,203 @@ impl RunesApp {
         tree.split_below(
             cpu_debug_inspector_node_index,
             0.5,
-            vec!["Controller Inspector".to_owned()],
+            vec![
+                "Controller Inspector".to_owned(),
+                "Cheats".to_owned(),
+                "Unmapped Accesses".to_owned(),
+                "Mapper Inspector".to_owned(),
+            ],
         );
 
-        Self {
+        let mut app = Self {
             context: RunesContext {
                 cpu,
                 page_cpu: 0,
                 page_rom: 0x80,
-                chr_rom_texture: None,
+                rom_linear: false,
+                rom_bank: 0,
+                rom_bank_page: 0,
+                follow_pc: false,
+                jump_address_input: String::new(),
+                pattern_table_textures: [None, None],
+                pattern_table_palette: 0,
+                nametable_texture: None,
+                sprite_texture: None,
+                selected_sprite: 0,
                 frame_texture: None,
                 running: false,
                 chr_rom_dirty: true,
+                nametable_dirty: true,
                 palette_snapshot,
+                palette_path: "palette.pal".to_string(),
+                palette_preset: Some(PalettePreset::Default),
                 last_tick: Instant::now(),
                 ppu_cycle_accumulator: 0.0,
+                frame_skip: 0,
+                auto_frame_skip: false,
+                frame_over_budget: false,
+                skipped_frames: 0,
+                opcode_breakpoint_input: String::new(),
+                freeze_address_input: String::new(),
+                trace_path: "trace.log".to_string(),
+                recording_path: "recording.mp4".to_string(),
+                recorder: None,
+                clipboard: None,
+                trace_line_cap: 1_000_000,
+                freeze_value_input: String::new(),
+                ram_search,
+                ram_search_filter: SearchFilter::Equal,
+                ram_search_value_input: String::new(),
+                run_to_scanline: 0,
+                run_to_dot: 0,
+                break_scanline: 0,
+                nmi_scanline: 0,
+                step_count: 1000,
+                step_frame_count: 10,
+                quick_save: None,
+                battery_save: None,
+                battery_flush_interval: DEFAULT_FLUSH_INTERVAL_SECONDS,
+                status_message: None,
+                load_error,
+                raster_overlay: false,
+                split_overlay: false,
+                highlight_transparent_background: false,
+                highlight_sprite_zero: false,
+                provenance_texture: None,
+
+                turbo_a_key: egui::Key::K,
+                turbo_b_key: egui::Key::J,
+                mic_button_held: false,
+                pad_buttons: ButtonLatch::default(),
+
+                pause_on_focus_loss: true,
+                focus_paused: false,
+
+                rom_name,
+                window_title: String::new(),
+                fps: 0.0,
+                fps_window_start: Instant::now(),
+                fps_window_frame: 0,
+
+                window,
+                applied_window: window,
+
+                rom_db: None,
+                rom_db_path: "nes20db.xml".to_string(),
+                fix_headers_on_load: false,
+
+                watch_rom: false,
+                rom_modified: None,
+                rom_checked: Instant::now(),
             },
             tree
-        }
+        };
+        app.context.open_battery_save();
+        app
     }
 }
 
 impl eframe::App for RunesApp { 
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         let frame_start = Instant::now();
         self.context.update_controller_state(ctx);
 
-        if ctx.input(|i| i.key_pressed(egui::Key::Space)) {
+        // Hotkeys are ignored while a text field (e.g. a memory address or search value) has focus
+        let typing = ctx.wants_keyboard_input();
+        let hotkey = |key: egui::Key| !typing && ctx.input(|i| i.key_pressed(key));
+
+        if hotkey(egui::Key::Space) {
             self.context.running = !self.context.running;
             if self.context.running {
+                self.context.cpu.breakpoint_hit = None;
                 self.context.reset_timing();
             }
         }
 
+        let focused = ctx.input(|i| i.focused);
+        self.context.focus_paused = self.context.pause_on_focus_loss && !focused;
+
         let mut frame_dirty = false;
         let mut frame_complete = false;
 
-        if ctx.input(|i| i.key_pressed(egui::Key::N)) {
+        let dropped_file = ctx.input(|i| i.raw.dropped_files.iter().find_map(|file| file.path.clone()));
+        if let Some(path) = dropped_file {
+            self.context.load_dropped_file(&path);
+            frame_dirty = true;
+        }
+
+        if hotkey(egui::Key::N) {
             self.context.step_instruction();
             frame_dirty = true;
         }
 
-        if ctx.input(|i| i.key_pressed(egui::Key::F)) {
-            frame_complete = self.context.run_frame();
+        if hotkey(egui::Key::F) {
+            if ctx.input(|i| i.modifiers.shift) {
+                self.context.cpu.run_to_next_scanline();
+            } else {
+                frame_complete = self.context.run_frame();
+            }
+            frame_dirty = true;
+        }
+
+        if hotkey(egui::Key::V) {
+            self.context.cpu.run_to_vblank();
+            frame_dirty = true;
+        }
+
+        if hotkey(egui::Key::O) {
+            self.context.step_out();
             frame_dirty = true;
         }
 
-        if ctx.input(|i| i.key_pressed(egui::Key::R)) {
-            self.context.reset();
+        if hotkey(egui::Key::F5) {
+            self.context.quick_save();
+        }
+
+        if hotkey(egui::Key::F9) {
+            self.context.quick_load();
             frame_dirty = true;
         }
 
-        if self.context.running {
+        if hotkey(egui::Key::F8) {
+            self.context.toggle_recording();
+        }
+
+        if hotkey(egui::Key::F12) {
+            self.context.copy_frame_to_clipboard();
+        }
+
+        if hotkey(egui::Key::F10) {
+            self.context.window.always_on_top = !self.context.window.always_on_top;
+        }
+
+        if hotkey(egui::Key::F11) {
+            self.context.window.fullscreen = !self.context.window.fullscreen;
+        }
+
+        if hotkey(egui::Key::R) {
+            if ctx.input(|i| i.modifiers.command) {
+                self.context.reload_rom();
+            } else {
+                self.context.reset();
+            }
+            frame_dirty = true;
+        }
+
+        self.context.poll_rom_file();
+
+        // Timing is reset every update while not running, so refocusing doesn't produce a catch-up burst
+        if self.context.running && !self.context.focus_paused {
             let delta = self.context.tick();
             frame_complete |= self.context.run_for_budget(delta);
+            if self.context.cpu.breakpoint_hit.is_some() {
+                self.context.running = false;
+                frame_dirty = true;
+            }
 
-            let target_frame_time = Duration::from_secs_f64(1.0 / TARGET_FPS);
+            let target_frame_time = Duration::from_secs_f64(1.0 / self.context.cpu.bus.region.frame_rate());
             let frame_time = frame_start.elapsed();
+            self.context.frame_over_budget = frame_time > target_frame_time;
             if frame_time < target_frame_time {
                 ctx.request_repaint_after(target_frame_time - frame_time);
             } else {
@@ -590,10 +2043,13 @@ impl eframe::App for RunesApp {
             }
         } else {
             self.context.reset_timing();
+            // Stepping shows every frame, partial ones included
+            self.context.cpu.bus.ppu.render_enabled = true;
         }
 
-        if frame_dirty || frame_complete {
-            self.context.update_frame_texture(ctx);
+        if frame_dirty || (frame_complete && self.context.should_present_frame()) {
+            self.context.update_frame_texture(ctx, frame_dirty && !frame_complete);
+            self.context.nametable_dirty = true;
             if self.context.cpu.bus.cartridge.chr_is_ram {
                 self.context.chr_rom_dirty = true;
             }
@@ -607,5 +2063,23 @@ impl eframe::App for RunesApp {
         DockArea::new(&mut self.tree)
             .style(Style::from_egui(ctx.style().as_ref()))
             .show(ctx, &mut self.context);
+
+        self.context.update_battery_save();
+
+        if let Some(title) = self.context.update_window_title() {
+            frame.set_window_title(title);
+        }
+
+        if self.context.window != self.context.applied_window {
+            self.context.window.apply(frame);
+            self.context.applied_window = self.context.window;
+            if let Err(error) = self.context.window.save() {
+                self.context.status_message = Some(format!("Couldn't save window settings: {}", error));
+            }
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.context.flush_battery_save();
     }
 }